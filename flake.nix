@@ -20,9 +20,12 @@
         pydantic
         python-multipart
         pyyaml
+        markdown
         sounddevice
         numpy
         websockets
+        httpx
+        pytest
       ]);
 
       # 2. Frontend and Electron Dependencies
@@ -1,22 +1,33 @@
 #!/usr/bin/env python3
 """
-Geolocation functionality using IP-based lookup.
-Provides device location data for capture metadata.
+Geolocation functionality for capture metadata.
+The provider is picked by the `location` config section: an IP-based lookup,
+GeoClue, a shell command or a fixed position.
 """
 
+import re
 import subprocess
 import json
 from typing import Optional, Dict, Any
 
+from kms_errors import ValidationError
 
-def get_device_location() -> Optional[Dict[str, Any]]:
+
+LOCATION_MODES = ("off", "ip", "geoclue", "command", "static")
+DEFAULT_LOCATION_TIMEOUT = 2.0
+# GeoClue's demo client; it prints each fix and exits after -t seconds
+DEFAULT_GEOCLUE_COMMAND = "/usr/libexec/geoclue-2.0/demos/where-am-i"
+GEOCLUE_FIELD_RE = re.compile(r"^\s*(Latitude|Longitude|Accuracy):\s*(-?[\d.]+)", re.M)
+
+
+def get_device_location(timeout: float = 5) -> Optional[Dict[str, Any]]:
     """Get device location using IP-based geolocation."""
     try:
         result = subprocess.run(
             ["curl", "-s", "http://ip-api.com/json/"],
             capture_output=True,
             text=True,
-            timeout=5,
+            timeout=timeout,
         )
         if result.returncode == 0:
             data = json.loads(result.stdout)
@@ -24,6 +35,8 @@ def get_device_location() -> Optional[Dict[str, Any]]:
                 return {
                     "latitude": data.get("lat"),
                     "longitude": data.get("lon"),
+                    "accuracy": None,
+                    "source": "ip",
                     "city": data.get("city"),
                     "country": data.get("country"),
                     "timezone": data.get("timezone"),
@@ -33,6 +46,111 @@ def get_device_location() -> Optional[Dict[str, Any]]:
     return None
 
 
+def _position(
+    latitude: Any, longitude: Any, accuracy: Any, source: str
+) -> Optional[Dict[str, Any]]:
+    """A location dict, or None unless latitude and longitude are numbers."""
+    try:
+        position = {"latitude": float(latitude), "longitude": float(longitude)}
+    except (TypeError, ValueError):
+        return None
+    try:
+        position["accuracy"] = float(accuracy) if accuracy is not None else None
+    except (TypeError, ValueError):
+        position["accuracy"] = None
+    position["source"] = source
+    return position
+
+
+def geoclue_location(command: str, timeout: float) -> Optional[Dict[str, Any]]:
+    """First fix printed by GeoClue's where-am-i (or a compatible command)."""
+    try:
+        result = subprocess.run(
+            [command, "-t", str(max(1, int(timeout)))],
+            capture_output=True,
+            text=True,
+            timeout=timeout,
+        )
+        output = result.stdout
+    except subprocess.TimeoutExpired as e:
+        # A fix printed before the timeout is still usable
+        output = e.stdout or ""
+        if isinstance(output, bytes):
+            output = output.decode("utf-8", errors="replace")
+    except OSError as e:
+        print(f"Geolocation failed: {e}")
+        return None
+    fields: Dict[str, str] = {}
+    for name, value in GEOCLUE_FIELD_RE.findall(output):
+        fields.setdefault(name.lower(), value)
+    return _position(
+        fields.get("latitude"),
+        fields.get("longitude"),
+        fields.get("accuracy"),
+        "geoclue",
+    )
+
+
+def command_location(command: str, timeout: float) -> Optional[Dict[str, Any]]:
+    """Location printed by a shell command, as JSON with latitude/longitude (or
+    lat/lon) and an optional accuracy, or as "lat,lon[,accuracy]"."""
+    try:
+        result = subprocess.run(
+            command, shell=True, capture_output=True, text=True, timeout=timeout
+        )
+    except (OSError, subprocess.TimeoutExpired) as e:
+        print(f"Geolocation failed: {e}")
+        return None
+    output = result.stdout.strip()
+    if result.returncode != 0 or not output:
+        return None
+    try:
+        data = json.loads(output)
+    except json.JSONDecodeError:
+        data = dict(zip(("latitude", "longitude", "accuracy"), output.split(",")))
+    if not isinstance(data, dict):
+        return None
+    return _position(
+        data.get("latitude", data.get("lat")),
+        data.get("longitude", data.get("lon")),
+        data.get("accuracy"),
+        "command",
+    )
+
+
+def get_location(config: Optional[Dict[str, Any]]) -> Optional[Dict[str, Any]]:
+    """Location for a new capture from the `location` config section, or None
+    when it is off or the provider fails or takes longer than timeout_seconds."""
+    config = config or {}
+    mode = config.get("mode")
+    # YAML reads a bare `off` as false
+    mode = "off" if mode is False else mode or "ip"
+    if mode not in LOCATION_MODES:
+        raise ValidationError(
+            f"Unknown location.mode {mode!r}; "
+            f"expected one of {', '.join(LOCATION_MODES)}"
+        )
+    timeout = float(config.get("timeout_seconds") or DEFAULT_LOCATION_TIMEOUT)
+    if mode == "ip":
+        return get_device_location(timeout)
+    if mode == "geoclue":
+        return geoclue_location(
+            config.get("geoclue_command") or DEFAULT_GEOCLUE_COMMAND, timeout
+        )
+    if mode == "command":
+        if not config.get("command"):
+            raise ValidationError("location.mode is command but no location.command")
+        return command_location(config["command"], timeout)
+    if mode == "static":
+        return _position(
+            config.get("latitude"),
+            config.get("longitude"),
+            config.get("accuracy"),
+            "static",
+        )
+    return None
+
+
 if __name__ == "__main__":
     location = get_device_location()
     if location:
@@ -0,0 +1,46 @@
+"""
+Error types shared by the database, markdown writer and API handlers.
+Each carries the HTTP status the server responds with when it escapes a handler.
+"""
+
+
+class KmsError(Exception):
+    """Base class for expected failures that map to a JSON error response."""
+
+    status_code = 500
+
+
+class ValidationError(KmsError):
+    status_code = 400
+
+
+class ForbiddenError(KmsError):
+    status_code = 403
+
+
+class NotFoundError(KmsError):
+    status_code = 404
+
+
+class ConflictError(KmsError):
+    status_code = 409
+
+
+class UnsupportedError(KmsError):
+    """A requested format or external tool is not available."""
+
+    status_code = 501
+
+
+class StorageError(KmsError):
+    """Reading or writing vault files failed."""
+
+
+class DatabaseError(KmsError):
+    """A database operation failed and was rolled back."""
+
+
+class CommandTimeoutError(KmsError):
+    """An external command (grim, wl-paste, ...) did not finish in time."""
+
+    status_code = 504
@@ -4,44 +4,375 @@ Safe markdown file writer with backup and conflict resolution.
 Handles writing captures to daily markdown files in the vault.
 """
 
+import hashlib
 import os
+import re
 import shutil
-from datetime import datetime, timezone
+from datetime import date, datetime, timezone
 from pathlib import Path
-from typing import Dict, Any, Optional, List
+from typing import Dict, Any, Optional, List, Tuple
 import yaml
 
+from kms_errors import StorageError, ValidationError
+
+
+FRONTMATTER_RE = re.compile(r"\A---\n(.*?)\n---(?:\n|\Z)(.*)\Z", re.DOTALL)
+DEFAULT_DATE_FORMAT = "%Y-%m-%d"
+PROCESSING_STATUSES = ("raw", "processed", "archived")
+TEMPLATE_PLACEHOLDER_RE = re.compile(r"\{\{\s*(\w+)\s*\}\}")
+TEMPLATE_FIELDS = (
+    "sections",
+    "content",
+    "clipboard",
+    "capture_id",
+    "timestamp",
+    "created_date",
+    "tags",
+    "sources",
+    "context",
+)
+ALIAS_MODES = ("capture_id", "title", "none")
+# capture.clipboard_fence: "auto" fences one-liners and detected languages,
+# "code" only text that looks like code
+CLIPBOARD_FENCE_MODES = ("auto", "always", "code", "never")
+MAX_TITLE_ALIAS_LENGTH = 50
+# Custom capture fields are top-level frontmatter keys with this prefix, so they
+# can never collide with the built-in keys
+FIELD_PREFIX = "field_"
+FIELD_KEY_RE = re.compile(r"^[A-Za-z][A-Za-z0-9_]*$")
+# vault.filename_pattern placeholders; the default keeps files named by id
+DEFAULT_FILENAME_PATTERN = "{capture_id}"
+FILENAME_FIELDS = ("capture_id", "slug", "date", "time")
+
+# capture.template_file path -> (mtime_ns, text), reloaded when the file changes
+_template_cache: Dict[Path, Tuple[int, str]] = {}
+
+SHEBANG_LANGUAGES = {
+    "python": "python",
+    "bash": "bash",
+    "sh": "sh",
+    "zsh": "zsh",
+    "fish": "fish",
+    "node": "javascript",
+    "deno": "typescript",
+    "ruby": "ruby",
+    "perl": "perl",
+}
+EXTENSION_LANGUAGES = {
+    "py": "python",
+    "rs": "rust",
+    "js": "javascript",
+    "ts": "typescript",
+    "tsx": "tsx",
+    "go": "go",
+    "c": "c",
+    "h": "c",
+    "cpp": "cpp",
+    "java": "java",
+    "rb": "ruby",
+    "sh": "bash",
+    "nix": "nix",
+    "yaml": "yaml",
+    "yml": "yaml",
+    "json": "json",
+    "sql": "sql",
+}
+SHEBANG_RE = re.compile(r"\A#!\s*(?:\S*/)?(?:env\s+)?([A-Za-z]+)")
+FILENAME_HINT_RE = re.compile(r"\b[\w./-]+\.([A-Za-z]+)\b")
+CODE_LINE_RE = re.compile(
+    r"[;{}]\s*$|^\s*[})\]]|^(?:\t| {2,})\S"
+    r"|^\s*(?:def|class|fn|func|function|import|let|const|var|pub|#include|"
+    r"SELECT|INSERT|UPDATE)\b"
+    r"|=>|->|==|!=|\w\(.*\)\s*$"
+)
+
+
+def detect_code_language(text: str) -> Optional[str]:
+    """Guess a fence language from a shebang or a file name on the first line."""
+    first_line = text.lstrip().split("\n", 1)[0]
+    shebang = SHEBANG_RE.match(first_line)
+    if shebang:
+        interpreter = re.sub(r"[\d.]+$", "", shebang.group(1))
+        return SHEBANG_LANGUAGES.get(interpreter)
+    if first_line.lstrip().startswith(("//", "#", "--", "/*")):
+        filename = FILENAME_HINT_RE.search(first_line)
+        if filename:
+            return EXTENSION_LANGUAGES.get(filename.group(1).lower())
+    return None
+
+
+def looks_like_code(text: str) -> bool:
+    """Whether most non-blank lines look like code rather than prose."""
+    if detect_code_language(text):
+        return True
+    lines = [line for line in text.splitlines() if line.strip()]
+    if not lines:
+        return False
+    code_lines = sum(1 for line in lines if CODE_LINE_RE.search(line))
+    return code_lines * 2 >= len(lines)
+
+
+def title_alias(content: str) -> Optional[str]:
+    """First non-empty line of the content, without heading marks, as an alias."""
+    for line in content.splitlines():
+        title = line.strip().lstrip("#").strip()
+        if title:
+            if len(title) > MAX_TITLE_ALIAS_LENGTH:
+                title = title[:MAX_TITLE_ALIAS_LENGTH].rstrip()
+            return title
+    return None
+
+
+def validate_fields(fields: Any) -> Dict[str, Any]:
+    """Custom capture fields: a mapping of identifier-like keys to strings,
+    numbers or booleans. Keys mapped to None are dropped."""
+    if fields is None:
+        return {}
+    if not isinstance(fields, dict):
+        raise ValidationError("fields must be an object")
+    result = {}
+    for key, value in fields.items():
+        if not FIELD_KEY_RE.match(str(key)):
+            raise ValidationError(
+                f"Invalid field name {key!r}; use letters, digits and underscores"
+            )
+        if value is None:
+            continue
+        if not isinstance(value, (str, int, float, bool)):
+            raise ValidationError(f"Field {key!r} must be a string, number or boolean")
+        result[str(key)] = value
+    return result
+
+
+def unresolved_placeholders(template: str) -> List[str]:
+    """{{placeholder}} names in a template that render empty (unknown fields)."""
+    unknown = []
+    for name in TEMPLATE_PLACEHOLDER_RE.findall(template):
+        if name not in TEMPLATE_FIELDS and name not in unknown:
+            unknown.append(name)
+    return unknown
+
+
+def has_content_section(template: str) -> bool:
+    """Whether a template renders a "## Content" section, which edits rewrite."""
+    return bool(re.search(r"^## Content[ \t]*$", template, re.M)) or any(
+        name == "sections" for name in TEMPLATE_PLACEHOLDER_RE.findall(template)
+    )
+
+
+def title_slug(content: str) -> str:
+    """Lowercase, dash-separated form of the content's title for file names."""
+    title = title_alias(content) or ""
+    slug = re.sub(r"\W+", "-", title.lower()).strip("-_")
+    return slug or "untitled"
+
+
+def content_sha256(body: str) -> str:
+    """Hash of a capture body (everything after the frontmatter) as written."""
+    return hashlib.sha256(body.encode("utf-8")).hexdigest()
+
+
+def section_headings(body: str) -> List[str]:
+    """Titles of the "## " sections in a capture body, ignoring fenced code."""
+    headings = []
+    in_fence = False
+    for line in body.splitlines():
+        if line.startswith("```"):
+            in_fence = not in_fence
+        elif not in_fence and line.startswith("## "):
+            headings.append(line[3:].strip())
+    return headings
+
+
+def body_sections(body: str) -> List[Tuple[str, str]]:
+    """(heading, text) of each "## " section in a capture body, ignoring
+    headings inside fenced code. Text before the first heading is dropped."""
+    sections: List[Tuple[str, List[str]]] = []
+    in_fence = False
+    for line in body.splitlines():
+        if line.startswith("```"):
+            in_fence = not in_fence
+        if not in_fence and line.startswith("## "):
+            sections.append((line[3:].strip(), []))
+        elif sections:
+            sections[-1][1].append(line)
+    return [(heading, "\n".join(lines).strip("\n")) for heading, lines in sections]
+
+
+def _frontmatter_list(value: Any) -> List[Any]:
+    """A frontmatter list, also accepting a comma-separated string."""
+    if isinstance(value, str):
+        return [item.strip() for item in value.split(",") if item.strip()]
+    if isinstance(value, list):
+        return [item for item in value if item is not None]
+    return []
+
+
+MEDIA_SECTIONS = ("Screenshot", "Image", "Audio", "File")
+MEDIA_LINK_RE = re.compile(
+    r"!?\[\[([^\]|]+)(?:\|[^\]]*)?\]\]"  # ![[target]] or [[target|alias]]
+    r"|!?\[[^\]]*\]\(([^)]+)\)"  # ![label](target) or [label](target)
+)
+
+
+def media_links(body: str) -> List[Tuple[str, str]]:
+    """(target, style) of each link in a body's media sections, where style is
+    "markdown" or "wikilink"."""
+    links = []
+    section = None
+    for line in body.splitlines():
+        if line.startswith("## "):
+            section = line[3:].strip()
+            continue
+        if section not in MEDIA_SECTIONS:
+            continue
+        for wikilink, markdown in MEDIA_LINK_RE.findall(line):
+            if wikilink:
+                links.append((wikilink.strip(), "wikilink"))
+            else:
+                links.append((markdown, "markdown"))
+    return links
+
+
+def expiry_timestamp(value: Any) -> Optional[str]:
+    """An `expires_at` value as a UTC ISO timestamp, or None if unset/invalid.
+
+    Dates mean midnight UTC and naive times are taken as UTC.
+    """
+    if isinstance(value, str):
+        try:
+            value = datetime.fromisoformat(value.strip().replace("Z", "+00:00"))
+        except ValueError:
+            return None
+    if isinstance(value, date) and not isinstance(value, datetime):
+        value = datetime(value.year, value.month, value.day)
+    if not isinstance(value, datetime):
+        return None
+    if value.tzinfo is None:
+        value = value.replace(tzinfo=timezone.utc)
+    return value.astimezone(timezone.utc).isoformat()
+
+
+def format_frontmatter_date(value: datetime, date_format: Optional[str] = None) -> str:
+    """Render created_date/last_edited_date with the configured strftime format."""
+    return value.strftime(date_format or DEFAULT_DATE_FORMAT)
+
 
 class SafeMarkdownWriter:
     """Handles safe writing of capture data to markdown files."""
 
-    def __init__(self, vault_path: str):
+    def __init__(self, vault_path: str, config: Optional[Dict[str, Any]] = None):
         self.vault_path = Path(vault_path).expanduser()
-        self.capture_dir = self.vault_path / "capture" / "raw_capture"
-        self.media_dir = self.vault_path / "capture" / "raw_capture" / "media"
+        self.config = config or {}
+        self.vault_config = self.config.get("vault", {})
+        self.capture_dir = self.vault_path / (
+            self.vault_config.get("capture_dir") or "capture/raw_capture"
+        )
+        self.media_dir = self.vault_path / (
+            self.vault_config.get("media_dir") or "capture/raw_capture/media"
+        )
 
         self.capture_dir.mkdir(parents=True, exist_ok=True)
         self.media_dir.mkdir(parents=True, exist_ok=True)
 
-    def write_capture(self, capture_data: Dict[str, Any]) -> Path:
-        """Write capture data to individual idea markdown file safely."""
+    def write_capture(
+        self, capture_data: Dict[str, Any], target: Optional[Path] = None
+    ) -> Path:
+        """Write capture data to individual idea markdown file safely.
+
+        `target` overwrites that file instead of picking a new, unused name.
+        """
+        if target is not None:
+            return self.atomic_write(target, self.format_capture(capture_data))
+        content = capture_data.get("content") or ""
         idea_file = self.get_idea_file(
-            capture_data.get("timestamp"), capture_data.get("capture_id")
+            capture_data.get("timestamp"), capture_data.get("capture_id"), content
         )
 
         if idea_file.exists():
             idea_file = self.get_unique_idea_file(
-                capture_data.get("timestamp"), capture_data.get("capture_id")
+                capture_data.get("timestamp"), capture_data.get("capture_id"), content
             )
+            if self.filename_pattern == DEFAULT_FILENAME_PATTERN:
+                # Keep the id in sync with the suffixed file name so the database
+                # row for this capture does not replace the one it collided with.
+                capture_data["capture_id"] = idea_file.stem
 
         formatted_content = self.format_capture(capture_data)
 
         return self.atomic_write(idea_file, formatted_content)
 
+    def mirror_files(self, paths: List[Path]) -> List[str]:
+        """Copy vault files to each vault.mirror_paths root, keeping their relative
+        layout. Returns the mirrors that received every file; failures are only
+        logged so the primary write stands."""
+        mirrored = []
+        for root in self.vault_config.get("mirror_paths") or []:
+            mirror_root = Path(root).expanduser()
+            try:
+                for path in paths:
+                    path = Path(path)
+                    try:
+                        rel_path = path.resolve().relative_to(self.vault_path.resolve())
+                    except ValueError:
+                        rel_path = Path(path.name)
+                    dest = mirror_root / rel_path
+                    dest.parent.mkdir(parents=True, exist_ok=True)
+                    shutil.copy2(path, dest)
+                mirrored.append(str(mirror_root))
+            except OSError as e:
+                print(f"⚠️  Mirror to {mirror_root} failed: {e}")
+        return mirrored
+
+    @property
+    def filename_pattern(self) -> str:
+        return self.vault_config.get("filename_pattern") or DEFAULT_FILENAME_PATTERN
+
+    def fence_clipboard(self, clip: str, lang: Optional[str]) -> bool:
+        """Whether clipboard text goes in a code fence, per capture.clipboard_fence
+        and capture.clipboard_fence_min_lines."""
+        capture_config = self.config.get("capture") or {}
+        mode = capture_config.get("clipboard_fence") or "auto"
+        if mode not in CLIPBOARD_FENCE_MODES:
+            raise ValidationError(
+                f"Unknown capture.clipboard_fence {mode!r}; "
+                f"expected one of {', '.join(CLIPBOARD_FENCE_MODES)}"
+            )
+        min_lines = int(capture_config.get("clipboard_fence_min_lines") or 1)
+        if mode == "never" or len(clip.strip().splitlines()) < min_lines:
+            return False
+        if mode == "always":
+            return True
+        if mode == "code":
+            return bool(lang) or looks_like_code(clip)
+        return bool(lang) or "\n" not in clip
+
+    def file_stem(self, timestamp: datetime, capture_id: str, content: str = "") -> str:
+        """File name (without .md) from vault.filename_pattern.
+
+        The capture id stays in the frontmatter either way, so links by id keep
+        working whatever the file is called.
+        """
+        fields = {
+            "capture_id": capture_id,
+            "slug": title_slug(content),
+            "date": timestamp.strftime("%Y-%m-%d"),
+            "time": timestamp.strftime("%H%M%S"),
+        }
+        try:
+            stem = self.filename_pattern.format(**fields)
+        except (KeyError, IndexError, ValueError) as e:
+            raise ValidationError(
+                f"Invalid vault.filename_pattern {self.filename_pattern!r}; "
+                f"placeholders are {', '.join(FILENAME_FIELDS)}"
+            ) from e
+        return stem.replace("/", "-").strip() or capture_id
+
     def get_idea_file(
         self,
         timestamp: Optional[datetime] = None,
         capture_id: Optional[str] = None,
+        content: str = "",
     ) -> Path:
         """Get the individual idea markdown file path."""
         if timestamp is None:
@@ -50,13 +381,14 @@ class SafeMarkdownWriter:
         if capture_id is None:
             capture_id = self.generate_capture_id(timestamp)
 
-        filename = f"{capture_id}.md"
+        filename = f"{self.file_stem(timestamp, capture_id, content)}.md"
         return self.capture_dir / filename
 
     def get_unique_idea_file(
         self,
         timestamp: Optional[datetime] = None,
         capture_id: Optional[str] = None,
+        content: str = "",
     ) -> Path:
         """Get a unique idea file path if the original exists."""
         if timestamp is None:
@@ -65,9 +397,10 @@ class SafeMarkdownWriter:
         if capture_id is None:
             capture_id = self.generate_capture_id(timestamp)
 
+        stem = self.file_stem(timestamp, capture_id, content)
         counter = 1
         while True:
-            filename = f"{capture_id}_{counter}.md"
+            filename = f"{stem}_{counter}.md"
             idea_file = self.capture_dir / filename
             if not idea_file.exists():
                 return idea_file
@@ -86,7 +419,7 @@ class SafeMarkdownWriter:
 
         except Exception as e:
             temp_file.unlink(missing_ok=True)
-            raise Exception(f"Failed to write capture: {e}")
+            raise StorageError(f"Failed to write capture: {e}") from e
 
     def format_capture(self, capture_data: Dict[str, Any]) -> str:
         """Format capture data as markdown with YAML frontmatter."""
@@ -129,15 +462,25 @@ class SafeMarkdownWriter:
         else:
             tag_entities = []
 
-        # Handle custom aliases from the capture data
-        custom_aliases = capture_data.get("aliases", [])
-        all_aliases = [capture_id]  # Always include the capture_id as an alias
-        
-        # Add any custom aliases if provided
-        for alias in custom_aliases:
+        # capture.aliases picks the automatic alias; explicit ones are always kept
+        alias_mode = (self.config.get("capture") or {}).get("aliases") or "capture_id"
+        if alias_mode not in ALIAS_MODES:
+            raise ValidationError(
+                f"Unknown capture.aliases {alias_mode!r}; "
+                f"expected one of {', '.join(ALIAS_MODES)}"
+            )
+        if alias_mode == "capture_id":
+            all_aliases = [capture_id]
+        elif alias_mode == "title":
+            title = title_alias(capture_data.get("content") or "")
+            all_aliases = [title] if title else []
+        else:
+            all_aliases = []
+
+        for alias in capture_data.get("aliases", []):
             if alias and alias not in all_aliases:
                 all_aliases.append(alias)
-                
+
         frontmatter = {
             "timestamp": iso_ts,
             "id": capture_id,
@@ -148,53 +491,158 @@ class SafeMarkdownWriter:
             "sources": source_entities,
             "tags": tag_entities,
             "location": capture_data.get("location"),
+            "source_app": capture_data.get("source_app"),
             "metadata": capture_data.get("metadata", {}),
-            "processing_status": "raw",
+            "processing_status": capture_data.get("processing_status") or "raw",
             "created_date": capture_data.get(
-                "created_date", timestamp_for_id.date().isoformat()
+                "created_date", self.format_date(timestamp_for_id)
             ),
             "last_edited_date": capture_data.get(
-                "last_edited_date", timestamp_for_id.date().isoformat()
+                "last_edited_date", self.format_date(timestamp_for_id)
             ),
         }
 
+        if capture_data.get("locked"):
+            frontmatter["locked"] = True
+        if capture_data.get("color"):
+            frontmatter["color"] = capture_data["color"]
+        if capture_data.get("parent"):
+            frontmatter["parent"] = capture_data["parent"]
+        if capture_data.get("priority") is not None:
+            frontmatter["priority"] = capture_data["priority"]
+        if capture_data.get("rating") is not None:
+            frontmatter["rating"] = capture_data["rating"]
+        if capture_data.get("importance") is not None:
+            frontmatter["importance"] = capture_data["importance"]
+        if capture_data.get("collection"):
+            frontmatter["collection"] = capture_data["collection"]
+        if capture_data.get("expires_at"):
+            frontmatter["expires_at"] = capture_data["expires_at"]
+        for key, value in (capture_data.get("fields") or {}).items():
+            frontmatter[FIELD_PREFIX + key] = value
+
         content_sections = []
 
         if str(capture_data.get("content", "")).strip():
             content_sections.append(f"## Content\n{capture_data.get('content')}\n")
 
+        for number, block in enumerate(capture_data.get("blocks") or [], start=1):
+            heading = block.get("heading") or f"Block {number}"
+            content_sections.append(f"## {heading}\n{block.get('body', '')}\n")
+
         clip = str(capture_data.get("clipboard", "") or "")
         if clip.strip():
-            if clip.startswith("```") or "\n" in clip:
+            lang = capture_data.get("clipboard_lang") or detect_code_language(clip)
+            if clip.startswith("```") or not self.fence_clipboard(clip, lang):
                 content_sections.append(f"## Clipboard\n{clip}\n")
             else:
-                content_sections.append(f"## Clipboard\n```\n{clip}\n```\n")
+                content_sections.append(
+                    f"## Clipboard\n```{lang or ''}\n{clip}\n```\n"
+                )
+
+        clipboard_section = content_sections[-1] if clip.strip() else ""
 
         media_files = capture_data.get("media_files", [])
-        if media_files:
+        order = self.vault_config.get("media_section_order") or []
+        if order:
+            # Stable sort: submission order is kept within a type, and types
+            # missing from the list go last
+            media_files = sorted(
+                media_files,
+                key=lambda m: (
+                    order.index(m.get("type", "file"))
+                    if m.get("type", "file") in order
+                    else len(order)
+                ),
+            )
+        if media_files and self.vault_config.get("embed_media", True):
             for media_file in media_files:
+                if media_file.get("inline"):
+                    # Already linked from the content it was extracted from
+                    continue
                 media_type = media_file.get("type", "file")
                 media_path = media_file.get("path", "")
 
                 if media_type == "screenshot":
-                    content_sections.append(
-                        f"## Screenshot\n![Screenshot]({media_path})\n"
-                    )
+                    relative_path = self.get_relative_media_path(media_path)
+                    link = self.media_link(relative_path, "Screenshot", embed=True)
+                    content_sections.append(f"## Screenshot\n{link}\n")
                 elif media_type == "audio":
                     relative_path = self.get_relative_media_path(media_path)
-                    content_sections.append(
-                        f"## Audio\n[Audio Recording]({relative_path})\n"
+                    link = self.media_link(
+                        relative_path, "Audio Recording", embed=False
                     )
+                    content_sections.append(f"## Audio\n{link}\n")
                 elif media_type == "image":
                     relative_path = self.get_relative_media_path(media_path)
-                    content_sections.append(f"## Image\n![Image]({relative_path})\n")
+                    link = self.media_link(relative_path, "Image", embed=True)
+                    content_sections.append(f"## Image\n{link}\n")
                 else:
                     relative_path = self.get_relative_media_path(media_path)
-                    content_sections.append(f"## File\n[Attachment]({relative_path})\n")
+                    link = self.media_link(relative_path, "Attachment", embed=False)
+                    content_sections.append(f"## File\n{link}\n")
+
+        body = "".join(content_sections)
+        template = self.load_template(capture_data.get("modalities"))
+        if template is not None:
+            values = {
+                "sections": body,
+                "content": str(capture_data.get("content", "") or ""),
+                "clipboard": clipboard_section,
+                "capture_id": capture_id,
+                "timestamp": iso_ts,
+                "created_date": frontmatter["created_date"],
+                "tags": ", ".join(str(t) for t in tag_entities),
+                "sources": ", ".join(str(s) for s in source_entities),
+                "context": ", ".join(str(c) for c in context_entities),
+            }
+            body = TEMPLATE_PLACEHOLDER_RE.sub(
+                lambda m: values.get(m.group(1), ""), template
+            )
+
+        if self.vault_config.get("trailing_newline", True):
+            body = body.rstrip("\n") + "\n" if body.strip("\n") else ""
+        if (self.config.get("capture") or {}).get("content_hash"):
+            frontmatter["content_sha256"] = content_sha256(body)
 
         yaml_content = yaml.dump(frontmatter, default_flow_style=False, sort_keys=False)
-        formatted_content = f"---\n{yaml_content}---\n{''.join(content_sections)}"
-        return formatted_content
+        return f"---\n{yaml_content}---\n{body}"
+
+    def template_path(self, modalities: Optional[List[str]] = None) -> Optional[Path]:
+        """capture.template_file for a capture, resolved against the vault.
+
+        The setting is one path, or a mapping from modality to path with an
+        optional "default"; the first of the capture's modalities listed wins.
+        """
+        template_file = (self.config.get("capture") or {}).get("template_file")
+        if isinstance(template_file, dict):
+            template_file = next(
+                (template_file[m] for m in modalities or [] if template_file.get(m)),
+                template_file.get("default"),
+            )
+        if not template_file:
+            return None
+        return self.vault_path / Path(template_file).expanduser()
+
+    def load_template(self, modalities: Optional[List[str]] = None) -> Optional[str]:
+        """Body template from capture.template_file (relative to the vault), or None
+        to use the built-in layout. The frontmatter is always written first.
+
+        Templates without a "## Content" heading or {{sections}} also fall back,
+        since content edits replace that section.
+        """
+        path = self.template_path(modalities)
+        if path is None:
+            return None
+        try:
+            mtime = path.stat().st_mtime_ns
+            cached = _template_cache.get(path)
+            if cached is None or cached[0] != mtime:
+                cached = (mtime, path.read_text(encoding="utf-8"))
+                _template_cache[path] = cached
+        except (OSError, UnicodeDecodeError):
+            return None
+        return cached[1] if has_content_section(cached[1]) else None
 
     def generate_capture_id(self, timestamp: datetime, provided_id: str = None) -> str:
         """Generate a unique capture ID based on timestamp or use provided ID."""
@@ -202,6 +650,41 @@ class SafeMarkdownWriter:
             return provided_id
         return timestamp.isoformat()
 
+    def format_date(self, value: datetime) -> str:
+        return format_frontmatter_date(value, self.vault_config.get("date_format"))
+
+    def parse_date(self, value: Any) -> Optional[datetime]:
+        """Parse a frontmatter date in the configured format (or ISO 8601)."""
+        if isinstance(value, datetime):
+            return value
+        if isinstance(value, date):
+            return datetime(value.year, value.month, value.day)
+        if not value:
+            return None
+        text = str(value)
+        try:
+            return datetime.strptime(
+                text, self.vault_config.get("date_format") or DEFAULT_DATE_FORMAT
+            )
+        except ValueError:
+            pass
+        try:
+            return datetime.fromisoformat(text)
+        except ValueError:
+            return None
+
+    def _frontmatter_date(self, value: Any) -> str:
+        parsed = self.parse_date(value)
+        if parsed is None:
+            return str(value or "")
+        return self.format_date(parsed)
+
+    def media_link(self, path: str, label: str, embed: bool) -> str:
+        """Render a media reference in the configured link style."""
+        if self.vault_config.get("media_link_style", "markdown") == "wikilink":
+            return f"![[{path}]]" if embed else f"[[{path}]]"
+        return f"![{label}]({path})" if embed else f"[{label}]({path})"
+
     def get_relative_media_path(self, media_path: str) -> str:
         """Convert absolute media path to relative path from capture dir."""
         media_path_obj = Path(media_path)
@@ -217,7 +700,7 @@ class SafeMarkdownWriter:
         return sorted(idea_files, key=lambda f: f.stat().st_mtime, reverse=True)
 
     def read_idea_file(self, idea_file: Path) -> Optional[Dict[str, Any]]:
-        """Read and parse an existing idea file."""
+        """Read and parse an existing idea file; None if it has no frontmatter."""
         try:
             with idea_file.open("r", encoding="utf-8") as f:
                 content = f.read()
@@ -233,10 +716,170 @@ class SafeMarkdownWriter:
                         "body": body,
                         "file_path": idea_file,
                     }
-        except Exception as e:
-            print(f"Error reading idea file {idea_file}: {e}")
+        except (OSError, UnicodeDecodeError, yaml.YAMLError) as e:
+            raise StorageError(f"Error reading idea file {idea_file}: {e}") from e
         return None
 
+    def find_capture_file(self, capture_id: str) -> Path:
+        """Location of a capture file when the database has no path.
+
+        With a custom vault.filename_pattern the capture directory is searched
+        for the file whose frontmatter carries the id.
+        """
+        default = self.capture_dir / f"{capture_id}.md"
+        if default.exists() or self.filename_pattern == DEFAULT_FILENAME_PATTERN:
+            return default
+        for idea_file in self.capture_dir.glob("*.md"):
+            try:
+                frontmatter, _ = self.split_frontmatter(
+                    idea_file.read_text(encoding="utf-8")
+                )
+            except (OSError, UnicodeDecodeError, yaml.YAMLError):
+                continue
+            if str(frontmatter.get("capture_id") or "") == capture_id:
+                return idea_file
+        return default
+
+    def split_frontmatter(self, text: str) -> Tuple[Dict[str, Any], str]:
+        """Split a capture file into its frontmatter mapping and raw body."""
+        match = FRONTMATTER_RE.match(text)
+        if not match:
+            return {}, text
+        frontmatter = yaml.safe_load(match.group(1)) or {}
+        if not isinstance(frontmatter, dict):
+            return {}, text
+        return frontmatter, match.group(2)
+
+    def capture_body(self, idea_file: Path) -> Optional[str]:
+        """A capture file's body without its frontmatter, or None if the file
+        can't be read."""
+        try:
+            text = idea_file.read_text(encoding="utf-8")
+        except (OSError, UnicodeDecodeError):
+            return None
+        match = FRONTMATTER_RE.match(text)
+        return (match.group(2) if match else text).strip()
+
+    def read_capture(self, idea_file: Path) -> Dict[str, Any]:
+        """Capture data from a capture file in the shape write_capture takes, so
+        it can be edited and written back.
+
+        The body's Content, Clipboard and media sections become content,
+        clipboard and media_files, and other sections become blocks; a body
+        without sections is all content. Frontmatter that doesn't parse is
+        treated as missing rather than failing the read.
+        """
+        try:
+            text = idea_file.read_text(encoding="utf-8")
+        except (OSError, UnicodeDecodeError) as e:
+            raise StorageError(f"Error reading capture file {idea_file}: {e}") from e
+        try:
+            frontmatter, body = self.split_frontmatter(text)
+        except yaml.YAMLError:
+            match = FRONTMATTER_RE.match(text)
+            frontmatter, body = {}, match.group(2) if match else text
+        capture = self._frontmatter_capture(frontmatter, idea_file)
+        for key in ("created_date", "last_edited_date"):
+            if not capture[key]:
+                del capture[key]
+        capture["aliases"] = _frontmatter_list(frontmatter.get("aliases"))
+
+        sections = body_sections(body)
+        if not sections:
+            capture["content"] = body.strip()
+            return capture
+        capture["content"] = ""
+        capture["blocks"] = []
+        capture["media_files"] = []
+        for heading, text in sections:
+            if heading == "Content" and not capture["content"]:
+                capture["content"] = text
+            elif heading == "Clipboard":
+                capture["clipboard"] = text
+            elif heading in MEDIA_SECTIONS:
+                for wikilink, markdown in MEDIA_LINK_RE.findall(text):
+                    path = Path((wikilink or markdown).strip().strip("<>"))
+                    if not path.is_absolute():
+                        path = Path(os.path.normpath(self.capture_dir / path))
+                    media_file = {"path": str(path), "name": path.name}
+                    if heading != "File":
+                        media_file["type"] = heading.lower()
+                    capture["media_files"].append(media_file)
+            else:
+                capture["blocks"].append({"heading": heading, "body": text})
+        return capture
+
+    def _frontmatter_capture(
+        self, frontmatter: Dict[str, Any], idea_file: Path
+    ) -> Dict[str, Any]:
+        """Capture data from frontmatter, tolerating hand-edited values."""
+        timestamp = frontmatter.get("timestamp")
+        if isinstance(timestamp, str):
+            try:
+                timestamp = datetime.fromisoformat(timestamp)
+            except ValueError:
+                timestamp = None
+        if not isinstance(timestamp, datetime):
+            timestamp = datetime.fromtimestamp(idea_file.stat().st_mtime, timezone.utc)
+
+        context = frontmatter.get("context") or []
+        if isinstance(context, list):
+            context = ", ".join(str(c) for c in context if c)
+
+        return {
+            "capture_id": str(
+                frontmatter.get("capture_id") or frontmatter.get("id") or idea_file.stem
+            ),
+            "timestamp": timestamp,
+            "context": str(context),
+            "tags": _frontmatter_list(frontmatter.get("tags")),
+            "sources": _frontmatter_list(frontmatter.get("sources")),
+            "modalities": _frontmatter_list(frontmatter.get("modalities")) or ["text"],
+            "location": frontmatter.get("location"),
+            "metadata": frontmatter.get("metadata") or {},
+            "created_date": self._frontmatter_date(frontmatter.get("created_date")),
+            "last_edited_date": self._frontmatter_date(
+                frontmatter.get("last_edited_date")
+            ),
+            "file_path": str(idea_file),
+            "locked": bool(frontmatter.get("locked")),
+            "color": frontmatter.get("color"),
+            "processing_status": frontmatter.get("processing_status") or "raw",
+            "parent": frontmatter.get("parent"),
+            "priority": frontmatter.get("priority"),
+            "rating": frontmatter.get("rating"),
+            "importance": frontmatter.get("importance"),
+            "collection": frontmatter.get("collection"),
+            "expires_at": expiry_timestamp(frontmatter.get("expires_at")),
+            "fields": {
+                key[len(FIELD_PREFIX) :]: value
+                for key, value in frontmatter.items()
+                if str(key).startswith(FIELD_PREFIX)
+            },
+        }
+
+    def render_file(self, frontmatter: Dict[str, Any], body: str) -> str:
+        if "content_sha256" in frontmatter:
+            # Signed captures stay verifiable after edits made through the app
+            frontmatter = {**frontmatter, "content_sha256": content_sha256(body)}
+        yaml_content = yaml.dump(frontmatter, default_flow_style=False, sort_keys=False)
+        return f"---\n{yaml_content}---\n{body}"
+
+    def rewrite_frontmatter(self, idea_file: Path, updates: Dict[str, Any]) -> str:
+        """Apply frontmatter updates to an existing file; returns the previous text.
+
+        Keys mapped to None are removed from the frontmatter.
+        """
+        original = idea_file.read_text(encoding="utf-8")
+        frontmatter, body = self.split_frontmatter(original)
+        for key, value in updates.items():
+            if value is None:
+                frontmatter.pop(key, None)
+            else:
+                frontmatter[key] = value
+        self.atomic_write(idea_file, self.render_file(frontmatter, body))
+        return original
+
     def save_media_file(self, source_path: Path, media_type: str) -> Path:
         """Save media file to media directory with unique name."""
         timestamp = datetime.now().strftime("%Y%m%d_%H%M%S_%f")[:-3]
@@ -265,7 +908,7 @@ class SafeMarkdownWriter:
             shutil.copy2(source_path, target_path)
             return target_path
         except Exception as e:
-            raise Exception(f"Failed to save media file: {e}")
+            raise StorageError(f"Failed to save media file: {e}") from e
 
 
 if __name__ == "__main__":
@@ -2,15 +2,46 @@ import os
 import sys
 import argparse
 import asyncio
+import base64
+import binascii
+import difflib
+import errno
+import fnmatch
+import html
+import inspect
+import math
+import shutil
 import subprocess
+import tempfile
+import threading
+import time
+import uuid
+import anyio.to_thread
 import yaml
 from pathlib import Path
-from datetime import datetime, timezone
-from typing import List, Optional, Dict, Any, Set
+from datetime import date, datetime, timedelta, timezone
+from typing import List, Optional, Dict, Any, Set, Tuple
 
-from fastapi import FastAPI, UploadFile, File, Form, WebSocket, WebSocketDisconnect
+from fastapi import (
+    FastAPI,
+    UploadFile,
+    File,
+    Form,
+    Body,
+    Query,
+    Request,
+    WebSocket,
+    WebSocketDisconnect,
+)
 from fastapi.middleware.cors import CORSMiddleware
-from fastapi.responses import JSONResponse, FileResponse
+from fastapi.responses import (
+    JSONResponse,
+    FileResponse,
+    HTMLResponse,
+    PlainTextResponse,
+    Response,
+    StreamingResponse,
+)
 from fastapi.staticfiles import StaticFiles
 
 # Import alias suggestions module
@@ -24,7 +55,7 @@ from hypercorn.config import Config
 from hypercorn.asyncio import serve
 
 sys.path.append(str(Path(__file__).resolve().parent.parent))
-from geolocation import get_device_location
+from geolocation import get_location
 
 # Try to import audio recorder, but make it optional
 try:
@@ -40,23 +71,151 @@ import json
 import re
 import http.client
 
-from main_db import MainDatabase
-from markdown_writer import SafeMarkdownWriter
+from kms_errors import (
+    KmsError,
+    CommandTimeoutError,
+    ConflictError,
+    DatabaseError,
+    ForbiddenError,
+    NotFoundError,
+    StorageError,
+    UnsupportedError,
+    ValidationError,
+)
+from main_db import MainDatabase, value_color
+from markdown_writer import (
+    DEFAULT_FILENAME_PATTERN,
+    FIELD_PREFIX,
+    PROCESSING_STATUSES,
+    SafeMarkdownWriter,
+    content_sha256,
+    expiry_timestamp,
+    format_frontmatter_date,
+    media_links,
+    section_headings,
+    title_alias,
+    unresolved_placeholders,
+    validate_fields,
+)
+from source_meta import (
+    DEFAULT_STRIP_PARAMS,
+    fetch_source_meta,
+    is_url,
+    strip_query_params,
+)
 
 app = FastAPI()
-app.add_middleware(
-    CORSMiddleware,
-    allow_origins=["*"],
-    allow_credentials=True,
-    allow_methods=["*"],
-    allow_headers=["*"],
-)
+
+
+class Metrics:
+    """Thread-safe counters exposed in Prometheus text format."""
+
+    HELP = {
+        "kms_captures_written_total": "Captures successfully written",
+        "kms_capture_errors_total": "Captures that failed to save",
+        "kms_screenshots_total": "Screenshot attempts by result",
+        "kms_http_requests_total": "HTTP requests by endpoint",
+    }
+
+    def __init__(self):
+        self._lock = threading.Lock()
+        self._counters: Dict[str, Dict[tuple, int]] = {}
+
+    def inc(self, name: str, labels: Optional[Dict[str, str]] = None, amount: int = 1):
+        key = tuple(sorted((labels or {}).items()))
+        with self._lock:
+            series = self._counters.setdefault(name, {})
+            series[key] = series.get(key, 0) + amount
+
+    @staticmethod
+    def _escape_label(value) -> str:
+        return str(value).replace("\\", "\\\\").replace('"', '\\"')
+
+    def render(self) -> str:
+        lines = []
+        with self._lock:
+            names = sorted(set(self.HELP) | set(self._counters))
+            for name in names:
+                lines.append(f"# HELP {name} {self.HELP.get(name, name)}")
+                lines.append(f"# TYPE {name} counter")
+                series = self._counters.get(name) or {(): 0}
+                for labels, value in sorted(series.items()):
+                    if labels:
+                        rendered = ",".join(
+                            f'{k}="{self._escape_label(v)}"' for k, v in labels
+                        )
+                        lines.append(f"{name}{{{rendered}}} {value}")
+                    else:
+                        lines.append(f"{name} {value}")
+        return "\n".join(lines) + "\n"
+
+
+metrics = Metrics()
+
+
+class RateLimiter:
+    """Per-client request budget over fixed one-minute windows."""
+
+    def __init__(self, per_minute: int):
+        self.per_minute = per_minute
+        self._lock = threading.Lock()
+        self._windows: Dict[str, tuple] = {}
+
+    def allow(self, client: str, now: Optional[float] = None) -> bool:
+        window = int((time.time() if now is None else now) // 60)
+        with self._lock:
+            start, count = self._windows.get(client, (window, 0))
+            if start != window:
+                start, count = window, 0
+            self._windows[client] = (start, count + 1)
+            return count < self.per_minute
+
+
+# Set from server.rate_limit_per_minute at startup; None disables limiting
+rate_limiter: Optional[RateLimiter] = None
+
+
+@app.middleware("http")
+async def limit_requests(request: Request, call_next):
+    client = request.client.host if request.client else ""
+    if rate_limiter and not rate_limiter.allow(client):
+        return JSONResponse({"error": "Rate limit exceeded"}, status_code=429)
+    return await call_next(request)
+
+
+@app.middleware("http")
+async def count_requests(request: Request, call_next):
+    response = await call_next(request)
+    # Label by route template (e.g. /api/captures/{capture_id}) to bound
+    # cardinality; static files and unmatched paths get one label each
+    route = request.scope.get("route")
+    endpoint = getattr(route, "path", None)
+    if not endpoint:
+        mounted = route is not None or request.scope.get("endpoint") is not None
+        endpoint = "static" if mounted else "unmatched"
+    labels = {
+        "endpoint": endpoint,
+        "method": request.method,
+        "status": str(response.status_code),
+    }
+    metrics.inc("kms_http_requests_total", labels)
+    return response
+
+
+@app.exception_handler(KmsError)
+async def kms_error_handler(request: Request, exc: KmsError):
+    return JSONResponse({"error": str(exc)}, status_code=exc.status_code)
+
 
 web_dist_path = Path(__file__).resolve().parent.parent / "web" / "dist"
 if not web_dist_path.exists():
     web_dist_path = Path(__file__).resolve().parent / "web" / "dist"
 
 main_db = None
+# Databases of vaults that configure their own (see _vault_cfg), by path
+_vault_dbs: Dict[str, MainDatabase] = {}
+# Concurrent first requests must not open (and migrate) the database twice
+_main_db_lock = threading.Lock()
 # Global variables to track AI-suggested tags/sources
 _ai_suggested_tags = set()
 _ai_suggested_sources = set()
@@ -64,24 +223,91 @@ _ai_suggested_sources = set()
 _config_path = None
 audio_manager = AudioRecordingManager() if AUDIO_RECORDING_AVAILABLE else None
 _ai_cache = {}
+# Storage usage is expensive to compute on large vaults, so it is cached briefly
+STORAGE_STATS_TTL_SECONDS = 30
+_storage_stats_cache: Dict[str, Any] = {}
+# vault.on_invalid_path: warn problems already printed, so each shows once
+_warned_vault_problems = set()
 
 
-def get_main_db():
-    """Get the initialized main database instance."""
+def get_main_db(cfg=None):
+    """Get the initialized main database instance.
+
+    With `cfg` (e.g. from _apply_vault_override), returns the database at its
+    database.path, which differs from the main one for vaults with their own.
+    """
     global main_db
+    # The lock is only taken while a database is being opened; once cached,
+    # lookups don't serialize requests
     if main_db is None:
-        cfg = normalize_config(load_config(_config_path))
-        main_db = MainDatabase(cfg["database"]["path"])
-    return main_db
+        with _main_db_lock:
+            if main_db is None:
+                main_db = _open_main_db(normalize_config(load_config(_config_path)))
+    if cfg is None or Path(cfg["database"]["path"]) == main_db.db_path:
+        return main_db
+    db_path = str(cfg["database"]["path"])
+    db = _vault_dbs.get(db_path)
+    if db is None:
+        with _main_db_lock:
+            db = _vault_dbs.get(db_path)
+            if db is None:
+                Path(db_path).parent.mkdir(parents=True, exist_ok=True)
+                db = _vault_dbs[db_path] = _open_main_db(cfg)
+    return db
 
 
-def load_config(config_path=None):
+def _open_main_db(cfg) -> MainDatabase:
+    # Media paths are stored relative to the vault unless configured otherwise
+    relative = cfg["vault"]["media_path_style"] == "relative"
+    return MainDatabase(
+        cfg["database"]["path"], cfg["vault"]["path"] if relative else None
+    )
+
+
+def _get_writer(cfg) -> SafeMarkdownWriter:
+    problem = _check_vault_path(cfg)
+    if problem and problem not in _warned_vault_problems:
+        _warned_vault_problems.add(problem)
+        print(f"⚠️  {problem}")
+    return SafeMarkdownWriter(str(Path(cfg["vault"]["path"]).expanduser()), cfg)
+
+
+def _format_date(cfg, value: Optional[datetime] = None) -> str:
+    """created_date/last_edited_date string in the configured vault.date_format."""
+    value = value or datetime.now(timezone.utc)
+    return format_frontmatter_date(value, cfg["vault"]["date_format"])
+
+
+def _discover_config_path() -> Path:
+    """Find config.yaml when no --config is given.
+
+    Order: $KMS_CONFIG_PATH, $XDG_CONFIG_HOME/kms-capture/config.yaml (or
+    ~/.config/kms-capture/config.yaml), then config.yaml in the repo root.
+    """
+    if os.environ.get("KMS_CONFIG_PATH"):
+        return Path(os.environ["KMS_CONFIG_PATH"]).expanduser()
+    xdg_path = _xdg_config_path()
+    if xdg_path.exists():
+        return xdg_path
+    return Path(__file__).resolve().parent.parent / "config.yaml"
+
+
+def _xdg_config_path() -> Path:
+    xdg = os.environ.get("XDG_CONFIG_HOME") or str(Path.home() / ".config")
+    return Path(xdg).expanduser() / "kms-capture" / "config.yaml"
+
+
+def _resolve_config_path(config_path=None) -> Path:
     if config_path:
         cfg_path = Path(config_path)
         if not cfg_path.is_absolute():
             cfg_path = Path(__file__).resolve().parent.parent / config_path
-    else:
-        cfg_path = Path(__file__).resolve().parent.parent / "config.yaml"
+        return cfg_path
+    return _discover_config_path()
+
+
+def load_config(config_path=None):
+    cfg_path = _resolve_config_path(config_path)
 
     if not cfg_path.exists():
         return {}
@@ -89,6 +315,62 @@ def load_config(config_path=None):
         return yaml.safe_load(f) or {}
 
 
+def _data_dir() -> Path:
+    """Directory for application data that lives outside the vault."""
+    if "KMS_DATA_DIR" in os.environ:
+        return Path(os.environ["KMS_DATA_DIR"])
+    if "XDG_DATA_HOME" in os.environ:
+        return Path(os.environ["XDG_DATA_HOME"]) / "kms-capture"
+    return Path.home() / ".local" / "share" / "kms-capture"
+
+
+def _render_db_path_template(template: str, mode: str) -> str:
+    """Expand a database.path_template such as "{data_dir}/{mode}.db"."""
+    placeholders = {
+        "data_dir": str(_data_dir()),
+        "repo_root": str(Path(__file__).resolve().parent.parent),
+        "home": str(Path.home()),
+        "mode": mode,
+    }
+    try:
+        rendered = template.format(**placeholders)
+    except (KeyError, IndexError) as e:
+        raise ValueError(
+            f"Unknown placeholder {e} in database.path_template; "
+            f"available: {', '.join(placeholders)}"
+        )
+    return os.path.expanduser(rendered)
+
+
+def _obsidian_attachment_dir(vault_path: str, capture_dir: str) -> Optional[str]:
+    """Vault-relative media dir from Obsidian's attachmentFolderPath, if set.
+
+    "/" is the vault root and "./sub" is relative to the note's folder, which for
+    captures is capture_dir.
+    """
+    app_json = Path(vault_path).expanduser() / ".obsidian" / "app.json"
+    try:
+        folder = json.loads(app_json.read_text(encoding="utf-8"))[
+            "attachmentFolderPath"
+        ]
+    except (OSError, ValueError, KeyError, TypeError):
+        return None
+    if not isinstance(folder, str):
+        return None
+    if folder == "." or folder.startswith("./"):
+        return str(Path(capture_dir) / folder[2:])
+    return folder.strip("/") or "."
+
+
+def _vault_media_dir(vault_path: str, vault_config) -> str:
+    capture_dir = vault_config.get("capture_dir") or "capture/raw_capture"
+    if vault_config.get("use_obsidian_attachment_folder"):
+        obsidian_dir = _obsidian_attachment_dir(vault_path, capture_dir)
+        if obsidian_dir:
+            return obsidian_dir
+    return vault_config.get("media_dir") or "capture/raw_capture/media"
+
+
 def normalize_config(cfg):
     dev_config = cfg.get("development", {})
     mode = dev_config.get("mode", "prod")
@@ -106,18 +388,21 @@ def normalize_config(cfg):
         vault_path = root_path + "/dev"
 
     db_path = database_config.get("path", "server/main.db")
+    path_template = database_config.get("path_template")
+    if isinstance(path_template, dict):
+        path_template = path_template.get(mode)
 
     if "KMS_DATA_DIR" in os.environ:
         data_dir = Path(os.environ["KMS_DATA_DIR"])
         db_path = str(data_dir / "main.db")
     elif "KMS_DB_PATH" in os.environ:
         db_path = os.environ["KMS_DB_PATH"]
+    elif path_template:
+        db_path = _render_db_path_template(path_template, mode)
+        Path(db_path).parent.mkdir(parents=True, exist_ok=True)
     elif not Path(db_path).is_absolute():
         if mode == "prod":
-            if "XDG_DATA_HOME" in os.environ:
-                data_dir = Path(os.environ["XDG_DATA_HOME"]) / "kms-capture"
-            else:
-                data_dir = Path.home() / ".local" / "share" / "kms-capture"
+            data_dir = _data_dir()
             data_dir.mkdir(parents=True, exist_ok=True)
             db_path = str(data_dir / "main.db")
         else:
@@ -130,22 +415,190 @@ def normalize_config(cfg):
         "vault": {
             "path": os.path.expanduser(vault_path),
             "capture_dir": vault_config.get("capture_dir") or "capture/raw_capture",
-            "media_dir": vault_config.get("media_dir") or "capture/raw_capture/media",
+            "media_dir": _vault_media_dir(vault_path, vault_config),
+            "use_obsidian_attachment_folder": bool(
+                vault_config.get("use_obsidian_attachment_folder", False)
+            ),
+            "trailing_newline": vault_config.get("trailing_newline", True),
+            "embed_media": bool(vault_config.get("embed_media", True)),
+            "media_section_order": vault_config.get("media_section_order") or [],
+            "media_path_style": vault_config.get("media_path_style") or "relative",
+            "media_link_style": vault_config.get("media_link_style") or "markdown",
+            "allowed_roots": vault_config.get("allowed_roots") or [],
+            "restrict_to_allowed_roots": bool(
+                vault_config.get("restrict_to_allowed_roots", False)
+            ),
+            "on_invalid_path": vault_config.get("on_invalid_path") or "error",
+            "reindex_exclude": vault_config.get("reindex_exclude") or [],
+            "mirror_paths": vault_config.get("mirror_paths") or [],
+            "archive_dir": vault_config.get("archive_dir")
+            or "capture/raw_capture/archive",
+            "hide_archived": bool(vault_config.get("hide_archived", False)),
+            "trash_dir": vault_config.get("trash_dir") or "capture/trash",
+            "date_format": vault_config.get("date_format") or "%Y-%m-%d",
+            "filename_pattern": vault_config.get("filename_pattern")
+            or DEFAULT_FILENAME_PATTERN,
         },
         "database": {
             "path": db_path,
         },
         "ui": cfg.get("ui", {}),
         "capture": cfg.get("capture", {}),
-        "keybindings": cfg.get("keybindings", {}),
+        "location": _location_config(cfg),
+        "keybindings": _parse_keybindings(cfg.get("keybindings"))["bindings"],
         "theme": cfg.get("theme", {}),
         "ai": cfg.get("ai", {}),
+        "sources": cfg.get("sources", {}),
+        "suggestions": cfg.get("suggestions", {}),
+        "server": cfg.get("server", {}),
+        "export": cfg.get("export", {}),
         "mode": mode,
         "is_dev": is_dev,
     }
     return d
 
 
+def _location_config(cfg) -> Dict[str, Any]:
+    """The `location` section; the older capture.geolocation_enabled: false
+    still turns lookups off when no mode is set."""
+    location = dict(cfg.get("location") or {})
+    if (cfg.get("capture") or {}).get("geolocation_enabled") is False:
+        location.setdefault("mode", "off")
+    return location
+
+
+LOOPBACK_HOSTS = {"127.0.0.1", "::1", "localhost"}
+
+
+def _is_loopback(request: Request) -> bool:
+    return bool(request.client) and request.client.host in LOOPBACK_HOSTS
+
+
+def _apply_vault_override(request: Request, cfg):
+    """Route a single request to the vault named in X-KMS-Vault.
+
+    Only loopback clients may use the header, and the path must sit under one of
+    `vault.allowed_roots`. Returns (cfg, error_response).
+    """
+    try:
+        return _vault_override(request, cfg), None
+    except ForbiddenError as e:
+        return cfg, JSONResponse({"error": str(e)}, status_code=403)
+
+
+def _vault_override(request: Request, cfg):
+    """Like _apply_vault_override, but raises ForbiddenError."""
+    override = request.headers.get("x-kms-vault", "").strip()
+    if not override:
+        return cfg
+    if not _is_loopback(request):
+        raise ForbiddenError("X-KMS-Vault is only accepted from loopback clients")
+    return _vault_cfg(cfg, override)
+
+
+def _request_vault(request: Request) -> Tuple[Dict[str, Any], MainDatabase]:
+    """Config and database for a request that reads or edits captures, following
+    X-KMS-Vault to vaults with their own database."""
+    cfg = _vault_override(request, normalize_config(load_config(_config_path)))
+    return cfg, get_main_db(cfg)
+
+
+def _allowed_roots(cfg) -> List[Tuple[Path, Optional[str]]]:
+    """(root, database) for each vault.allowed_roots entry. Entries are a path,
+    or {path, database} for vaults that keep their own database."""
+    roots = []
+    for entry in cfg["vault"]["allowed_roots"]:
+        if isinstance(entry, dict):
+            path, database = entry.get("path"), entry.get("database")
+        else:
+            path, database = entry, None
+        if path:
+            roots.append((Path(str(path)).expanduser().resolve(), database))
+    return roots
+
+
+def _vault_cfg(cfg, vault_path: str):
+    """Config for another vault, which must sit under one of vault.allowed_roots.
+
+    If the closest matching root names a `database`, the vault uses it instead
+    of the main one; a relative database path is resolved against the vault.
+    """
+    target = Path(vault_path).expanduser().resolve()
+    matches = [
+        (root, database)
+        for root, database in _allowed_roots(cfg)
+        if target == root or root in target.parents
+    ]
+    if not matches:
+        raise ForbiddenError(f"Vault path {vault_path} is not in vault.allowed_roots")
+    vault = {**cfg["vault"], "path": str(target)}
+    if vault["use_obsidian_attachment_folder"]:
+        vault["media_dir"] = _vault_media_dir(str(target), vault)
+    result = {**cfg, "vault": vault}
+    _, database = max(matches, key=lambda match: len(match[0].parts))
+    if database:
+        db_path = Path(str(database)).expanduser()
+        if not db_path.is_absolute():
+            db_path = target / db_path
+        result["database"] = {**cfg["database"], "path": str(db_path)}
+    return result
+
+
+VAULT_PATH_FAILURE_MODES = ("error", "warn")
+
+
+def _canonical_vault_path(cfg) -> Path:
+    """vault.path with `~` expanded and symlinks resolved.
+
+    Raises ValidationError for a broken symlink or a symlink loop anywhere in
+    the path, and ForbiddenError when `vault.restrict_to_allowed_roots` is set
+    and the path is outside every `vault.allowed_roots` entry.
+    """
+    configured = cfg["vault"]["path"]
+    path = Path(configured).expanduser()
+    for part in (path, *path.parents):
+        if not part.is_symlink():
+            continue
+        try:
+            part.stat()
+        except OSError as e:
+            if e.errno == errno.ELOOP:
+                raise ValidationError(
+                    f"vault.path {configured}: {part} is a symlink loop"
+                ) from e
+            raise ValidationError(
+                f"vault.path {configured}: {part} is a broken symlink"
+            ) from e
+    resolved = path.resolve()
+    if cfg["vault"]["restrict_to_allowed_roots"] and not any(
+        resolved == root or root in resolved.parents
+        for root, _ in _allowed_roots(cfg)
+    ):
+        raise ForbiddenError(
+            f"vault.path {configured} resolves to {resolved}, "
+            "which is not in vault.allowed_roots"
+        )
+    return resolved
+
+
+def _check_vault_path(cfg) -> Optional[str]:
+    """Problem with vault.path, or None. With `vault.on_invalid_path: error`
+    (the default) the problem is raised instead of returned."""
+    mode = cfg["vault"]["on_invalid_path"]
+    if mode not in VAULT_PATH_FAILURE_MODES:
+        raise ValidationError(
+            f"Unknown vault.on_invalid_path {mode!r}; "
+            f"expected one of {', '.join(VAULT_PATH_FAILURE_MODES)}"
+        )
+    try:
+        _canonical_vault_path(cfg)
+    except KmsError as e:
+        if mode == "error":
+            raise
+        return str(e)
+    return None
+
+
 def _kebab_case(s: str) -> str:
     s = s.strip().lower()
     s = re.sub(r"[^a-z0-9]+", "-", s)
@@ -234,252 +687,3259 @@ def _build_prompt(field_type: str, content: str, cfg: dict) -> str:
     return ""
 
 
-@app.get("/api/config")
-def api_config():
-    cfg = normalize_config(load_config(_config_path))
-    return cfg
+@app.get("/api/config")
+def api_config():
+    cfg = normalize_config(load_config(_config_path))
+    return cfg
+
+
+@app.get("/api/config/paths")
+def api_config_paths():
+    """Where the server reads and writes, with vault.path canonicalized."""
+    cfg = normalize_config(load_config(_config_path))
+    try:
+        resolved, error = str(_canonical_vault_path(cfg)), None
+    except KmsError as e:
+        resolved, error = None, str(e)
+    vault = Path(cfg["vault"]["path"]).expanduser()
+    return {
+        "config": str(_resolve_config_path(_config_path)),
+        "vault": cfg["vault"]["path"],
+        "vault_resolved": resolved,
+        "vault_error": error,
+        "capture_dir": str(vault / cfg["vault"]["capture_dir"]),
+        "media_dir": str(vault / cfg["vault"]["media_dir"]),
+        "database": cfg["database"]["path"],
+    }
+
+
+# Read once at startup, so edits to these only apply after a restart
+RESTART_CONFIG_SECTIONS = ("server", "database")
+
+
+@app.get("/api/config/raw")
+def api_config_raw():
+    """config.yaml as written, for the settings editor."""
+    cfg_path = _resolve_config_path(_config_path)
+    text = cfg_path.read_text() if cfg_path.exists() else ""
+    return PlainTextResponse(text, media_type="application/yaml; charset=utf-8")
+
+
+@app.put("/api/config/raw")
+def api_config_raw_update(request: Request, payload: Dict[str, Any] = Body(...)):
+    """Validate {"content": yaml} and atomically replace config.yaml with it.
+
+    Other settings are read per request and apply immediately; changes to the
+    sections in RESTART_CONFIG_SECTIONS are listed in `restart_required`. Only
+    loopback clients may do this, since the config names shell commands
+    (capture.post_save_hook) and the vaults requests may reach.
+    """
+    if not _is_loopback(request):
+        raise ForbiddenError("The config can only be changed from loopback clients")
+    text = payload.get("content")
+    if not isinstance(text, str):
+        raise ValidationError("Expected a string 'content' field")
+    try:
+        new_cfg = yaml.safe_load(text) or {}
+    except yaml.YAMLError as e:
+        mark = getattr(e, "problem_mark", None)
+        problem = getattr(e, "problem", None) or str(e)
+        return JSONResponse(
+            {
+                "error": f"Invalid YAML: {problem}",
+                "line": mark.line + 1 if mark else None,
+                "column": mark.column + 1 if mark else None,
+            },
+            status_code=400,
+        )
+    if not isinstance(new_cfg, dict):
+        raise ValidationError("Config must be a YAML mapping")
+    try:
+        normalize_config(new_cfg)
+    except ValueError as e:
+        raise ValidationError(str(e)) from e
+
+    cfg_path = _resolve_config_path(_config_path)
+    old_cfg = load_config(_config_path)
+    cfg_path.parent.mkdir(parents=True, exist_ok=True)
+    fd, temp_name = tempfile.mkstemp(
+        dir=cfg_path.parent, prefix=f".{cfg_path.name}.", suffix=".tmp"
+    )
+    temp_path = Path(temp_name)
+    try:
+        with os.fdopen(fd, "w") as temp_file:
+            temp_file.write(text)
+        if cfg_path.exists():
+            shutil.copymode(cfg_path, temp_path)
+        temp_path.replace(cfg_path)
+    except OSError as e:
+        temp_path.unlink(missing_ok=True)
+        raise StorageError(f"Failed to write {cfg_path}: {e}") from e
+    return {
+        "saved_to": str(cfg_path),
+        "restart_required": [
+            section
+            for section in RESTART_CONFIG_SECTIONS
+            if old_cfg.get(section) != new_cfg.get(section)
+        ],
+    }
+
+
+DEFAULT_KEYBINDINGS = {
+    "save": "Ctrl+Enter",
+    "toggle_help": "F1",
+    "reset": "Escape",
+    "toggle_text": "Ctrl+1",
+    "toggle_clipboard": "Ctrl+2",
+    "toggle_screenshot": "Ctrl+3",
+    "toggle_audio": "Ctrl+4",
+    "toggle_system_audio": "Ctrl+5",
+}
+KEY_MODIFIERS = {
+    "ctrl": "Ctrl",
+    "control": "Ctrl",
+    "alt": "Alt",
+    "option": "Alt",
+    "shift": "Shift",
+    "meta": "Meta",
+    "cmd": "Meta",
+    "super": "Meta",
+}
+KEY_ALIASES = {"esc": "Escape", "return": "Enter", "del": "Delete", "space": "Space"}
+
+
+def _normalize_key(binding: Any) -> str:
+    """Canonical form of a binding like "ctrl+shift+s" -> "Ctrl+Shift+S"."""
+    if not isinstance(binding, str) or not binding.strip():
+        raise ValueError("binding must be a non-empty string")
+    parts = [p.strip() for p in binding.split("+")]
+    *mods, key = parts
+    modifiers = set()
+    for mod in mods:
+        if mod.lower() not in KEY_MODIFIERS:
+            raise ValueError(f"unknown modifier {mod!r}")
+        modifiers.add(KEY_MODIFIERS[mod.lower()])
+    if not key or key.lower() in KEY_MODIFIERS:
+        raise ValueError("binding has no key")
+    key = KEY_ALIASES.get(key.lower(), key)
+    key = key.upper() if len(key) == 1 else key[0].upper() + key[1:]
+    order = ["Ctrl", "Alt", "Shift", "Meta"]
+    return "+".join([m for m in order if m in modifiers] + [key])
+
+
+def _parse_keybindings(raw: Any) -> Dict[str, Any]:
+    """Merge `keybindings` from config over the defaults.
+
+    Bad entries are reported in `warnings` and leave the default in place, so a
+    typo never stops the config from loading.
+    """
+    bindings = dict(DEFAULT_KEYBINDINGS)
+    warnings = []
+    if raw and not isinstance(raw, dict):
+        warnings.append("keybindings must be a mapping of action to key")
+        raw = {}
+    for action, binding in (raw or {}).items():
+        if action not in DEFAULT_KEYBINDINGS:
+            warnings.append(f"Unknown keybinding action {action!r}")
+            continue
+        try:
+            bindings[action] = _normalize_key(binding)
+        except ValueError as e:
+            warnings.append(f"Invalid keybinding for {action}: {e}")
+
+    by_key: Dict[str, List[str]] = {}
+    for action, key in bindings.items():
+        by_key.setdefault(key, []).append(action)
+    conflicts = [
+        {"key": key, "actions": actions}
+        for key, actions in by_key.items()
+        if len(actions) > 1
+    ]
+    for conflict in conflicts:
+        warnings.append(
+            f"{conflict['key']} is bound to {', '.join(conflict['actions'])}"
+        )
+    return {"bindings": bindings, "conflicts": conflicts, "warnings": warnings}
+
+
+@app.get("/api/keybindings")
+def api_keybindings():
+    return _parse_keybindings(load_config(_config_path).get("keybindings"))
+
+
+def _ollama_health(host: str, port: int) -> bool:
+    try:
+        parsed_host = host.replace("http://", "").replace("https://", "")
+        if ":" in parsed_host:
+            parsed_host = parsed_host.split(":")[0]
+        conn = http.client.HTTPConnection(parsed_host, port=port, timeout=3)
+        conn.request("GET", "/api/version")
+        res = conn.getresponse()
+        ok = res.status == 200
+        conn.close()
+        return ok
+    except Exception:
+        return False
+
+
+CLIPBOARD_UTF8_POLICIES = ("lossy", "reject", "base64")
+
+
+def _decode_clipboard(raw: bytes, policy: str) -> Dict[str, str]:
+    """Clipboard bytes as {"content", "type"} under capture.clipboard_utf8_policy.
+
+    Valid UTF-8 is always returned as text. Otherwise "lossy" substitutes U+FFFD,
+    "reject" raises and "base64" returns the bytes in a fenced base64 block.
+    """
+    try:
+        return {"content": raw.decode("utf-8"), "type": "text"}
+    except UnicodeDecodeError as e:
+        if policy == "reject":
+            raise ValidationError(f"Clipboard is not valid UTF-8: {e}") from e
+        if policy == "base64":
+            encoded = base64.b64encode(raw).decode("ascii")
+            return {"content": f"```base64\n{encoded}\n```", "type": "base64"}
+        return {"content": raw.decode("utf-8", errors="replace"), "type": "text"}
+
+
+# Seconds before an external command is killed, overridable in capture config
+DEFAULT_COMMAND_TIMEOUTS = {"clipboard": 2.0, "screenshot": 60.0}
+
+
+def _command_timeout(cfg, kind: str) -> float:
+    value = cfg["capture"].get(f"{kind}_timeout_seconds")
+    return float(value) if value else DEFAULT_COMMAND_TIMEOUTS[kind]
+
+
+def _run_command(command: List[str], timeout: float, **kwargs):
+    """subprocess.run that kills the command after `timeout` seconds and raises
+    CommandTimeoutError, so a hung compositor can't hold a worker thread."""
+    try:
+        return subprocess.run(command, timeout=timeout, **kwargs)
+    except subprocess.TimeoutExpired:
+        raise CommandTimeoutError(f"{command[0]} timed out after {timeout:g}s")
+
+
+# Tried in this order unless capture.clipboard_tools / capture.screenshot_tools
+# pick others. Screenshot commands write to {path}, or to stdout without one
+CLIPBOARD_COMMANDS = {
+    "wl-paste": ["wl-paste", "-t", "text"],
+    "xclip": ["xclip", "-selection", "clipboard", "-o"],
+    "xsel": ["xsel", "--clipboard", "--output"],
+    "pbpaste": ["pbpaste"],
+}
+SCREENSHOT_COMMANDS = {
+    "grimblast": ["grimblast", "--notify", "--freeze", "save", "area", "-"],
+    "spectacle": ["spectacle", "-b", "-n", "-r", "-o", "{path}"],
+    "maim": ["maim", "-s", "{path}"],
+    "import": ["import", "{path}"],
+    "screencapture": ["screencapture", "-i", "{path}"],
+}
+
+
+def _capture_tools(cfg, kind: str, commands: Dict[str, List[str]]) -> List[str]:
+    """capture.<kind>_tools, or every known tool in order; unknown names are
+    rejected."""
+    tools = cfg["capture"].get(f"{kind}_tools") or list(commands)
+    if isinstance(tools, str):
+        tools = [tools]
+    unknown = [tool for tool in tools if tool not in commands]
+    if unknown:
+        raise ValidationError(
+            f"Unknown capture.{kind}_tools {', '.join(map(str, unknown))}; "
+            f"expected any of {', '.join(commands)}"
+        )
+    return list(tools)
+
+
+@app.get("/api/clipboard")
+def api_clipboard():
+    """Get current clipboard content from the first clipboard tool that works.
+
+    If none of the tools is installed the response carries an `error` naming
+    them, so a missing tool isn't mistaken for an empty clipboard.
+    """
+    cfg = normalize_config(load_config(_config_path))
+    policy = cfg["capture"].get("clipboard_utf8_policy") or "lossy"
+    if policy not in CLIPBOARD_UTF8_POLICIES:
+        raise ValidationError(
+            f"Unknown capture.clipboard_utf8_policy {policy!r}; "
+            f"expected one of {', '.join(CLIPBOARD_UTF8_POLICIES)}"
+        )
+    tools = _capture_tools(cfg, "clipboard", CLIPBOARD_COMMANDS)
+    found = False
+    for tool in tools:
+        try:
+            result = _run_command(
+                CLIPBOARD_COMMANDS[tool],
+                _command_timeout(cfg, "clipboard"),
+                capture_output=True,
+            )
+        except OSError:
+            continue
+        found = True
+        # e.g. wl-paste outside Wayland; an X11 or macOS tool may still work
+        if result.returncode == 0:
+            return _decode_clipboard(result.stdout, policy)
+    if not found:
+        return {
+            "content": "",
+            "type": "text",
+            "error": f"No clipboard tool found; tried {', '.join(tools)}",
+        }
+    return {"content": "", "type": "text"}
+
+
+def _region_screenshot(
+    cfg, screenshot_path: Path, timeout: float
+) -> Tuple[Optional[int], List[str]]:
+    """Select a region with each installed screenshot tool in turn until one
+    succeeds. Returns the last exit code (None if none is installed) and the
+    tools that ran."""
+    returncode, tried = None, []
+    for tool in _capture_tools(cfg, "screenshot", SCREENSHOT_COMMANDS):
+        template = SCREENSHOT_COMMANDS[tool]
+        command = [arg.format(path=screenshot_path) for arg in template]
+        try:
+            if "{path}" in template:
+                result = _run_command(command, timeout, capture_output=True)
+            else:
+                with open(screenshot_path, "wb") as out:
+                    result = _run_command(
+                        command, timeout, stdout=out, stderr=subprocess.PIPE
+                    )
+        except OSError:
+            screenshot_path.unlink(missing_ok=True)
+            continue
+        except CommandTimeoutError:
+            screenshot_path.unlink(missing_ok=True)
+            raise
+        returncode = result.returncode
+        tried.append(tool)
+        if returncode == 0:
+            break
+        # e.g. grimblast outside Wayland; a later tool may still work
+        screenshot_path.unlink(missing_ok=True)
+    return returncode, tried
+
+
+@app.post("/api/screenshot")
+def api_screenshot(output: str = "", mode: str = "region"):
+    """Trigger screenshot capture.
+
+    By default an area is selected interactively with the first tool of
+    capture.screenshot_tools that succeeds; `output` captures a whole monitor by
+    name (see /api/outputs) and mode=window captures the focused window with grim,
+    falling back to region selection. The mode used is returned.
+    """
+    try:
+        timestamp = datetime.now().strftime("%Y%m%d_%H%M%S_%f")[:-3]
+        cfg = normalize_config(load_config(_config_path))
+        media_dir = Path(cfg["vault"]["path"]).expanduser() / cfg["vault"]["media_dir"]
+        media_dir.mkdir(parents=True, exist_ok=True)
+        screenshot_path = media_dir / f"{timestamp}_screenshot.png"
+
+        used_mode = "region"
+        command = None
+        if output.strip():
+            used_mode = "output"
+            command = ["grim", "-o", output.strip(), str(screenshot_path)]
+        elif mode == "window":
+            geometry = _active_window_geometry()
+            if geometry:
+                used_mode = "window"
+                command = ["grim", "-g", geometry, str(screenshot_path)]
+
+        timeout = _command_timeout(cfg, "screenshot")
+        error = "Screenshot failed"
+        if command:
+            result = _run_command(command, timeout, capture_output=True).returncode
+        else:
+            result, tried = _region_screenshot(cfg, screenshot_path, timeout)
+            error = f"Screenshot failed; tried {', '.join(tried)}"
+            if result is None:
+                metrics.inc("kms_screenshots_total", {"result": "failure"})
+                tools = _capture_tools(cfg, "screenshot", SCREENSHOT_COMMANDS)
+                return {
+                    "success": False,
+                    "error": f"No screenshot tool found; tried {', '.join(tools)}",
+                    "mode": used_mode,
+                }
+
+        if result == 0:
+            metrics.inc("kms_screenshots_total", {"result": "success"})
+            dimensions = _image_dimensions(screenshot_path)
+            screenshot_path, compression = _compress_screenshot(
+                screenshot_path, cfg["capture"]
+            )
+            response = {
+                "path": str(screenshot_path),
+                "success": True,
+                "mode": used_mode,
+                "width": dimensions[0] if dimensions else None,
+                "height": dimensions[1] if dimensions else None,
+                "output": output.strip() or None,
+            }
+            if compression:
+                response["compression"] = compression
+            return response
+        metrics.inc("kms_screenshots_total", {"result": "failure"})
+        return {"success": False, "error": error, "mode": used_mode}
+    except CommandTimeoutError:
+        metrics.inc("kms_screenshots_total", {"result": "timeout"})
+        raise
+    except Exception as e:
+        metrics.inc("kms_screenshots_total", {"result": "failure"})
+        return {"success": False, "error": str(e)}
+
+
+def _image_dimensions(path: Path) -> Optional[Tuple[int, int]]:
+    """(width, height) read from a PNG or WebP header, or None."""
+    try:
+        with open(path, "rb") as f:
+            header = f.read(30)
+    except OSError:
+        return None
+    if header.startswith(b"\x89PNG\r\n\x1a\n") and header[12:16] == b"IHDR":
+        return (
+            int.from_bytes(header[16:20], "big"),
+            int.from_bytes(header[20:24], "big"),
+        )
+    if header.startswith(b"RIFF") and header[8:12] == b"WEBP":
+        chunk = header[12:16]
+        if chunk == b"VP8X":
+            return (
+                int.from_bytes(header[24:27], "little") + 1,
+                int.from_bytes(header[27:30], "little") + 1,
+            )
+        if chunk == b"VP8L":
+            bits = int.from_bytes(header[21:25], "little")
+            return (bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1
+        if chunk == b"VP8 ":
+            return (
+                int.from_bytes(header[26:28], "little") & 0x3FFF,
+                int.from_bytes(header[28:30], "little") & 0x3FFF,
+            )
+    return None
+
+
+SCREENSHOT_COMPRESSIONS = {"oxipng": "oxipng", "webp": "cwebp"}
+
+
+def _compress_screenshot(
+    path: Path, capture_cfg: Dict[str, Any]
+) -> Tuple[Path, Optional[Dict[str, Any]]]:
+    """Shrink a screenshot per capture.screenshot_compression ("oxipng" or
+    "webp"). Returns the path to use (a .webp replaces the .png) and a size
+    report; if the tool is missing or fails the original is kept untouched."""
+    method = capture_cfg.get("screenshot_compression") or "none"
+    if method == "none":
+        return path, None
+    if method not in SCREENSHOT_COMPRESSIONS:
+        return path, {"method": method, "skipped": "unknown compression method"}
+    tool = SCREENSHOT_COMPRESSIONS[method]
+    if not shutil.which(tool):
+        return path, {"method": method, "skipped": f"{tool} is not installed"}
+
+    original_bytes = path.stat().st_size
+    if method == "webp":
+        quality = int(capture_cfg.get("screenshot_webp_quality", 80))
+        target = path.with_suffix(".webp")
+        command = [tool, "-quiet", "-q", str(quality), str(path), "-o", str(target)]
+    else:
+        target = path
+        command = [tool, "-q", "-o", "2", "--strip", "safe", str(path)]
+    try:
+        result = subprocess.run(command, capture_output=True, timeout=60)
+        error = (result.stderr or b"").decode(errors="replace").strip()
+        ok = result.returncode == 0 and target.exists()
+    except (OSError, subprocess.TimeoutExpired) as e:
+        ok, error = False, str(e)
+    if not ok:
+        if target != path:
+            target.unlink(missing_ok=True)
+        print(f"⚠️  {tool} failed, keeping original screenshot: {error}")
+        return path, {"method": method, "skipped": f"{tool} failed"}
+
+    if target != path:
+        path.unlink(missing_ok=True)
+    return target, {
+        "method": method,
+        "original_bytes": original_bytes,
+        "compressed_bytes": target.stat().st_size,
+    }
+
+
+def _active_window_geometry() -> Optional[str]:
+    """grim -g geometry ("x,y wxh") of the focused window, from sway or Hyprland."""
+
+    def from_sway(tree):
+        rect = (_find_focused_node(tree) or {}).get("rect") or {}
+        return rect.get("x"), rect.get("y"), rect.get("width"), rect.get("height")
+
+    def from_hyprland(window):
+        return (*window["at"], *window["size"])
+
+    for command, parse in (
+        (["swaymsg", "-t", "get_tree", "-r"], from_sway),
+        (["hyprctl", "activewindow", "-j"], from_hyprland),
+    ):
+        try:
+            result = subprocess.run(command, capture_output=True, text=True, timeout=2)
+            if result.returncode != 0:
+                continue
+            x, y, width, height = parse(json.loads(result.stdout))
+        except Exception:
+            continue
+        if width and height:
+            return f"{x},{y} {width}x{height}"
+    return None
+
+
+def _list_outputs() -> List[Dict[str, Any]]:
+    """Ask the compositor (sway, then Hyprland) for its connected outputs."""
+    commands = (["swaymsg", "-t", "get_outputs", "-r"], ["hyprctl", "monitors", "-j"])
+    for command in commands:
+        try:
+            result = subprocess.run(command, capture_output=True, text=True, timeout=2)
+            if result.returncode != 0:
+                continue
+            outputs = json.loads(result.stdout)
+        except Exception:
+            continue
+        return [
+            {
+                "name": o.get("name"),
+                "description": o.get("description")
+                or " ".join(p for p in (o.get("make"), o.get("model")) if p),
+                "focused": bool(o.get("focused")),
+            }
+            for o in outputs
+            if o.get("name") and o.get("active", True)
+        ]
+    return []
+
+
+@app.get("/api/outputs")
+def api_outputs():
+    return {"outputs": _list_outputs()}
+
+
+# Tools behind screenshots/clipboard
+DIAGNOSTIC_TOOLS = {
+    "grim": "wayland screenshots",
+    "grimblast": "wayland region screenshots",
+    "slurp": "wayland region selection",
+    "wl-paste": "wayland clipboard",
+    "swaymsg": "sway outputs/windows",
+    "hyprctl": "Hyprland outputs/windows",
+    "xclip": "x11 clipboard",
+    "xsel": "x11 clipboard",
+    "spectacle": "KDE screenshots",
+    "maim": "x11 screenshots",
+    "import": "x11 screenshots (ImageMagick)",
+    "pbpaste": "macOS clipboard",
+    "screencapture": "macOS screenshots",
+    "oxipng": "screenshot compression",
+    "cwebp": "screenshot compression (webp)",
+}
+
+
+@app.get("/api/diagnostics")
+def api_diagnostics():
+    """Which screenshot/clipboard tools are installed and whether the compositor
+    answers, so setup problems show up as a report instead of failed captures.
+
+    Screenshots and the clipboard are ready when any of capture.screenshot_tools
+    or capture.clipboard_tools is installed, and the first installed one is
+    reported as the tool that would be used.
+    """
+    cfg = normalize_config(load_config(_config_path))
+    tools = {}
+    for name, purpose in DIAGNOSTIC_TOOLS.items():
+        path = shutil.which(name)
+        tools[name] = {"installed": path is not None, "path": path, "purpose": purpose}
+    screenshot_tool = _first_installed_tool(cfg, "screenshot", SCREENSHOT_COMMANDS)
+    clipboard_tool = _first_installed_tool(cfg, "clipboard", CLIPBOARD_COMMANDS)
+    outputs = _list_outputs()
+    return {
+        "tools": tools,
+        "session": {
+            "wayland_display": os.environ.get("WAYLAND_DISPLAY"),
+            "x11_display": os.environ.get("DISPLAY"),
+        },
+        "compositor": {"responding": bool(outputs), "outputs": len(outputs)},
+        "screenshot_ready": screenshot_tool is not None,
+        "screenshot_tool": screenshot_tool,
+        "clipboard_ready": clipboard_tool is not None,
+        "clipboard_tool": clipboard_tool,
+    }
+
+
+def _first_installed_tool(
+    cfg, kind: str, commands: Dict[str, List[str]]
+) -> Optional[str]:
+    for tool in _capture_tools(cfg, kind, commands):
+        if shutil.which(commands[tool][0]):
+            return tool
+    return None
+
+
+def _find_focused_node(node: Dict[str, Any]) -> Optional[Dict[str, Any]]:
+    if node.get("focused"):
+        return node
+    for child in node.get("nodes", []) + node.get("floating_nodes", []):
+        found = _find_focused_node(child)
+        if found:
+            return found
+    return None
+
+
+def _detect_source_app(cfg) -> Optional[str]:
+    """Detect the focused application, or None when disabled or unavailable."""
+    capture_cfg = cfg.get("capture") or {}
+    if not capture_cfg.get("source_app_detection", False):
+        return None
+    try:
+        command = capture_cfg.get("source_app_command")
+        if command:
+            result = subprocess.run(
+                command, shell=True, capture_output=True, text=True, timeout=2
+            )
+            return (result.stdout.strip() or None) if result.returncode == 0 else None
+
+        result = subprocess.run(
+            ["swaymsg", "-t", "get_tree"], capture_output=True, text=True, timeout=2
+        )
+        if result.returncode != 0:
+            return None
+        focused = _find_focused_node(json.loads(result.stdout))
+        if not focused:
+            return None
+        window_props = focused.get("window_properties") or {}
+        return focused.get("app_id") or window_props.get("class") or None
+    except Exception:
+        return None
+
+
+def _parse_capture_timestamp(value: str) -> Optional[datetime]:
+    """Parse an RFC3339 timestamp into UTC, or None if it is empty or invalid."""
+    value = value.strip()
+    if not value:
+        return None
+    try:
+        parsed = datetime.fromisoformat(value.replace("Z", "+00:00"))
+    except ValueError:
+        return None
+    if parsed.tzinfo is None:
+        return None
+    return parsed.astimezone(timezone.utc)
+
+
+HEX_COLOR_RE = re.compile(r"#(?:[0-9a-fA-F]{3}|[0-9a-fA-F]{6})")
+
+
+def _validate_color(color: Optional[str]) -> Optional[str]:
+    """A capture display colour: None/empty, or a #rgb / #rrggbb hex string."""
+    color = (color or "").strip()
+    if not color:
+        return None
+    if not HEX_COLOR_RE.fullmatch(color):
+        raise ValidationError(f"Invalid color {color!r}; expected #rgb or #rrggbb")
+    return color.lower()
+
+
+def _dedup_values(values: List[str], case_insensitive: bool = True) -> List[str]:
+    """Drop blank and repeated entries, keeping the first spelling of each."""
+    seen = set()
+    result = []
+    for value in values:
+        value = value.strip()
+        key = value.casefold() if case_insensitive else value
+        if value and key not in seen:
+            seen.add(key)
+            result.append(value)
+    return result
+
+
+def _validate_status(status: Optional[str]) -> str:
+    """A processing_status from PROCESSING_STATUSES; empty means "raw"."""
+    status = (status or "").strip() or "raw"
+    if status not in PROCESSING_STATUSES:
+        raise ValidationError(
+            f"Invalid processing_status {status!r}; "
+            f"expected one of {', '.join(PROCESSING_STATUSES)}"
+        )
+    return status
+
+
+CONTENT_NORMALIZATIONS = ("none", "trim", "strip_trailing_ws")
+
+
+def _normalize_content(content: str, mode: str) -> str:
+    """Tidy pasted content per capture.normalize_content.
+
+    "trim" drops blank lines at the start and end; "strip_trailing_ws" also
+    strips trailing whitespace from every line. Leading indentation is kept.
+    """
+    if mode not in CONTENT_NORMALIZATIONS:
+        raise ValidationError(
+            f"Unknown capture.normalize_content {mode!r}; "
+            f"expected one of {', '.join(CONTENT_NORMALIZATIONS)}"
+        )
+    if mode == "none":
+        return content
+    lines = content.splitlines()
+    if mode == "strip_trailing_ws":
+        lines = [line.rstrip() for line in lines]
+    while lines and not lines[0].strip():
+        lines.pop(0)
+    while lines and not lines[-1].strip():
+        lines.pop()
+    return "\n".join(lines)
+
+
+DATA_URI_IMAGE_RE = re.compile(
+    r"!\[([^\]]*)\]\(data:image/([\w.+-]+);base64,([A-Za-z0-9+/=\s]+)\)"
+)
+# Inline image types that are saved, by extension. SVG is left inline: served
+# from the vault it could run scripts.
+DATA_URI_EXTENSIONS = {"png": "png", "jpeg": "jpg", "gif": "gif", "webp": "webp"}
+
+
+def _extract_inline_images(
+    content: str, media_dir: Path, writer: SafeMarkdownWriter
+) -> Tuple[str, List[Dict[str, Any]], List[Tuple[Path, bytes]]]:
+    """Decode `![alt](data:image/...;base64,...)` images bound for media_dir.
+
+    Returns the content with each data URI replaced by a link to its file, media
+    entries for them, and the (path, bytes) to write once the capture is known
+    to be valid (see _write_inline_images). Undecodable images and other image
+    types are left untouched.
+    """
+    files_meta = []
+    images = []
+    stamp = datetime.now().strftime("%Y%m%d_%H%M%S_%f")
+
+    def save(match):
+        alt, subtype, data = match.groups()
+        ext = DATA_URI_EXTENSIONS.get(subtype.lower())
+        if not ext:
+            return match.group(0)
+        try:
+            raw = base64.b64decode("".join(data.split()), validate=True)
+        except ValueError:
+            return match.group(0)
+        dest = media_dir / f"{stamp}_inline_{len(files_meta) + 1}.{ext}"
+        images.append((dest, raw))
+        files_meta.append(
+            {
+                "path": str(dest),
+                "name": dest.name,
+                "content_type": f"image/{subtype}",
+                "type": "image",
+                "inline": True,
+            }
+        )
+        link_path = writer.get_relative_media_path(str(dest))
+        return writer.media_link(link_path, alt or "Image", embed=True)
+
+    return DATA_URI_IMAGE_RE.sub(save, content), files_meta, images
+
+
+def _write_inline_images(images: List[Tuple[Path, bytes]]):
+    for dest, raw in images:
+        try:
+            dest.parent.mkdir(parents=True, exist_ok=True)
+            dest.write_bytes(raw)
+        except OSError as e:
+            raise StorageError(f"Failed to save inline image {dest.name}: {e}") from e
+
+
+def _validate_one_to_five(value: Any, field: str) -> Optional[int]:
+    """An integer field from 1 to 5; empty/None means unset."""
+    if value is None or str(value).strip() == "":
+        return None
+    try:
+        number = int(str(value).strip())
+    except ValueError:
+        number = 0
+    if not 1 <= number <= 5:
+        raise ValidationError(f"Invalid {field} {value!r}; expected 1-5")
+    return number
+
+
+def _validate_priority(priority: Any) -> Optional[int]:
+    """A capture priority from 1 (highest) to 5."""
+    return _validate_one_to_five(priority, "priority")
+
+
+def _validate_rating(rating: Any) -> Optional[int]:
+    """A journal/mood rating from 1 (worst) to 5."""
+    return _validate_one_to_five(rating, "rating")
+
+
+def _validate_importance(importance: Any) -> Optional[float]:
+    """A capture importance as a finite number; empty/None means unset."""
+    if importance is None or str(importance).strip() == "":
+        return None
+    try:
+        number = float(str(importance).strip())
+    except ValueError:
+        number = math.nan
+    if not math.isfinite(number):
+        raise ValidationError(f"Invalid importance {importance!r}; expected a number")
+    return number
+
+
+def _validate_collection(collection: Any) -> Optional[str]:
+    """A collection (notebook) name; each capture is in at most one. Empty/None
+    means none."""
+    name = str(collection or "").strip()
+    if "\n" in name:
+        raise ValidationError("Collection names must be a single line")
+    return name or None
+
+
+def _strip_source_params(cfg, sources: List[str]) -> Tuple[List[str], Dict[str, str]]:
+    """URL sources without the query parameters in sources.strip_params (true
+    for DEFAULT_STRIP_PARAMS, or a list of globs), plus {cleaned: original}."""
+    patterns = (cfg.get("sources") or {}).get("strip_params") or []
+    if patterns is True:
+        patterns = DEFAULT_STRIP_PARAMS
+    cleaned, originals = [], {}
+    for source in sources:
+        stripped = strip_query_params(source, patterns) if patterns else source
+        if stripped != source:
+            originals[stripped] = source
+        cleaned.append(stripped)
+    return cleaned, originals
+
+
+def _parse_fields(fields: Any) -> Dict[str, Any]:
+    """Custom fields from a JSON object (a string in form posts)."""
+    if isinstance(fields, str):
+        if not fields.strip():
+            return {}
+        try:
+            fields = json.loads(fields)
+        except json.JSONDecodeError as e:
+            raise ValidationError(f"fields must be a JSON object: {e}") from e
+    return validate_fields(fields)
+
+
+def _validate_expires_at(expires_at: Any) -> Optional[str]:
+    """An RFC 3339 time or date as a UTC ISO timestamp; empty/None means none."""
+    if expires_at is None or str(expires_at).strip() == "":
+        return None
+    parsed = expiry_timestamp(str(expires_at))
+    if parsed is None:
+        raise ValidationError(f"Invalid expires_at {expires_at!r}")
+    return parsed
+
+
+def _parse_blocks(blocks: str) -> List[Dict[str, str]]:
+    """Parse the `blocks` form field: a JSON array of {heading, body} objects."""
+    if not blocks.strip():
+        return []
+    try:
+        parsed = json.loads(blocks)
+    except json.JSONDecodeError as e:
+        raise ValidationError(f"Invalid blocks: {e}") from e
+    if not isinstance(parsed, list):
+        raise ValidationError("Invalid blocks: must be a JSON array")
+    result = []
+    for block in parsed:
+        if not isinstance(block, dict):
+            raise ValidationError(
+                "Invalid blocks: each block must be an object with heading and body"
+            )
+        heading = str(block.get("heading") or "").strip()
+        body = str(block.get("body") or "")
+        if not body.strip() and not heading:
+            continue
+        if not heading:
+            # An unnamed block would be a second "## Content" section
+            raise ValidationError("Invalid blocks: each block needs a heading")
+        result.append({"heading": heading, "body": body})
+    return result
+
+
+def _recovery_dir() -> Path:
+    return _data_dir() / "recovery"
+
+
+def _save_recovery(capture: Dict[str, Any], error: Exception, cfg) -> Optional[str]:
+    """Persist a capture that failed to save so it can be resubmitted later."""
+    if not (cfg.get("capture") or {}).get("recovery_enabled", True):
+        return None
+    try:
+        recovery_dir = _recovery_dir()
+        recovery_dir.mkdir(parents=True, exist_ok=True)
+        recovery_id = datetime.now(timezone.utc).strftime("%Y%m%dT%H%M%S%f")
+        payload = {
+            "id": recovery_id,
+            "failed_at": datetime.now(timezone.utc).isoformat(),
+            "error": str(error),
+            "attempts": 1,
+            "capture": {
+                **capture,
+                "timestamp": capture["timestamp"].isoformat(),
+            },
+        }
+        (recovery_dir / f"{recovery_id}.json").write_text(
+            json.dumps(payload, indent=2), encoding="utf-8"
+        )
+        return recovery_id
+    except Exception as e:
+        print(f"Failed to write recovery file: {e}")
+        return None
+
+
+def _new_capture(cfg, ts: Optional[datetime] = None, **fields) -> Dict[str, Any]:
+    """Build a capture dict with defaults for every field not given."""
+    ts = ts or datetime.now(timezone.utc)
+    capture = {
+        "timestamp": ts,
+        "content": "",
+        "blocks": [],
+        "clipboard": "",
+        "context": "",
+        "tags": [],
+        "modalities": ["text"],
+        "sources": [],
+        "media_files": [],
+        "created_date": _format_date(cfg, ts),
+        "last_edited_date": _format_date(cfg, ts),
+        "capture_id": ts.isoformat(),
+        "aliases": [],
+    }
+    capture.update(fields)
+    if "location" not in capture:
+        capture["location"] = get_location(cfg.get("location"))
+    if "source_app" not in capture:
+        capture["source_app"] = _detect_source_app(cfg)
+    return capture
+
+
+def _save_failed_response(capture: Dict[str, Any], error: Exception, cfg):
+    metrics.inc("kms_capture_errors_total")
+    error_body = {"error": f"Save failed: {str(error)}"}
+    recovery_id = _save_recovery(capture, error, cfg)
+    if recovery_id:
+        error_body["recovery_id"] = recovery_id
+    status = error.status_code if isinstance(error, KmsError) else 500
+    return JSONResponse(error_body, status_code=status)
+
+
+def _written_sections(writer: SafeMarkdownWriter, idea_file: Path) -> List[str]:
+    """Section headings actually present in a written capture file."""
+    _, body = writer.split_frontmatter(idea_file.read_text(encoding="utf-8"))
+    return section_headings(body)
+
+
+# capture.duplicate_ids: what a capture sent with the id of an existing one
+# does. "replace" (the default) edits it, overwriting the old file and row,
+# "suffix" stores it as id_N and "reject" fails with a 409. Generated ids that
+# collide always get a suffix.
+DUPLICATE_ID_MODES = ("suffix", "replace", "reject")
+# The duplicate-id check and the write it decides on must not interleave with
+# another capture's
+_persist_lock = threading.Lock()
+
+
+def _persist_capture(
+    capture: Dict[str, Any],
+    cfg,
+    force: bool = False,
+    explicit_id: bool = False,
+    images: List[Tuple[Path, bytes]] = (),
+) -> Path:
+    """Write the capture markdown file and record it in the database, then copy
+    the file and its media to any vault.mirror_paths (see capture["mirrored_to"]).
+
+    `explicit_id` is set when the client chose the capture_id, which makes an
+    existing capture with that id subject to capture.duplicate_ids. Replacing a
+    locked capture needs `force`, as for the edit endpoints. `images` are the
+    decoded inline images, written only once the capture is accepted.
+    """
+    writer = _get_writer(cfg)
+    db = get_main_db(cfg)
+    mode = cfg["capture"].get("duplicate_ids") or "replace"
+    if mode not in DUPLICATE_ID_MODES:
+        raise ValidationError(
+            f"Unknown capture.duplicate_ids {mode!r}; "
+            f"expected one of {', '.join(DUPLICATE_ID_MODES)}"
+        )
+    if not explicit_id:
+        mode = "suffix"
+    with _persist_lock:
+        target = None
+        capture_id = capture["capture_id"]
+        stored = db.get_capture_file_path(capture_id)
+        existing = Path(stored) if stored else None
+        if existing is None and mode != "suffix":
+            # Only an edit needs a file the database doesn't know about; with a
+            # custom filename_pattern this reads every capture file
+            existing = writer.find_capture_file(capture_id)
+        exists = db.capture_exists(capture_id)
+        if exists or (mode != "suffix" and existing.exists()):
+            if mode == "reject":
+                raise ConflictError(f"Capture {capture_id} already exists")
+            if mode == "replace":
+                # An edit of the capture: it keeps its file and created_date
+                on_disk = existing is not None and existing.exists()
+                previous = writer.read_capture(existing) if on_disk else {}
+                if exists:
+                    _require_capture(db, capture_id, force)
+                elif previous.get("locked") and not force:
+                    raise ConflictError(
+                        f"Capture {capture_id} is locked; pass force=true"
+                    )
+                target = existing
+                if previous.get("created_date"):
+                    capture["created_date"] = previous["created_date"]
+                capture["last_edited_date"] = _format_date(
+                    cfg, datetime.now(timezone.utc)
+                )
+            else:
+                capture["capture_id"] = db.available_capture_id(capture_id)
+        _write_inline_images(images)
+        p = writer.write_capture(capture, target)
+        capture["file_path"] = str(p)
+        _store_capture(db, writer, capture)
+    metrics.inc("kms_captures_written_total")
+    capture["sections"] = _written_sections(writer, p)
+    if cfg["vault"]["mirror_paths"]:
+        media = [Path(m["path"]) for m in capture.get("media_files") or []]
+        capture["mirrored_to"] = writer.mirror_files(
+            [p, *[m for m in media if m.exists()]]
+        )
+    return p
+
+
+AUDIO_EXTENSIONS = {".wav", ".mp3", ".ogg", ".m4a", ".flac", ".webm", ".opus"}
+
+
+def _is_audio_upload(name: str, content_type: Optional[str]) -> bool:
+    return (content_type or "").startswith("audio/") or (
+        Path(name).suffix.lower() in AUDIO_EXTENSIONS
+    )
+
+
+def _run_post_save_hook(command: str, file_path: Path, capture_id: str):
+    """Run capture.post_save_hook in the background; failures are only logged."""
+    env = {
+        **os.environ,
+        "KMS_CAPTURE_PATH": str(file_path),
+        "KMS_CAPTURE_ID": capture_id,
+    }
+
+    def run():
+        try:
+            result = subprocess.run(
+                command,
+                shell=True,
+                env=env,
+                capture_output=True,
+                text=True,
+                timeout=300,
+            )
+            output = (result.stdout + result.stderr).strip()
+            if result.returncode != 0:
+                print(f"⚠️  post_save_hook exited {result.returncode}: {output}")
+            elif output:
+                print(f"post_save_hook: {output}")
+        except Exception as e:
+            print(f"⚠️  post_save_hook failed: {e}")
+
+    threading.Thread(target=run, daemon=True).start()
+
+
+def _infer_modalities(
+    content: str, clipboard: str, blocks: List[Dict[str, str]], files_meta
+) -> List[str]:
+    """Infer modalities from what was actually submitted with a capture."""
+    inferred = []
+    if content.strip() or blocks:
+        inferred.append("text")
+    if clipboard.strip():
+        inferred.append("clipboard")
+    for media_file in files_meta:
+        media_type = media_file.get("type", "")
+        if media_type in ("screenshot", "audio") and media_type not in inferred:
+            inferred.append(media_type)
+    return inferred
+
+
+def _validate_modalities_have_content(capture_data, modalities):
+    """Validate that selected modalities have actual content."""
+    if not modalities:
+        return False
+
+    for modality in modalities:
+        if modality == "text":
+            content = capture_data.get("content", "").strip()
+            if not content and not capture_data.get("blocks"):
+                return False
+        elif modality == "clipboard":
+            pass
+        elif modality == "screenshot":
+            if not capture_data.get("media_files"):
+                return False
+        elif modality == "audio":
+            if not capture_data.get("media_files"):
+                return False
+        elif modality == "system-audio":
+            if not capture_data.get("media_files"):
+                return False
+
+    return True
+
+
+def _audio_media(cfg: Dict[str, Any], audio_paths: str) -> List[Dict[str, Any]]:
+    """media_files entries for recordings saved by /api/audio/stop, given as a
+    comma-separated list of their file paths."""
+    paths = _clean_values(audio_paths)
+    if not paths:
+        return []
+    media_dir = (
+        Path(cfg["vault"]["path"]).expanduser() / cfg["vault"]["media_dir"]
+    ).resolve()
+    entries = []
+    for raw in paths:
+        path = Path(raw).expanduser().resolve()
+        if media_dir not in path.parents or not path.is_file():
+            raise ValidationError(f"No recording at {raw!r} in the media folder")
+        entries.append({"path": str(path), "type": "audio", "name": path.name})
+    return entries
+
+
+@app.post("/api/capture")
+async def api_capture(
+    request: Request,
+    content: str = Form(""),
+    context: str = Form(""),
+    tags: str = Form(""),
+    sources: str = Form(""),
+    alias: str = Form(""),
+    aliases: str = Form(""),
+    capture_id: str = Form(""),
+    modalities: str = Form(""),
+    clipboard: str = Form(""),
+    clipboard_lang: str = Form(""),
+    screenshot_path: str = Form(""),
+    screenshot_type: str = Form(""),
+    screenshot_output: str = Form(""),
+    audio_paths: str = Form(""),
+    blocks: str = Form(""),
+    timestamp: str = Form(""),
+    draft_id: str = Form(""),
+    skip_hook: bool = Form(False),
+    force: bool = Form(False),
+    color: str = Form(""),
+    processing_status: str = Form("raw"),
+    parent: str = Form(""),
+    priority: str = Form(""),
+    rating: str = Form(""),
+    importance: str = Form(""),
+    collection: str = Form(""),
+    expires_at: str = Form(""),
+    fields: str = Form(""),
+    created_date: Optional[str] = Form(None),
+    last_edited_date: Optional[str] = Form(None),
+    media: Optional[List[UploadFile]] = File(None),
+):
+    cfg, error = _apply_vault_override(
+        request, normalize_config(load_config(_config_path))
+    )
+    if error:
+        return error
+    warnings = []
+    ts = _parse_capture_timestamp(timestamp)
+    if ts is None:
+        if timestamp.strip():
+            warning = f"Ignoring invalid timestamp {timestamp!r}; using current time"
+            print(f"⚠️  {warning}")
+            warnings.append(warning)
+        ts = datetime.now(timezone.utc)
+    cds = created_date or _format_date(cfg, ts)
+    les = last_edited_date or _format_date(cfg, ts)
+    tag_list = (
+        [t.strip() for t in tags.split(",") if t.strip()]
+        if isinstance(tags, str)
+        else []
+    )
+    src_list = (
+        [s.strip() for s in sources.split(",") if s.strip()]
+        if isinstance(sources, str)
+        else []
+    )
+    mod_list = (
+        [m.strip() for m in modalities.split(",") if m.strip()]
+        if isinstance(modalities, str)
+        else []
+    )
+    ctx = context.strip()
+    if not ctx and cfg["capture"].get("inherit_last_context", False):
+        # A capture without a context continues the previous capture's session
+        last_context = get_main_db(cfg).get_most_recent_values().get("context") or []
+        ctx = last_context[0] if last_context else ""
+    case_insensitive = cfg["capture"].get("dedup_case_insensitive", True)
+    tag_list = _dedup_values(tag_list, case_insensitive)
+    src_list, original_sources = _strip_source_params(cfg, src_list)
+    src_list = _dedup_values(src_list, case_insensitive)
+    if "," in ctx:
+        ctx = ", ".join(_dedup_values(ctx.split(","), case_insensitive))
+    block_list = _parse_blocks(blocks)
+    capture_color = _validate_color(color)
+    status = _validate_status(processing_status)
+    capture_priority = _validate_priority(priority)
+    capture_rating = _validate_rating(rating)
+    capture_importance = _validate_importance(importance)
+    capture_collection = _validate_collection(collection)
+    capture_expires_at = _validate_expires_at(expires_at)
+    capture_fields = _parse_fields(fields)
+    parent = parent.strip()
+    if parent and not get_main_db(cfg).capture_exists(parent):
+        warning = f"Parent capture {parent!r} does not exist"
+        print(f"⚠️  {warning}")
+        warnings.append(warning)
+    elif parent and capture_id.strip():
+        _validate_parent(get_main_db(cfg), capture_id.strip(), parent)
+    files_meta = []
+    if media:
+        media_dir = Path(cfg["vault"]["path"]).expanduser() / cfg["vault"]["media_dir"]
+        media_dir.mkdir(parents=True, exist_ok=True)
+        for f in media:
+            name = f.filename or f"upload_{datetime.now().timestamp()}"
+            dest = media_dir / name
+            b = await f.read()
+            dest.write_bytes(b)
+            media_file = {"path": str(dest), "name": name}
+            if _is_audio_upload(name, f.content_type):
+                media_file["type"] = "audio"
+            files_meta.append(media_file)
+
+    if screenshot_path and screenshot_type:
+        dimensions = _image_dimensions(Path(screenshot_path))
+        files_meta.append(
+            {
+                "path": screenshot_path,
+                "type": screenshot_type,
+                "width": dimensions[0] if dimensions else None,
+                "height": dimensions[1] if dimensions else None,
+                "output": screenshot_output.strip() or None,
+            }
+        )
+
+    files_meta.extend(_audio_media(cfg, audio_paths))
+
+    content = _normalize_content(
+        content or "", cfg["capture"].get("normalize_content") or "none"
+    )
+    inline_images = []
+    if "](data:image/" in content:
+        media_dir = Path(cfg["vault"]["path"]).expanduser() / cfg["vault"]["media_dir"]
+        content, inline_media, inline_images = _extract_inline_images(
+            content, media_dir, _get_writer(cfg)
+        )
+        files_meta.extend(inline_media)
+
+    if (cfg.get("capture") or {}).get("auto_detect_modalities", True):
+        inferred = _infer_modalities(content, clipboard, block_list, files_meta)
+        mod_list = _union(mod_list, inferred)
+
+    # Use provided capture_id if available, otherwise generate a new one using timestamp
+    actual_capture_id = capture_id.strip() if capture_id.strip() else ts.isoformat()
+    
+    # `alias` (one) and `aliases` (comma-separated) are kept alongside the
+    # automatic alias chosen by capture.aliases
+    alias_list = _dedup_values([alias] + aliases.split(","), False)
+    capture = _new_capture(
+        cfg,
+        ts,
+        content=content or "",
+        blocks=block_list,
+        clipboard=clipboard or "",
+        clipboard_lang=clipboard_lang.strip(),
+        context=ctx,
+        tags=tag_list,
+        modalities=mod_list or ["text"],
+        sources=src_list,
+        media_files=files_meta,
+        created_date=cds,
+        last_edited_date=les,
+        capture_id=actual_capture_id,
+        aliases=alias_list,
+        color=capture_color,
+        processing_status=status,
+        parent=parent or None,
+        priority=capture_priority,
+        rating=capture_rating,
+        importance=capture_importance,
+        collection=capture_collection,
+        expires_at=capture_expires_at,
+        fields=capture_fields,
+    )
+    if original_sources and (cfg.get("sources") or {}).get("keep_original_urls"):
+        capture["metadata"] = {"original_sources": original_sources}
+
+    if not _validate_modalities_have_content(capture, mod_list):
+        return JSONResponse(
+            {"error": "No content provided for selected modalities"}, status_code=400
+        )
+
+    try:
+        p = _persist_capture(
+            capture,
+            cfg,
+            force,
+            explicit_id=bool(capture_id.strip()),
+            images=inline_images,
+        )
+    except Exception as e:
+        return _save_failed_response(capture, e, cfg)
+
+    import os
+
+    file_exists = os.path.exists(p) if p else False
+    recovery_id = None
+    if not file_exists:
+        # The write reported success but left no file; keep the input
+        recovery_id = _save_recovery(
+            capture, StorageError(f"{p} missing after write"), cfg
+        )
+
+    if draft_id.strip():
+        get_main_db().delete_draft(draft_id.strip())
+
+    hook = (cfg.get("capture") or {}).get("post_save_hook")
+    if hook and not skip_hook:
+        _run_post_save_hook(hook, p, capture["capture_id"])
+
+    try:
+        # Store the last used tags and sources in the database for persistence
+        # Distinguish between AI-suggested and user-added tags/sources
+        global _ai_suggested_tags, _ai_suggested_sources
+
+        # Find which tags were AI-suggested vs user-added
+        user_tags = [tag for tag in tag_list if tag not in _ai_suggested_tags]
+        user_sources = [
+            source for source in src_list if source not in _ai_suggested_sources
+        ]
+
+        # Store both sets separately
+        get_main_db(cfg).store_last_used_values(
+            {"tags": user_tags, "sources": user_sources},
+            {
+                "tags": [
+                    tag for tag in tag_list if tag in _ai_suggested_tags
+                ],  # Only keep AI tags that were actually used
+                "sources": [
+                    source for source in src_list if source in _ai_suggested_sources
+                ],  # Only keep AI sources that were actually used
+            },
+        )
+
+        # Return a properly formatted JSON response
+        response = {
+            "capture_id": capture["capture_id"],
+            "saved_to": str(p),
+            "verified": file_exists,
+            "sections": capture.get("sections", []),
+            "mirrored_to": capture.get("mirrored_to", []),
+        }
+        if warnings:
+            response["warnings"] = warnings
+        if recovery_id:
+            response["recovery_id"] = recovery_id
+        return response
+    except Exception as e:
+        # Return a properly formatted JSON error response
+        return JSONResponse({"error": f"Save failed: {str(e)}"}, status_code=500)
+
+
+async def _call_api_capture(request, fields: Dict[str, Any]):
+    """Call api_capture directly with `fields`; omitted form fields get their
+    defaults and list values are joined with commas. No media uploads."""
+    kwargs: Dict[str, Any] = {"request": request, "media": None}
+    for name, param in inspect.signature(api_capture).parameters.items():
+        if name in kwargs:
+            continue
+        value = fields.get(name, param.default.default)
+        if isinstance(value, list):
+            value = ",".join(str(v) for v in value)
+        kwargs[name] = value
+    return await api_capture(**kwargs)
+
+
+@app.post("/api/quick")
+async def api_quick(request: Request, payload: Dict[str, Any] = Body(...)):
+    """Fastest capture path: only {content}, everything else from config.
+
+    Tags, sources and modalities come from capture.quick; like any capture
+    without a context, it reuses the last one with capture.inherit_last_context.
+    """
+    content = payload.get("content")
+    if not isinstance(content, str) or not content.strip():
+        raise ValidationError("Quick capture needs non-empty 'content'")
+    cfg, error = _apply_vault_override(
+        request, normalize_config(load_config(_config_path))
+    )
+    if error:
+        return error
+    quick_cfg = cfg["capture"].get("quick") or {}
+    fields: Dict[str, Any] = {
+        "content": content,
+        "tags": quick_cfg.get("tags") or [],
+        "sources": quick_cfg.get("sources") or [],
+        "modalities": quick_cfg.get("modalities") or ["text"],
+    }
+    result = await _call_api_capture(request, fields)
+    if isinstance(result, Response):
+        return result
+    return {"capture_id": result["capture_id"], "saved_to": result["saved_to"]}
+
+
+@app.get("/api/schema/capture")
+def api_schema_capture():
+    """JSON Schema for the /api/capture form, derived from the endpoint signature."""
+    spec = app.openapi()
+    request_body = spec["paths"]["/api/capture"]["post"]["requestBody"]
+    schema = request_body["content"]["multipart/form-data"]["schema"]
+    if "$ref" in schema:
+        schema = spec["components"]["schemas"][schema["$ref"].rsplit("/", 1)[-1]]
+    return {
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        **schema,
+        "title": "CaptureForm",
+        "required": schema.get("required", []),
+    }
+
+
+# Sample media attached to template previews, by modality
+TEMPLATE_PREVIEW_MEDIA = {
+    "text": None,
+    "clipboard": None,
+    "screenshot": ("screenshot", "sample_screenshot.png"),
+    "image": ("image", "sample_image.png"),
+    "audio": ("audio", "sample_audio.wav"),
+    "system-audio": ("audio", "sample_system_audio.wav"),
+}
+
+
+@app.get("/api/templates/{modality}/preview")
+def api_template_preview(modality: str):
+    """Render the capture template for a modality with sample data.
+
+    Nothing is written. `template` is the file used (null for the built-in
+    layout) and `unresolved` lists placeholders that would render empty.
+    """
+    if modality not in TEMPLATE_PREVIEW_MEDIA:
+        raise ValidationError(
+            f"Unknown modality {modality!r}; "
+            f"expected one of {', '.join(TEMPLATE_PREVIEW_MEDIA)}"
+        )
+    cfg = normalize_config(load_config(_config_path))
+    writer = _get_writer(cfg)
+    fields: Dict[str, Any] = {
+        "content": "Sample capture content",
+        "context": "sample-context",
+        "tags": ["sample-tag"],
+        "sources": ["sample-source"],
+        "modalities": [modality],
+        "location": None,
+        "source_app": None,
+    }
+    if modality == "clipboard":
+        fields["clipboard"] = "Sample clipboard text"
+    media = TEMPLATE_PREVIEW_MEDIA[modality]
+    if media:
+        media_type, filename = media
+        fields["media_files"] = [
+            {"type": media_type, "path": str(writer.media_dir / filename)}
+        ]
+    template_path = writer.template_path([modality])
+    template = writer.load_template([modality])
+    return {
+        "modality": modality,
+        "template": str(template_path) if template is not None else None,
+        "markdown": writer.format_capture(_new_capture(cfg, **fields)),
+        "unresolved": unresolved_placeholders(template or ""),
+    }
+
+
+IMAGE_MIME_EXTENSIONS = {
+    "image/png": ".png",
+    "image/jpeg": ".jpg",
+    "image/gif": ".gif",
+    "image/webp": ".webp",
+}
+IMAGE_SIGNATURES = {
+    "image/png": (b"\x89PNG\r\n\x1a\n",),
+    "image/jpeg": (b"\xff\xd8\xff",),
+    "image/gif": (b"GIF87a", b"GIF89a"),
+    "image/webp": (b"RIFF",),
+}
+DEFAULT_MAX_IMAGE_BYTES = 10 * 1024 * 1024
+
+
+@app.post("/api/capture/image")
+def api_capture_image(request: Request, payload: Dict[str, Any] = Body(...)):
+    """Create a capture from inline base64 image data (e.g. a browser extension).
+
+    Body: {"data_base64": str, "mime": "image/png", "tags": [...] or "a,b",
+    "content": optional str, "context": optional str}.
+    """
+    cfg, error = _apply_vault_override(
+        request, normalize_config(load_config(_config_path))
+    )
+    if error:
+        return error
+    mime = str(payload.get("mime") or "").lower()
+    if mime not in IMAGE_MIME_EXTENSIONS:
+        return JSONResponse(
+            {"error": f"Unsupported image type: {mime or 'missing'}"}, status_code=415
+        )
+
+    max_bytes = int(
+        (cfg.get("capture") or {}).get("max_image_bytes", DEFAULT_MAX_IMAGE_BYTES)
+    )
+    encoded = str(payload.get("data_base64") or "")
+    # base64 inflates by 4/3, so reject oversized payloads before decoding
+    if len(encoded) * 3 // 4 > max_bytes:
+        return JSONResponse({"error": "Image too large"}, status_code=413)
+    try:
+        data = base64.b64decode(encoded, validate=True)
+    except (binascii.Error, ValueError):
+        return JSONResponse({"error": "Invalid base64 image data"}, status_code=400)
+    if not data:
+        return JSONResponse({"error": "Empty image data"}, status_code=400)
+    if len(data) > max_bytes:
+        return JSONResponse({"error": "Image too large"}, status_code=413)
+    if not data.startswith(IMAGE_SIGNATURES[mime]):
+        return JSONResponse(
+            {"error": f"Image data does not match type {mime}"}, status_code=400
+        )
+
+    media_dir = Path(cfg["vault"]["path"]).expanduser() / cfg["vault"]["media_dir"]
+    media_dir.mkdir(parents=True, exist_ok=True)
+    timestamp = datetime.now().strftime("%Y%m%d_%H%M%S_%f")[:-3]
+    image_path = media_dir / f"{timestamp}_image{IMAGE_MIME_EXTENSIONS[mime]}"
+    image_path.write_bytes(data)
+
+    tags = payload.get("tags") or []
+    if isinstance(tags, str):
+        tags = tags.split(",")
+    capture = _new_capture(
+        cfg,
+        content=str(payload.get("content") or ""),
+        context=str(payload.get("context") or "").strip(),
+        tags=_clean_values(tags),
+        modalities=["image"],
+        media_files=[
+            {"path": str(image_path), "type": "image", "name": image_path.name}
+        ],
+    )
+    try:
+        p = _persist_capture(capture, cfg)
+    except Exception as e:
+        return _save_failed_response(capture, e, cfg)
+
+    return {
+        "saved_to": str(p),
+        "verified": p.exists(),
+        "capture_id": capture["capture_id"],
+        "image_path": str(image_path),
+        "sections": capture.get("sections", []),
+        "mirrored_to": capture.get("mirrored_to", []),
+    }
+
+
+@app.get("/api/metrics")
+def api_metrics():
+    """Counters for monitoring, in Prometheus text exposition format."""
+    return PlainTextResponse(
+        metrics.render(), media_type="text/plain; version=0.0.4; charset=utf-8"
+    )
+
+
+@app.get("/api/recovery")
+@app.get("/api/deadletter")
+def api_recovery():
+    """List captures that failed to save and can be resubmitted."""
+    drafts = []
+    recovery_dir = _recovery_dir()
+    if recovery_dir.exists():
+        for f in sorted(recovery_dir.glob("*.json"), reverse=True):
+            try:
+                payload = json.loads(f.read_text(encoding="utf-8"))
+            except Exception as e:
+                print(f"Skipping unreadable recovery file {f}: {e}")
+                continue
+            capture = payload.get("capture", {})
+            drafts.append(
+                {
+                    "id": payload.get("id", f.stem),
+                    "failed_at": payload.get("failed_at"),
+                    "error": payload.get("error"),
+                    "attempts": payload.get("attempts", 1),
+                    "content": capture.get("content", ""),
+                    "clipboard": capture.get("clipboard", ""),
+                    "capture_id": capture.get("capture_id"),
+                }
+            )
+    return {"recovery": drafts}
+
+
+@app.post("/api/recovery/{recovery_id}/resubmit")
+@app.post("/api/deadletter/{recovery_id}/retry")
+def api_recovery_resubmit(recovery_id: str):
+    """Retry saving a recovered capture; the draft is removed once it succeeds.
+    A failed retry stays queued with its attempt count and latest error."""
+    recovery_file = _recovery_dir() / f"{Path(recovery_id).name}.json"
+    if not recovery_file.exists():
+        raise NotFoundError("Recovery draft not found")
+
+    payload = json.loads(recovery_file.read_text(encoding="utf-8"))
+    capture = dict(payload["capture"])
+    capture["timestamp"] = datetime.fromisoformat(capture["timestamp"])
+
+    cfg = normalize_config(load_config(_config_path))
+    try:
+        p = _persist_capture(capture, cfg)
+    except Exception as e:
+        payload["attempts"] = payload.get("attempts", 1) + 1
+        payload["error"] = str(e)
+        payload["last_attempt_at"] = datetime.now(timezone.utc).isoformat()
+        recovery_file.write_text(json.dumps(payload, indent=2), encoding="utf-8")
+        if isinstance(e, KmsError):
+            raise
+        raise StorageError(f"Save failed: {e}") from e
+
+    recovery_file.unlink(missing_ok=True)
+    return {"saved_to": str(p), "verified": p.exists()}
+
+
+def _dir_usage(path: Path, exclude: Optional[Path] = None) -> Dict[str, int]:
+    """Total size and file count of a directory tree, optionally skipping a subtree."""
+    total_bytes = 0
+    file_count = 0
+    if path.exists():
+        for f in path.rglob("*"):
+            if exclude is not None and (f == exclude or exclude in f.parents):
+                continue
+            if f.is_file():
+                total_bytes += f.stat().st_size
+                file_count += 1
+    return {"bytes": total_bytes, "files": file_count}
+
+
+@app.get("/api/stats/storage")
+def api_stats_storage(refresh: bool = False):
+    """Disk usage of the capture dir, media dir, and database file."""
+    cfg = normalize_config(load_config(_config_path))
+    vault = Path(cfg["vault"]["path"]).expanduser()
+    capture_dir = vault / cfg["vault"]["capture_dir"]
+    media_dir = vault / cfg["vault"]["media_dir"]
+    db_file = Path(cfg["database"]["path"])
+
+    cache_key = f"{vault}|{db_file}"
+    cached = _storage_stats_cache.get(cache_key)
+    if (
+        not refresh
+        and cached
+        and time.monotonic() - cached[0] < STORAGE_STATS_TTL_SECONDS
+    ):
+        return cached[1]
+
+    # The media dir usually lives inside the capture dir; don't count it twice
+    captures = _dir_usage(capture_dir, exclude=media_dir)
+    media = _dir_usage(media_dir)
+    database = {
+        "bytes": db_file.stat().st_size if db_file.exists() else 0,
+        "files": 1 if db_file.exists() else 0,
+    }
+    result = {
+        "captures": {"path": str(capture_dir), **captures},
+        "media": {"path": str(media_dir), **media},
+        "database": {"path": str(db_file), **database},
+        "total_bytes": captures["bytes"] + media["bytes"] + database["bytes"],
+        "computed_at": datetime.now(timezone.utc).isoformat(),
+    }
+    _storage_stats_cache[cache_key] = (time.monotonic(), result)
+    return result
+
+
+# SQLite expression for each bucket's start date; weeks start on Monday
+TIMELINE_BUCKETS = {
+    "day": "date(timestamp)",
+    "week": "date(timestamp, 'weekday 0', '-6 days')",
+    "month": "date(timestamp, 'start of month')",
+}
+MAX_TIMELINE_BUCKETS = 5000
+
+
+def _bucket_start(bucket: str, day: date) -> date:
+    if bucket == "week":
+        return day - timedelta(days=day.weekday())
+    if bucket == "month":
+        return day.replace(day=1)
+    return day
+
+
+def _next_bucket(bucket: str, start: date) -> date:
+    if bucket == "week":
+        return start + timedelta(days=7)
+    if bucket == "month":
+        return (start + timedelta(days=32)).replace(day=1)
+    return start + timedelta(days=1)
+
+
+def _parse_day(value: str, name: str) -> date:
+    try:
+        return date.fromisoformat(value.strip()[:10])
+    except ValueError:
+        raise ValidationError(f"Invalid {name} date {value!r}")
+
+
+def _timeline_range(db: MainDatabase, bucket: str, start: str, end: str):
+    """(first, last) day of a stats series; `to` defaults to today (UTC) and
+    `from` to the day of the first capture."""
+    if bucket not in TIMELINE_BUCKETS:
+        raise ValidationError(
+            f"Invalid bucket {bucket!r}; expected one of {', '.join(TIMELINE_BUCKETS)}"
+        )
+    last = _parse_day(end, "to") if end else datetime.now(timezone.utc).date()
+    if start:
+        first = _parse_day(start, "from")
+    else:
+        first_capture = db.first_capture_date()
+        first = date.fromisoformat(first_capture) if first_capture else last
+    if first > last:
+        raise ValidationError("from must not be after to")
+    return first, last
+
+
+def _bucket_keys(bucket: str, first: date, last: date) -> List[str]:
+    """Start dates of every bucket from `first` to `last`, gaps included."""
+    keys = []
+    current = _bucket_start(bucket, first)
+    while current <= last:
+        if len(keys) >= MAX_TIMELINE_BUCKETS:
+            raise ValidationError(
+                f"Range covers more than {MAX_TIMELINE_BUCKETS} {bucket} buckets"
+            )
+        keys.append(current.isoformat())
+        current = _next_bucket(bucket, current)
+    return keys
+
+
+@app.get("/api/stats/timeline")
+def api_stats_timeline(
+    request: Request,
+    bucket: str = "day",
+    start: str = Query("", alias="from"),
+    end: str = Query("", alias="to"),
+):
+    """Capture counts per day, week or month, with empty buckets filled in."""
+    _, db = _request_vault(request)
+    first, last = _timeline_range(db, bucket, start, end)
+    counts = db.capture_counts(
+        TIMELINE_BUCKETS[bucket], first.isoformat(), last.isoformat()
+    )
+    series = [
+        {"bucket_start": key, "count": counts.get(key, 0)}
+        for key in _bucket_keys(bucket, first, last)
+    ]
+    return {"bucket": bucket, "series": series}
+
+
+@app.get("/api/stats/rating")
+def api_stats_rating(
+    request: Request,
+    bucket: str = "day",
+    start: str = Query("", alias="from"),
+    end: str = Query("", alias="to"),
+):
+    """Average rating per day, week or month. Buckets without rated captures
+    have a null average; unrated captures are ignored."""
+    _, db = _request_vault(request)
+    first, last = _timeline_range(db, bucket, start, end)
+    ratings = db.rating_averages(
+        TIMELINE_BUCKETS[bucket], first.isoformat(), last.isoformat()
+    )
+    series = []
+    for key in _bucket_keys(bucket, first, last):
+        average, count = ratings.get(key, (None, 0))
+        series.append(
+            {
+                "bucket_start": key,
+                "average": round(average, 2) if average is not None else None,
+                "count": count,
+            }
+        )
+    return {"bucket": bucket, "series": series}
+
+
+def _link_candidates(link: str, style: str, idea_file: Path, vault: Path) -> List[Path]:
+    """Where a media link may point: relative to the capture file, and for
+    wikilinks also relative to the vault root (as Obsidian resolves them)."""
+    target = Path(link.strip().strip("<>"))
+    if target.is_absolute():
+        return [target]
+    candidates = [idea_file.parent / target]
+    if style == "wikilink":
+        candidates.append(vault / target)
+    return candidates
+
+
+@app.get("/api/media/broken")
+def api_media_broken(request: Request):
+    """Media references whose file does not exist: links in each capture's media
+    sections and the media_files rows recorded for it."""
+    cfg, db = _request_vault(request)
+    writer = _get_writer(cfg)
+    vault = Path(cfg["vault"]["path"]).expanduser()
+
+    broken = []
+    for entry in db.capture_files():
+        capture_id = entry["capture_id"]
+        idea_file = Path(entry["file_path"] or "")
+        if entry["file_path"] and idea_file.exists():
+            _, body = writer.split_frontmatter(idea_file.read_text(encoding="utf-8"))
+            for link, style in media_links(body):
+                if re.match(r"^[a-z][a-z0-9+.-]*:", link, re.I):
+                    continue  # http:, data: and other URLs
+                candidates = _link_candidates(link, style, idea_file, vault)
+                if not any(c.exists() for c in candidates):
+                    broken.append(
+                        {
+                            "capture_id": capture_id,
+                            "source": "markdown",
+                            "link": link,
+                            "resolved": str(candidates[0]),
+                        }
+                    )
+        for media_path in entry["media_paths"]:
+            resolved = Path(media_path).expanduser()
+            if not resolved.is_absolute():
+                resolved = vault / resolved
+            if not resolved.exists():
+                broken.append(
+                    {
+                        "capture_id": capture_id,
+                        "source": "database",
+                        "link": media_path,
+                        "resolved": str(resolved),
+                    }
+                )
+    return {"broken": broken, "count": len(broken)}
+
+
+@app.post("/api/vault/init")
+def api_vault_init():
+    """Create the vault directories and a starter config if they are missing.
+
+    Safe to call repeatedly; returns which paths were created and which already
+    existed.
+    """
+    cfg = normalize_config(load_config(_config_path))
+    vault = Path(cfg["vault"]["path"]).expanduser()
+    created, existing = [], []
+    for directory in (
+        vault,
+        vault / cfg["vault"]["capture_dir"],
+        vault / cfg["vault"]["media_dir"],
+    ):
+        (existing if directory.exists() else created).append(str(directory))
+        directory.mkdir(parents=True, exist_ok=True)
+
+    config_path = _resolve_config_path(_config_path)
+    if config_path.exists():
+        existing.append(str(config_path))
+    else:
+        if not _config_path and not os.environ.get("KMS_CONFIG_PATH"):
+            # Keep a generated config out of the repository
+            config_path = _xdg_config_path()
+        example = Path(__file__).resolve().parent.parent / "config-prod.yaml"
+        config_path.parent.mkdir(parents=True, exist_ok=True)
+        config_path.write_text(example.read_text(encoding="utf-8"), encoding="utf-8")
+        created.append(str(config_path))
+
+    return {"created": created, "existing": existing}
+
+
+@app.get("/api/browse/tags")
+def api_browse_tags(request: Request, recent: int = 3):
+    """Tags with capture counts and their latest capture ids, for the tag sidebar."""
+    _, db = _request_vault(request)
+    return {"tags": db.browse_tags(max(0, min(recent, 50)))}
+
+
+VALUE_COUNT_SORTS = ("last_used", "count")
+
+
+def _validate_value_sort(sort: str) -> str:
+    if sort not in VALUE_COUNT_SORTS:
+        raise ValidationError(
+            f"Unknown sort {sort!r}; expected one of {', '.join(VALUE_COUNT_SORTS)}"
+        )
+    return sort
+
+
+@app.get("/api/contexts")
+def api_contexts(request: Request, sort: str = "last_used"):
+    """Every context with its capture count and last use, for the context picker."""
+    _, db = _request_vault(request)
+    contexts = db.context_counts(_validate_value_sort(sort))
+    return {"contexts": contexts, "total": len(contexts)}
+
+
+@app.get("/api/collections")
+def api_collections(request: Request, sort: str = "last_used"):
+    """Every collection (notebook) with its capture count and last use."""
+    _, db = _request_vault(request)
+    collections = db.collection_counts(_validate_value_sort(sort))
+    return {"collections": collections, "total": len(collections)}
+
+
+@app.get("/api/tags/top")
+def api_top_tags(
+    request: Request, limit: int = 8, recent_days: Optional[float] = None
+):
+    """Most-used tags for the quick-add chips, colored like tag suggestions.
+
+    `recent_days` counts only recent usage. Fetched once per form open, so the
+    response may be cached briefly.
+    """
+    if limit < 1:
+        raise ValidationError("limit must be at least 1")
+    since = None
+    if recent_days is not None:
+        since = datetime.now(timezone.utc) - timedelta(days=recent_days)
+    cfg, db = _request_vault(request)
+    colors, palette = _value_colors(cfg, "tag")
+    tags = [
+        {
+            **tag,
+            "color": colors.get(tag["value"]) or value_color(tag["value"], palette),
+        }
+        for tag in db.top_tags(min(limit, 100), since)
+    ]
+    return JSONResponse(
+        {"tags": tags}, headers={"Cache-Control": "private, max-age=60"}
+    )
+
+
+@app.get("/api/graph")
+def api_graph(
+    request: Request,
+    root: str = "",
+    depth: int = 1,
+    tags: bool = False,
+    limit: int = 500,
+):
+    """Captures linked by [[wikilinks]], for a graph view.
+
+    Pass `root` for the local graph within `depth` links of one capture, and
+    `tags=true` to add tag nodes. Large vaults are capped at `limit` captures
+    (see `truncated`).
+    """
+    _, db = _request_vault(request)
+    root = root.strip()
+    if root and not db.capture_exists(root):
+        raise NotFoundError(f"Capture {root} not found")
+    if depth < 0:
+        raise ValidationError("depth must not be negative")
+    return db.get_capture_graph(root or None, depth, tags, limit, label=title_alias)
+
+
+@app.get("/api/graph/tags")
+def api_graph_tags(request: Request, min_weight: int = 1):
+    """Tag co-occurrence graph; edges below `min_weight` are dropped."""
+    _, db = _request_vault(request)
+    return db.get_tag_graph(max(min_weight, 1))
+
+
+@app.post("/api/drafts")
+def api_save_draft(state: str = Form(...), draft_id: str = Form("")):
+    """Autosave the in-progress capture form state."""
+    try:
+        parsed_state = json.loads(state)
+    except json.JSONDecodeError as e:
+        raise ValidationError(f"Invalid draft state: {e}") from e
+
+    cfg = normalize_config(load_config(_config_path))
+    retention_days = float((cfg.get("capture") or {}).get("draft_retention_days", 7))
+    db = get_main_db()
+    db.prune_drafts(retention_days)
+
+    draft_id = draft_id.strip() or uuid.uuid4().hex
+    db.save_draft(draft_id, parsed_state)
+    return {"draft_id": draft_id}
+
+
+@app.get("/api/drafts/latest")
+def api_latest_draft():
+    """Get the most recently autosaved draft so the UI can restore it."""
+    return {"draft": get_main_db().get_latest_draft()}
+
+
+@app.get("/api/ui-state")
+def api_ui_state():
+    """Persisted frontend state, e.g. the last vault, mode and window geometry."""
+    return {"state": get_main_db().get_ui_state()}
+
+
+@app.put("/api/ui-state")
+def api_update_ui_state(payload: Dict[str, Any] = Body(...)):
+    """Merge keys into the persisted UI state; a key set to null removes it."""
+    if any(not key.strip() for key in payload):
+        raise ValidationError("UI state keys must not be empty")
+    return {"state": get_main_db().update_ui_state(payload)}
+
+
+def _capture_file(
+    db: MainDatabase, capture_id: str, writer: SafeMarkdownWriter
+) -> Path:
+    stored = db.get_capture_file_path(capture_id)
+    return Path(stored) if stored else writer.find_capture_file(capture_id)
+
+
+def _clean_values(values) -> List[str]:
+    if isinstance(values, str):
+        values = values.split(",")
+    return [str(v).strip() for v in (values or []) if str(v).strip()]
+
+
+@app.post("/api/tags/bulk")
+def api_tags_bulk(
+    request: Request, payload: Dict[str, Any] = Body(...), force: bool = False
+):
+    """Add/remove tags on every capture matching a filter.
+
+    Body: {"filter": {capture_ids|tag|source|context|query|fts}, "add": [...],
+    "remove": [...]}. The database and markdown frontmatter are updated
+    together; if any file fails to rewrite, nothing is changed. Locked captures
+    are skipped (and listed in `locked`) unless force=true.
+    """
+    capture_filter = payload.get("filter") or {}
+    add = _clean_values(payload.get("add"))
+    remove = _clean_values(payload.get("remove"))
+    if not add and not remove:
+        raise ValidationError("Nothing to add or remove")
+
+    cfg, db = _request_vault(request)
+    capture_ids = db.find_capture_ids(capture_filter)
+    if not capture_ids and not any(capture_filter.values()):
+        raise ValidationError("A non-empty filter is required")
+
+    locked = [] if force else [i for i in capture_ids if db.is_locked(i)]
+    capture_ids = [i for i in capture_ids if i not in locked]
+    touched = _bulk_update_tags(db, cfg, capture_ids, add, remove)
+    return {"capture_ids": touched, "count": len(touched), "locked": locked}
+
+
+def _bulk_update_tags(db, cfg, capture_ids, add, remove) -> List[str]:
+    """Update tags in the database and frontmatter of every capture in one
+    transaction; returns the ids whose tags changed. If any file fails to
+    rewrite, nothing is changed."""
+    writer = _get_writer(cfg)
+    today = _format_date(cfg)
+    touched: List[str] = []
+
+    def rewrite_files():
+        originals: List[tuple] = []
+        try:
+            for capture_id in capture_ids:
+                idea_file = _capture_file(db, capture_id, writer)
+                if not idea_file.exists():
+                    continue
+                frontmatter, _ = writer.split_frontmatter(
+                    idea_file.read_text(encoding="utf-8")
+                )
+                old_tags = list(frontmatter.get("tags") or [])
+                new_tags = [t for t in old_tags if t not in remove]
+                new_tags += [t for t in add if t not in new_tags]
+                if new_tags == old_tags:
+                    continue
+                originals.append(
+                    (
+                        idea_file,
+                        writer.rewrite_frontmatter(
+                            idea_file, {"tags": new_tags, "last_edited_date": today}
+                        ),
+                    )
+                )
+                touched.append(capture_id)
+        except Exception:
+            for idea_file, original in originals:
+                writer.atomic_write(idea_file, original)
+            raise
+
+    try:
+        db.bulk_update_tags(capture_ids, add, remove, before_commit=rewrite_files)
+    except Exception as e:
+        raise DatabaseError(f"Bulk tag update failed: {e}") from e
+
+    db.record_file_states(touched)
+    return touched
+
+
+@app.post("/api/search/tag")
+def api_search_tag(request: Request, payload: Dict[str, Any] = Body(...)):
+    """Add tags to every capture matching a full-text query.
+
+    Body: {"query": FTS5 query over content and context, "add": [...]}. Like
+    /api/tags/bulk, the database and frontmatter are updated together.
+    """
+    query = str(payload.get("query") or "").strip()
+    add = _clean_values(payload.get("add"))
+    if not query:
+        raise ValidationError("A search query is required")
+    if not add:
+        raise ValidationError("Nothing to add")
+    cfg, db = _request_vault(request)
+    capture_ids = db.find_capture_ids({"fts": query})
+    touched = _bulk_update_tags(db, cfg, capture_ids, add, [])
+    return {"capture_ids": touched, "count": len(touched), "matched": len(capture_ids)}
+
+
+def _union(*lists) -> List[Any]:
+    result: List[Any] = []
+    for values in lists:
+        for v in values or []:
+            if v not in result:
+                result.append(v)
+    return result
+
+
+@app.post("/api/captures/merge")
+def api_captures_merge(
+    request: Request, payload: Dict[str, Any] = Body(...), force: bool = False
+):
+    """Merge several captures into one.
+
+    Body: {"ids": [a, b, ...], "keep": a}. Bodies are appended to the kept
+    capture, tags/sources/media are unioned, custom fields the kept capture lacks
+    are added, and the other captures' files and rows are removed. Any locked
+    capture refuses the merge with a 409 unless force=true.
+    """
+    ids = list(dict.fromkeys(_clean_values(payload.get("ids"))))
+    keep = str(payload.get("keep") or "").strip()
+    if len(ids) < 2:
+        raise ValidationError("At least two ids are required")
+    if keep not in ids:
+        raise ValidationError("keep must be one of ids")
+
+    cfg, db = _request_vault(request)
+    missing = [i for i in ids if not db.capture_exists(i)]
+    if missing:
+        raise NotFoundError(f"Captures not found: {', '.join(missing)}")
+    for capture_id in ids:
+        _require_capture(db, capture_id, force)
+
+    writer = _get_writer(cfg)
+    others = [i for i in ids if i != keep]
+    files = {i: _capture_file(db, i, writer) for i in ids}
+    if not files[keep].exists():
+        raise NotFoundError("Kept capture file not found")
+
+    original = files[keep].read_text(encoding="utf-8")
+    frontmatter, body = writer.split_frontmatter(original)
+    for other in others:
+        if not files[other].exists():
+            continue
+        other_fm, other_body = writer.split_frontmatter(
+            files[other].read_text(encoding="utf-8")
+        )
+        for key in ("tags", "sources", "context", "modalities"):
+            frontmatter[key] = _union(frontmatter.get(key), other_fm.get(key))
+        for key, value in other_fm.items():
+            if key.startswith(FIELD_PREFIX):
+                frontmatter.setdefault(key, value)
+        # Keep the merged ids as aliases so existing links still resolve
+        frontmatter["aliases"] = _union(
+            frontmatter.get("aliases"), other_fm.get("aliases"), [other]
+        )
+        body = body.rstrip("\n") + "\n" + other_body.lstrip("\n")
+    frontmatter["last_edited_date"] = _format_date(cfg)
+    merged_text = writer.render_file(frontmatter, body)
+
+    try:
+        db.merge_captures(
+            keep,
+            others,
+            before_commit=lambda: writer.atomic_write(files[keep], merged_text),
+        )
+    except Exception:
+        writer.atomic_write(files[keep], original)
+        raise
+
+    for other in others:
+        files[other].unlink(missing_ok=True)
+
+    db.record_file_states([keep])
+    return {"capture_id": keep, "merged": others, "file_path": str(files[keep])}
+
+
+CONTENT_SECTION_RE = re.compile(r"^## Content\n.*?(?=^## |\Z)", re.M | re.S)
+
+
+def _replace_content_section(body: str, content: str) -> str:
+    section = f"## Content\n{content.rstrip()}\n"
+    match = CONTENT_SECTION_RE.search(body)
+    if not match:
+        return section + body
+    return body[: match.start()] + section + body[match.end() :]
+
+
+def _store_capture(db, writer, capture: Dict[str, Any]):
+    """Index a capture that was just written, recording its file's body as the
+    new revision."""
+    capture["revision_text"] = writer.capture_body(Path(capture["file_path"]))
+    db.store_capture_data(capture)
+
+
+def _validate_parent(db: MainDatabase, capture_id: str, parent: str):
+    """Reject a parent that is the capture itself or one of its descendants."""
+    if parent == capture_id:
+        raise ValidationError("A capture cannot be its own parent")
+    if capture_id in db.parent_chain(parent):
+        raise ValidationError(f"Parent {parent} would make a cycle with {capture_id}")
+
+
+def _require_capture(db: MainDatabase, capture_id: str, force: bool = True):
+    """Raise unless the capture exists and (without force) is not locked."""
+    if not db.capture_exists(capture_id):
+        raise NotFoundError(f"Capture {capture_id} not found")
+    if not force and db.is_locked(capture_id):
+        raise ConflictError(f"Capture {capture_id} is locked; pass force=true")
+
+
+def _capture_filter(
+    modality: str,
+    tag: str,
+    source: str,
+    context: str,
+    query: str,
+    status: str,
+    collection: str = "",
+    field: Optional[List[str]] = None,
+) -> Dict[str, Any]:
+    """Filter dict for MainDatabase.list_captures from the list query params.
+
+    With vault.hide_archived, archived captures are left out unless a status is
+    asked for explicitly. Each `field` is a "key:value" custom field match.
+    """
+    if status.strip():
+        _validate_status(status)
+    cfg = normalize_config(load_config(_config_path))
+    hide_archived = cfg["vault"]["hide_archived"] and not status.strip()
+    return {
+        "status": status.strip(),
+        "exclude_status": "archived" if hide_archived else "",
+        "modality": modality.strip(),
+        "tag": tag.strip(),
+        "source": source.strip(),
+        "context": context.strip(),
+        "collection": collection.strip(),
+        "query": query.strip(),
+        "fields": [_parse_field_filter(f) for f in field or []],
+    }
+
+
+def _parse_field_filter(value: str) -> Tuple[str, str]:
+    key, sep, wanted = value.partition(":")
+    if not sep or not key.strip():
+        raise ValidationError(f"Invalid field filter {value!r}; expected key:value")
+    return key.strip(), wanted.strip()
+
+
+@app.get("/api/captures")
+def api_list_captures(
+    request: Request,
+    modality: str = "",
+    tag: str = "",
+    source: str = "",
+    context: str = "",
+    collection: str = "",
+    query: str = "",
+    status: str = "",
+    field: List[str] = Query([]),
+    sort: str = "timestamp",
+    limit: int = 50,
+    offset: int = 0,
+    after: str = "",
+):
+    """List captures, optionally filtered (all filters must match). `field` is
+    repeatable and matches custom fields as key:value.
+
+    Newest first, or sort=priority for highest priority first. Pass the returned
+    `next_cursor` as `after` to fetch the next page without offset drift.
+    """
+    if sort not in ("timestamp", "priority"):
+        raise ValidationError("sort must be 'timestamp' or 'priority'")
+    cursor = _decode_cursor(after) if after else None
+    if cursor and (sort != "timestamp" or offset):
+        raise ValidationError("after cannot be combined with offset or sort")
+    cfg, error = _apply_vault_override(
+        request, normalize_config(load_config(_config_path))
+    )
+    if error:
+        return error
+    capture_filter = _capture_filter(
+        modality, tag, source, context, query, status, collection, field
+    )
+    limit = max(1, min(limit, 500))
+    captures = get_main_db(cfg).list_captures(
+        capture_filter, limit, max(offset, 0), sort, after=cursor
+    )
+    next_cursor = None
+    if sort == "timestamp" and len(captures) == limit:
+        next_cursor = _encode_cursor(captures[-1])
+    return {"captures": captures, "count": len(captures), "next_cursor": next_cursor}
+
+
+@app.get("/api/search")
+def api_search(
+    request: Request,
+    q: str = "",
+    tag: str = "",
+    source: str = "",
+    start: str = Query("", alias="from"),
+    end: str = Query("", alias="to"),
+    limit: int = 20,
+    offset: int = 0,
+):
+    """Captures whose content or context contains `q`, newest first, with a
+    snippet of the content. `from`/`to` are inclusive days; with no query or
+    filters this is the most recent captures."""
+    cfg, error = _apply_vault_override(
+        request, normalize_config(load_config(_config_path))
+    )
+    if error:
+        return error
+    capture_filter = _capture_filter("", tag, source, "", "", "")
+    if start:
+        capture_filter["from"] = _parse_day(start, "from").isoformat()
+    if end:
+        capture_filter["before"] = (
+            _parse_day(end, "to") + timedelta(days=1)
+        ).isoformat()
+    limit = max(1, min(limit, 500))
+    results = get_main_db(cfg).search_captures(q, capture_filter, limit, max(offset, 0))
+    return {"results": results, "count": len(results)}
+
+
+def _encode_cursor(capture: Dict[str, Any]) -> str:
+    """Opaque keyset cursor for the position just after `capture`."""
+    raw = json.dumps([capture["timestamp"], capture["capture_id"]])
+    return base64.urlsafe_b64encode(raw.encode("utf-8")).decode("ascii")
+
+
+def _decode_cursor(cursor: str) -> Tuple[str, str]:
+    try:
+        timestamp, capture_id = json.loads(base64.urlsafe_b64decode(cursor))
+    except (ValueError, TypeError):
+        raise ValidationError("Invalid cursor")
+    return str(timestamp), str(capture_id)
+
+
+STREAM_PAGE_SIZE = 500
+
+
+@app.get("/api/captures/stream")
+def api_stream_captures(
+    request: Request,
+    modality: str = "",
+    tag: str = "",
+    source: str = "",
+    context: str = "",
+    collection: str = "",
+    query: str = "",
+    status: str = "",
+    field: List[str] = Query([]),
+):
+    """Every matching capture as newline-delimited JSON, newest first.
+
+    Takes the same filters as /api/captures; rows are read a page at a time so
+    large vaults start rendering before the whole list is loaded.
+    """
+    capture_filter = _capture_filter(
+        modality, tag, source, context, query, status, collection, field
+    )
+    _, db = _request_vault(request)
+
+    def rows():
+        # Keyset pages, so captures saved mid-stream don't shift later pages
+        after = None
+        while True:
+            page = db.list_captures(capture_filter, STREAM_PAGE_SIZE, after=after)
+            for capture in page:
+                yield json.dumps(capture, default=str) + "\n"
+            if len(page) < STREAM_PAGE_SIZE:
+                return
+            after = (page[-1]["timestamp"], page[-1]["capture_id"])
+
+    return StreamingResponse(rows(), media_type="application/x-ndjson")
+
+
+@app.get("/api/captures/near")
+def api_captures_near(
+    request: Request, lat: float, lon: float, radius_km: float = 1.0
+):
+    """Captures recorded within radius_km of a point, nearest first."""
+    if not (-90 <= lat <= 90 and -180 <= lon <= 180):
+        raise ValidationError("lat/lon out of range")
+    if radius_km <= 0:
+        raise ValidationError("radius_km must be positive")
+    _, db = _request_vault(request)
+    captures = db.captures_near(lat, lon, radius_km)
+    return {"captures": captures, "count": len(captures)}
+
+
+@app.get("/api/duplicates")
+def api_duplicates(
+    request: Request,
+    threshold: float = 0.9,
+    limit: int = 50,
+    offset: int = 0,
+    scan_limit: int = 2000,
+):
+    """Near-duplicate capture pairs by content similarity, most similar first.
+
+    Only the newest `scan_limit` captures are compared, so the cost stays bounded
+    on large vaults; results are paged with limit/offset.
+    """
+    if not 0 < threshold <= 1:
+        raise ValidationError("threshold must be in (0, 1]")
+    _, db = _request_vault(request)
+    pairs = db.find_duplicates(threshold, max(1, min(scan_limit, 10000)))
+    limit = max(1, min(limit, 500))
+    offset = max(offset, 0)
+    return {"pairs": pairs[offset : offset + limit], "total": len(pairs)}
+
+
+@app.get("/api/captures/drift")
+def api_captures_drift(request: Request):
+    """Captures whose files were edited or removed outside the app since they
+    were last indexed. Resync them with POST /api/reindex/selected."""
+    _, db = _request_vault(request)
+    drifted = db.find_drift()
+    return {"captures": drifted, "count": len(drifted)}
+
+
+@app.get("/api/captures/expiring")
+def api_captures_expiring(request: Request, before: str = ""):
+    """Captures with an expires_at, soonest first; `before` limits the preview to
+    those the sweeper will have removed by then. Locked captures are listed but
+    never removed."""
+    cutoff = _validate_expires_at(before)
+    _, db = _request_vault(request)
+    expiring = db.expiring_captures(cutoff)
+    return {"captures": expiring, "count": len(expiring)}
+
+
+def _sweep_expired(now: Optional[datetime] = None) -> List[str]:
+    """Soft-delete expired, unlocked captures by moving their files to
+    vault.trash_dir and dropping their rows. Returns the removed ids."""
+    now = now or datetime.now(timezone.utc)
+    db = get_main_db()
+    cfg = normalize_config(load_config(_config_path))
+    writer = _get_writer(cfg)
+    trash_dir = Path(cfg["vault"]["path"]).expanduser() / cfg["vault"]["trash_dir"]
+
+    removed = []
+    for expired in db.expiring_captures(now.astimezone(timezone.utc).isoformat()):
+        if expired["locked"]:
+            continue
+        source_file = _capture_file(db, expired["capture_id"], writer)
+
+        def move_to_trash(source_file=source_file):
+            if source_file.exists():
+                trash_dir.mkdir(parents=True, exist_ok=True)
+                target = _unique_path(trash_dir / source_file.name)
+                shutil.move(str(source_file), target)
+
+        try:
+            db.delete_capture(expired["capture_id"], before_commit=move_to_trash)
+        except (KmsError, OSError) as e:
+            print(f"⚠️  Could not remove expired {expired['capture_id']}: {e}")
+            continue
+        removed.append(expired["capture_id"])
+    if removed:
+        print(f"Moved {len(removed)} expired capture(s) to {trash_dir}")
+    return removed
+
+
+@app.get("/api/captures/{capture_id}/verify")
+def api_verify_capture(request: Request, capture_id: str):
+    """Check a signed capture's body against its frontmatter content_sha256.
+
+    `matches` is null for captures written without capture.content_hash.
+    """
+    cfg, db = _request_vault(request)
+    _require_capture(db, capture_id)
+    writer = _get_writer(cfg)
+    idea_file = _capture_file(db, capture_id, writer)
+    if not idea_file.exists():
+        raise NotFoundError(f"Capture file {idea_file} not found")
+
+    frontmatter, body = writer.split_frontmatter(idea_file.read_text(encoding="utf-8"))
+    expected = frontmatter.get("content_sha256")
+    actual = content_sha256(body)
+    return {
+        "capture_id": capture_id,
+        "signed": expected is not None,
+        "matches": None if expected is None else expected == actual,
+        "expected": expected,
+        "actual": actual,
+    }
+
+
+MARKDOWN_TEXT_RULES = (
+    (re.compile(r"^```.*$\n?", re.M), ""),
+    (re.compile(r"^#{1,6}\s+", re.M), ""),
+    (re.compile(r"!\[([^\]]*)\]\([^)]*\)"), r"\1"),
+    (re.compile(r"!?\[\[([^\]|]*\|)?([^\]]*)\]\]"), r"\2"),
+    (re.compile(r"\[([^\]]*)\]\([^)]*\)"), r"\1"),
+    (re.compile(r"(\*\*|__|\*|_|~~|`)(\S(?:.*?\S)?)\1"), r"\2"),
+)
+
+
+def _markdown_to_text(body: str) -> str:
+    """Plain text from a capture body: markup removed, words and line breaks kept."""
+    for pattern, replacement in MARKDOWN_TEXT_RULES:
+        body = pattern.sub(replacement, body)
+    return body.strip() + "\n"
+
+
+def _render_html(title: str, body: str) -> str:
+    try:
+        import markdown
+    except ImportError:
+        raise UnsupportedError("HTML export needs the 'markdown' package")
+    rendered = markdown.markdown(body, extensions=["fenced_code", "tables"])
+    return (
+        '<!DOCTYPE html>\n<html><head><meta charset="utf-8">'
+        f"<title>{html.escape(title)}</title></head>\n"
+        f"<body>\n{rendered}\n</body></html>\n"
+    )
+
+
+def _render_pdf(cfg, body: str) -> bytes:
+    """PDF via export.pdf_converter (pandoc by default)."""
+    converter = (cfg.get("export") or {}).get("pdf_converter") or "pandoc"
+    if not shutil.which(converter):
+        raise UnsupportedError(
+            f"PDF export needs {converter!r}, which is not installed"
+        )
+    with tempfile.TemporaryDirectory() as tmp:
+        source = Path(tmp) / "capture.md"
+        output = Path(tmp) / "capture.pdf"
+        source.write_text(body, encoding="utf-8")
+        result = subprocess.run(
+            [converter, str(source), "-o", str(output)],
+            capture_output=True,
+            text=True,
+            timeout=120,
+        )
+        if result.returncode != 0 or not output.exists():
+            raise StorageError(f"{converter} failed: {result.stderr.strip()}")
+        return output.read_bytes()
+
+
+@app.get("/api/captures/{capture_id}/export")
+def api_export_capture(request: Request, capture_id: str, format: str = "html"):
+    """Render a single capture as html, txt or pdf (pdf needs a converter)."""
+    if format not in ("html", "txt", "pdf"):
+        raise UnsupportedError(f"Unsupported export format {format!r}")
+    cfg, db = _request_vault(request)
+    _require_capture(db, capture_id)
+    writer = _get_writer(cfg)
+    idea_file = _capture_file(db, capture_id, writer)
+    if not idea_file.exists():
+        raise NotFoundError(f"Capture file {idea_file} not found")
+
+    _, body = writer.split_frontmatter(idea_file.read_text(encoding="utf-8"))
+    headers = {"Content-Disposition": f'inline; filename="{idea_file.stem}.{format}"'}
+    if format == "txt":
+        return PlainTextResponse(_markdown_to_text(body), headers=headers)
+    if format == "html":
+        return HTMLResponse(_render_html(capture_id, body), headers=headers)
+    return Response(
+        _render_pdf(cfg, body), media_type="application/pdf", headers=headers
+    )
+
+
+@app.get("/api/captures/{capture_id}/children")
+def api_capture_children(
+    request: Request, capture_id: str, limit: int = 50, offset: int = 0
+):
+    """Captures whose `parent` is this capture, newest first."""
+    _, db = _request_vault(request)
+    _require_capture(db, capture_id)
+    captures = db.list_captures(
+        {"parent": capture_id}, max(1, min(limit, 500)), max(offset, 0)
+    )
+    return {"captures": captures, "count": len(captures)}
+
+
+@app.get("/api/captures/{capture_id}/revisions")
+def api_capture_revisions(request: Request, capture_id: str):
+    """Stored revisions of a capture's content, oldest first."""
+    _, db = _request_vault(request)
+    _require_capture(db, capture_id)
+    return {"capture_id": capture_id, "revisions": db.list_revisions(capture_id)}
+
+
+REVISION_DIFF_FORMATS = ("unified", "lines")
+
+
+def _line_diff(old: str, new: str) -> List[Dict[str, str]]:
+    """Every line of both texts tagged "equal", "delete" or "insert"."""
+    old_lines, new_lines = old.splitlines(), new.splitlines()
+    lines = []
+    matcher = difflib.SequenceMatcher(a=old_lines, b=new_lines, autojunk=False)
+    for op, i1, i2, j1, j2 in matcher.get_opcodes():
+        if op == "equal":
+            lines.extend({"op": "equal", "text": t} for t in old_lines[i1:i2])
+            continue
+        lines.extend({"op": "delete", "text": t} for t in old_lines[i1:i2])
+        lines.extend({"op": "insert", "text": t} for t in new_lines[j1:j2])
+    return lines
+
+
+@app.get("/api/captures/{capture_id}/revisions/diff")
+def api_capture_revision_diff(
+    request: Request,
+    capture_id: str,
+    start: Optional[int] = Query(None, alias="from"),
+    end: Optional[int] = Query(None, alias="to"),
+    format: str = "unified",
+):
+    """Diff between two revisions of a capture's content.
+
+    `to` defaults to the latest revision and `from` to the one before it.
+    format=unified gives a unified diff string, format=lines a list of lines
+    tagged equal/delete/insert.
+    """
+    if format not in REVISION_DIFF_FORMATS:
+        raise ValidationError(
+            f"Invalid format {format!r}; "
+            f"expected one of {', '.join(REVISION_DIFF_FORMATS)}"
+        )
+    _, db = _request_vault(request)
+    _require_capture(db, capture_id)
+    revisions = [r["revision"] for r in db.list_revisions(capture_id)]
+    if end is None:
+        end = revisions[-1] if revisions else 0
+    if start is None:
+        start = max(end - 1, 1)
+    texts = {}
+    for revision in (start, end):
+        text = db.get_revision(capture_id, revision)
+        if text is None:
+            raise NotFoundError(f"Capture {capture_id} has no revision {revision}")
+        texts[revision] = text
+
+    result: Dict[str, Any] = {"capture_id": capture_id, "from": start, "to": end}
+    if format == "lines":
+        result["lines"] = _line_diff(texts[start], texts[end])
+    else:
+        result["diff"] = "\n".join(
+            difflib.unified_diff(
+                texts[start].splitlines(),
+                texts[end].splitlines(),
+                fromfile=f"{capture_id}@{start}",
+                tofile=f"{capture_id}@{end}",
+                lineterm="",
+            )
+        )
+    return result
+
+
+@app.put("/api/captures/{capture_id}")
+def api_update_capture(
+    request: Request,
+    capture_id: str,
+    payload: Dict[str, Any] = Body(...),
+    force: bool = False,
+):
+    """Edit a capture's content, color, processing_status, parent, priority,
+    rating, collection, expires_at, fields and/or tags, sources, context,
+    modalities, aliases. `fields` is merged into the custom fields; a key set to
+    null removes it."""
+    cfg, db = _request_vault(request)
+    _require_capture(db, capture_id, force)
+
+    fields = ("tags", "sources", "context", "modalities", "aliases")
+    editable = (
+        *fields,
+        "content",
+        "color",
+        "processing_status",
+        "parent",
+        "priority",
+        "rating",
+        "collection",
+        "expires_at",
+        "fields",
+    )
+    if not any(key in payload for key in editable):
+        raise ValidationError("No editable fields given")
+    color = _validate_color(payload.get("color"))
+    status = _validate_status(payload.get("processing_status"))
+    priority = _validate_priority(payload.get("priority"))
+    rating = _validate_rating(payload.get("rating"))
+    collection = _validate_collection(payload.get("collection"))
+    expires = _validate_expires_at(payload.get("expires_at"))
+    field_updates = payload.get("fields") or {}
+    if not isinstance(field_updates, dict):
+        raise ValidationError("fields must be an object")
+    validate_fields(field_updates)
+    parent = str(payload.get("parent") or "").strip()
+    if parent and not db.capture_exists(parent):
+        raise NotFoundError(f"Parent capture {parent} not found")
+    if parent:
+        _validate_parent(db, capture_id, parent)
+
+    writer = _get_writer(cfg)
+    idea_file = _capture_file(db, capture_id, writer)
+    if not idea_file.exists():
+        raise NotFoundError(f"Capture file {idea_file} not found")
+
+    original = idea_file.read_text(encoding="utf-8")
+    frontmatter, body = writer.split_frontmatter(original)
+    for key in fields:
+        if key in payload:
+            frontmatter[key] = _clean_values(payload[key])
+    if "content" in payload:
+        body = _replace_content_section(body, str(payload["content"] or ""))
+    if "color" in payload:
+        if color:
+            frontmatter["color"] = color
+        else:
+            frontmatter.pop("color", None)
+    if "processing_status" in payload:
+        frontmatter["processing_status"] = status
+    if "parent" in payload:
+        if parent:
+            frontmatter["parent"] = parent
+        else:
+            frontmatter.pop("parent", None)
+    if "priority" in payload:
+        if priority is not None:
+            frontmatter["priority"] = priority
+        else:
+            frontmatter.pop("priority", None)
+    if "rating" in payload:
+        if rating is not None:
+            frontmatter["rating"] = rating
+        else:
+            frontmatter.pop("rating", None)
+    if "collection" in payload:
+        if collection:
+            frontmatter["collection"] = collection
+        else:
+            frontmatter.pop("collection", None)
+    if "expires_at" in payload:
+        if expires:
+            frontmatter["expires_at"] = expires
+        else:
+            frontmatter.pop("expires_at", None)
+    for key, value in field_updates.items():
+        if value is None:
+            frontmatter.pop(FIELD_PREFIX + key, None)
+        else:
+            frontmatter[FIELD_PREFIX + key] = value
+    frontmatter["last_edited_date"] = _format_date(cfg)
+
+    try:
+        writer.atomic_write(idea_file, writer.render_file(frontmatter, body))
+        _store_capture(db, writer, writer.read_capture(idea_file))
+    except KmsError:
+        writer.atomic_write(idea_file, original)
+        raise
+
+    response = {"capture_id": capture_id, "file_path": str(idea_file)}
+    if cfg["vault"]["mirror_paths"]:
+        response["mirrored_to"] = writer.mirror_files([idea_file])
+    return response
+
+
+@app.delete("/api/captures/{capture_id}")
+def api_delete_capture(request: Request, capture_id: str, force: bool = False):
+    cfg, db = _request_vault(request)
+    _require_capture(db, capture_id, force)
+
+    idea_file = _capture_file(db, capture_id, _get_writer(cfg))
+    db.delete_capture(
+        capture_id, before_commit=lambda: idea_file.unlink(missing_ok=True)
+    )
+    return {"capture_id": capture_id, "deleted": True}
+
 
+def _set_capture_lock(request: Request, capture_id: str, locked: bool):
+    cfg, db = _request_vault(request)
+    _require_capture(db, capture_id)
 
-def _ollama_health(host: str, port: int) -> bool:
-    try:
-        parsed_host = host.replace("http://", "").replace("https://", "")
-        if ":" in parsed_host:
-            parsed_host = parsed_host.split(":")[0]
-        conn = http.client.HTTPConnection(parsed_host, port=port, timeout=3)
-        conn.request("GET", "/api/version")
-        res = conn.getresponse()
-        ok = res.status == 200
-        conn.close()
-        return ok
-    except Exception:
-        return False
+    writer = _get_writer(cfg)
+    idea_file = _capture_file(db, capture_id, writer)
+    # Unlocked captures simply omit the field
+    updates = {"locked": True if locked else None}
+    originals = []
 
+    def rewrite_file():
+        if idea_file.exists():
+            originals.append(writer.rewrite_frontmatter(idea_file, updates))
 
-@app.get("/api/clipboard")
-def api_clipboard():
-    """Get current clipboard content."""
     try:
-        result = subprocess.run(
-            ["wl-paste", "-t", "text"], capture_output=True, text=True, timeout=2
+        db.set_locked(capture_id, locked, before_commit=rewrite_file)
+    except KmsError:
+        if originals:
+            writer.atomic_write(idea_file, originals[0])
+        raise
+    db.record_file_states([capture_id])
+    return {"capture_id": capture_id, "locked": locked}
+
+
+@app.post("/api/captures/{capture_id}/lock")
+def api_lock_capture(request: Request, capture_id: str):
+    return _set_capture_lock(request, capture_id, True)
+
+
+@app.post("/api/captures/{capture_id}/unlock")
+def api_unlock_capture(request: Request, capture_id: str):
+    return _set_capture_lock(request, capture_id, False)
+
+
+def _unique_path(path: Path) -> Path:
+    counter = 1
+    candidate = path
+    while candidate.exists():
+        candidate = path.with_name(f"{path.stem}_{counter}{path.suffix}")
+        counter += 1
+    return candidate
+
+
+@app.post("/api/captures/{capture_id}/move")
+def api_move_capture(
+    request: Request,
+    capture_id: str,
+    payload: Dict[str, Any] = Body(...),
+    force: bool = False,
+):
+    """Move a capture and its media into another vault.
+
+    Body: {"target_vault": path under vault.allowed_roots}. Media links in the
+    body are rewritten, and a name collision in the target gives the capture a
+    suffixed id (its old id is kept as an alias). If the target vault has its own
+    database, the capture's rows move there.
+    """
+    cfg, db = _request_vault(request)
+    _require_capture(db, capture_id, force)
+    target_vault = str(payload.get("target_vault") or "").strip()
+    if not target_vault:
+        raise ValidationError("target_vault is required")
+    target_cfg = _vault_cfg(cfg, target_vault)
+    if Path(target_cfg["vault"]["path"]) == Path(cfg["vault"]["path"]).resolve():
+        raise ValidationError("Capture is already in that vault")
+    target_db = get_main_db(target_cfg)
+
+    source = _get_writer(cfg)
+    target = _get_writer(target_cfg)
+    source_file = _capture_file(db, capture_id, source)
+    if not source_file.exists():
+        raise NotFoundError(f"Capture file {source_file} not found")
+
+    target_file = _unique_path(target.capture_dir / source_file.name)
+    new_id = capture_id
+    if target.filename_pattern == DEFAULT_FILENAME_PATTERN:
+        # File names are ids here, so a suffixed name means a suffixed id
+        new_id = target_file.stem
+
+    media_moves: Dict[str, str] = {}
+    for media_path in db.get_media_paths(capture_id):
+        old = Path(media_path)
+        if old.exists() and source.media_dir in old.parents:
+            media_moves[str(old)] = str(_unique_path(target.media_dir / old.name))
+
+    frontmatter, body = source.split_frontmatter(
+        source_file.read_text(encoding="utf-8")
+    )
+    for old, new in media_moves.items():
+        body = body.replace(old, new)
+        body = body.replace(
+            source.get_relative_media_path(old), target.get_relative_media_path(new)
         )
-        if result.returncode == 0:
-            return {"content": result.stdout, "type": "text"}
-        return {"content": "", "type": "text"}
-    except Exception:
-        return {"content": "", "type": "text"}
+        body = body.replace(f"[[{Path(old).name}]]", f"[[{Path(new).name}]]")
+    if new_id != capture_id:
+        frontmatter["id"] = frontmatter["capture_id"] = new_id
+        frontmatter["aliases"] = _union(
+            [new_id], frontmatter.get("aliases"), [capture_id]
+        )
+    text = target.render_file(frontmatter, body)
 
+    moved: List[tuple] = []
 
-@app.post("/api/screenshot")
-def api_screenshot():
-    """Trigger grim screenshot capture."""
+    def move_files():
+        try:
+            for old, new in media_moves.items():
+                shutil.move(old, new)
+                moved.append((old, new))
+            target.atomic_write(target_file, text)
+        except OSError as e:
+            raise StorageError(f"Failed to move capture files: {e}") from e
+
+    imported = False
     try:
-        timestamp = datetime.now().strftime("%Y%m%d_%H%M%S_%f")[:-3]
-        cfg = normalize_config(load_config(_config_path))
-        media_dir = Path(cfg["vault"]["path"]).expanduser() / cfg["vault"]["media_dir"]
-        media_dir.mkdir(parents=True, exist_ok=True)
-        screenshot_path = media_dir / f"{timestamp}_screenshot.png"
+        if target_db is db:
+            db.move_capture(
+                capture_id,
+                new_id,
+                str(target_file),
+                media_moves,
+                before_commit=move_files,
+            )
+        else:
+            # The target vault has its own database: copy the rows over, then
+            # drop them from this one
+            rows = db.export_capture(capture_id)
+            for media_file in rows["media_files"]:
+                path = media_file["file_path"]
+                media_file["file_path"] = media_moves.get(path, path)
+            target_db.import_capture(
+                rows, new_id, str(target_file), before_commit=move_files
+            )
+            imported = True
+            db.delete_capture(capture_id)
+    except KmsError:
+        if imported:
+            target_db.delete_capture(new_id)
+        for old, new in reversed(moved):
+            shutil.move(new, old)
+        target_file.unlink(missing_ok=True)
+        raise
 
-        # grimblast --notify  --freeze save area - > {screenshot path}
-        import os
-
-        result = os.system(
-            f"grimblast --notify --freeze save area - > {screenshot_path}"
-        )
-        # result = subprocess.run(
-        #     [
-        #         "grimblast",
-        #         "--notify",
-        #         "--freeze",
-        #         "save",
-        #         "-",
-        #         ">",
-        #         str(screenshot_path),
-        #     ],
-        #     capture_output=True,
-        #     timeout=50,
-        # )
+    source_file.unlink(missing_ok=True)
+    target_db.record_file_states([new_id])
+    return {"capture_id": new_id, "file_path": str(target_file)}
 
-        if result == 0:
-            return {"path": str(screenshot_path), "success": True}
-        return {"success": False, "error": "Screenshot failed"}
-    except Exception as e:
-        return {"success": False, "error": str(e)}
 
+def _archive_dir(cfg) -> Path:
+    return Path(cfg["vault"]["path"]).expanduser() / cfg["vault"]["archive_dir"]
 
-def _validate_modalities_have_content(capture_data, modalities):
-    """Validate that selected modalities have actual content."""
-    if not modalities:
-        return False
 
-    for modality in modalities:
-        if modality == "text":
-            content = capture_data.get("content", "").strip()
-            if not content:
-                return False
-        elif modality == "clipboard":
-            pass
-        elif modality == "screenshot":
-            if not capture_data.get("media_files"):
-                return False
-        elif modality == "audio":
-            if not capture_data.get("media_files"):
-                return False
-        elif modality == "system-audio":
-            if not capture_data.get("media_files"):
-                return False
+@app.post("/api/archive")
+def api_archive(request: Request, before: str, force: bool = False):
+    """Move captures taken before `before` (a date or RFC 3339 time) into
+    vault.archive_dir and mark them archived. Locked captures are skipped
+    unless force=true; nothing is deleted."""
+    cutoff = _parse_capture_timestamp(before)
+    if cutoff is None:
+        try:
+            cutoff = datetime.strptime(before.strip(), "%Y-%m-%d").replace(
+                tzinfo=timezone.utc
+            )
+        except ValueError:
+            raise ValidationError(f"Invalid before date {before!r}")
 
-    return True
+    cfg, db = _request_vault(request)
+    writer = _get_writer(cfg)
+    archive_dir = _archive_dir(cfg)
+    archive_dir.mkdir(parents=True, exist_ok=True)
 
+    archived: List[str] = []
+    failed = []
+    for capture_id in db.captures_before(cutoff, include_locked=force):
+        source_file = _capture_file(db, capture_id, writer)
+        if not source_file.exists():
+            failed.append({"capture_id": capture_id, "error": "File not found"})
+            continue
+        target_file = _unique_path(archive_dir / source_file.name)
+        frontmatter, body = writer.split_frontmatter(
+            source_file.read_text(encoding="utf-8")
+        )
+        frontmatter["processing_status"] = "archived"
+        # Relative media links must still resolve from the archive folder
+        for media_path in db.get_media_paths(capture_id):
+            old_link = os.path.relpath(media_path, source_file.parent)
+            new_link = os.path.relpath(media_path, target_file.parent)
+            body = body.replace(f"]({old_link})", f"]({new_link})")
+        text = writer.render_file(frontmatter, body)
+        try:
+            db.archive_capture(
+                capture_id,
+                str(target_file),
+                before_commit=lambda: writer.atomic_write(target_file, text),
+            )
+        except KmsError as e:
+            target_file.unlink(missing_ok=True)
+            failed.append({"capture_id": capture_id, "error": str(e)})
+            continue
+        source_file.unlink(missing_ok=True)
+        archived.append(capture_id)
 
-@app.post("/api/capture")
-async def api_capture(
-    content: str = Form(""),
-    context: str = Form(""),
-    tags: str = Form(""),
-    sources: str = Form(""),
-    alias: str = Form(""),
-    capture_id: str = Form(""),
-    modalities: str = Form(""),
-    clipboard: str = Form(""),
-    screenshot_path: str = Form(""),
-    screenshot_type: str = Form(""),
-    created_date: Optional[str] = Form(None),
-    last_edited_date: Optional[str] = Form(None),
-    media: Optional[List[UploadFile]] = File(None),
-):
-    cfg = normalize_config(load_config(_config_path))
-    writer = SafeMarkdownWriter(str(Path(cfg["vault"]["path"]).expanduser()))
-    ts = datetime.now(timezone.utc)
-    cds = created_date or ts.date().isoformat()
-    les = last_edited_date or ts.date().isoformat()
-    tag_list = (
-        [t.strip() for t in tags.split(",") if t.strip()]
-        if isinstance(tags, str)
-        else []
-    )
-    src_list = (
-        [s.strip() for s in sources.split(",") if s.strip()]
-        if isinstance(sources, str)
-        else []
-    )
-    mod_list = (
-        [m.strip() for m in modalities.split(",") if m.strip()]
-        if isinstance(modalities, str)
-        else []
-    )
-    ctx = context.strip() if context.strip() else ""
-    files_meta = []
-    if media:
-        media_dir = Path(cfg["vault"]["path"]).expanduser() / cfg["vault"]["media_dir"]
-        media_dir.mkdir(parents=True, exist_ok=True)
-        for f in media:
-            name = f.filename or f"upload_{datetime.now().timestamp()}"
-            dest = media_dir / name
-            b = await f.read()
-            dest.write_bytes(b)
-            files_meta.append({"path": str(dest), "name": name})
+    db.record_file_states(archived)
+    return {"moved": len(archived), "capture_ids": archived, "failed": failed}
 
-    if screenshot_path and screenshot_type:
-        files_meta.append({"path": screenshot_path, "type": screenshot_type})
-    location_data = get_device_location()
 
-    # Use provided capture_id if available, otherwise generate a new one using timestamp
-    actual_capture_id = capture_id.strip() if capture_id.strip() else ts.isoformat()
-    
-    # Handle alias - if provided, add it to the aliases list
-    aliases = []
-    if alias.strip():
-        aliases.append(alias.strip())
-    
-    # Initialize capture data
-    capture = {
-        "timestamp": ts,
-        "content": content or "",
-        "clipboard": clipboard or "",
-        "context": ctx,
-        "tags": tag_list,
-        "modalities": mod_list or ["text"],
-        "sources": src_list,
-        "location": location_data,
-        "media_files": files_meta,
-        "created_date": cds,
-        "last_edited_date": les,
-        "capture_id": actual_capture_id,
-        "aliases": aliases
+def _is_reindex_excluded(rel_path: Path, patterns: List[str]) -> bool:
+    """Match directory/file names (e.g. "templates") or globs ("archive/*")."""
+    rel = rel_path.as_posix()
+    for pattern in patterns:
+        pattern = str(pattern).rstrip("/")
+        if fnmatch.fnmatch(rel, pattern):
+            return True
+        if any(fnmatch.fnmatch(part, pattern) for part in rel_path.parts):
+            return True
+    return False
+
+
+@app.post("/api/reindex")
+def api_reindex(request: Request):
+    """Rebuild the database rows for every capture file in the vault."""
+    cfg, db = _request_vault(request)
+    writer = _get_writer(cfg)
+    patterns = cfg["vault"]["reindex_exclude"]
+    # The archive may sit inside the capture dir; its captures are not live
+    archive_dir = _archive_dir(cfg).resolve()
+
+    indexed = 0
+    skipped_excluded = 0
+    failed = []
+    for idea_file in sorted(writer.capture_dir.rglob("*.md")):
+        rel_path = idea_file.relative_to(writer.capture_dir)
+        archived = archive_dir in idea_file.resolve().parents
+        if archived or _is_reindex_excluded(rel_path, patterns):
+            skipped_excluded += 1
+            continue
+        try:
+            _store_capture(db, writer, writer.read_capture(idea_file))
+            indexed += 1
+        except Exception as e:
+            failed.append({"file": rel_path.as_posix(), "error": str(e)})
+
+    return {
+        "indexed": indexed,
+        "skipped_excluded": skipped_excluded,
+        "failed": failed,
     }
 
-    if not _validate_modalities_have_content(capture, mod_list):
+
+@app.post("/api/reindex/selected")
+def api_reindex_selected(request: Request, payload: Dict[str, Any] = Body(...)):
+    """Re-read just the given captures' files into the database.
+
+    Body: {"capture_ids": [...]}, e.g. the ids reported by /api/captures/drift.
+    """
+    capture_ids = _clean_values(payload.get("capture_ids"))
+    if not capture_ids:
+        raise ValidationError("capture_ids is required")
+    cfg, db = _request_vault(request)
+    writer = _get_writer(cfg)
+
+    indexed = []
+    failed = []
+    for capture_id in capture_ids:
+        idea_file = _capture_file(db, capture_id, writer)
+        if not idea_file.exists():
+            failed.append({"capture_id": capture_id, "error": "File not found"})
+            continue
+        try:
+            _store_capture(db, writer, writer.read_capture(idea_file))
+            indexed.append(capture_id)
+        except Exception as e:
+            failed.append({"capture_id": capture_id, "error": str(e)})
+    return {"indexed": indexed, "failed": failed}
+
+
+@app.post("/api/search/reindex")
+def api_search_reindex(request: Request):
+    """Rebuild the full-text index from the captures table, e.g. after bulk imports."""
+    _, db = _request_vault(request)
+    started = time.perf_counter()
+    rows = db.rebuild_fts()
+    return {
+        "rows": rows,
+        "duration_ms": round((time.perf_counter() - started) * 1000, 1),
+    }
+
+
+@app.get("/api/sources/{value:path}/meta")
+def api_source_meta(value: str, refresh: bool = False):
+    """Title and favicon for a URL source, fetched once and cached with a TTL."""
+    if not is_url(value):
+        return JSONResponse({"error": "Source is not a URL"}, status_code=400)
+
+    cfg = normalize_config(load_config(_config_path))
+    ttl_hours = float((cfg.get("sources") or {}).get("meta_ttl_hours", 24 * 7))
+    db = get_main_db()
+    cached = db.get_source_meta(value)
+    if cached and not refresh:
+        fetched_at = datetime.fromisoformat(cached["fetched_at"])
+        age_hours = (datetime.now(timezone.utc) - fetched_at).total_seconds() / 3600
+        if age_hours < ttl_hours:
+            return cached
+
+    try:
+        meta = fetch_source_meta(value, _data_dir() / "favicons")
+    except Exception as e:
+        if cached:
+            return {**cached, "stale": True}
         return JSONResponse(
-            {"error": "No content provided for selected modalities"}, status_code=400
+            {"error": f"Failed to fetch source metadata: {e}"}, status_code=502
         )
 
-    p = writer.write_capture(capture)
+    db.store_source_meta(value, meta)
+    return db.get_source_meta(value)
 
-    get_main_db().store_capture_data(capture)
 
-    import os
+def _value_colors(cfg, field_type: str) -> Tuple[Dict[str, str], List[str]]:
+    """(overrides, palette) for suggestion colors: ui.colors maps values to hex
+    colors, with ui.tag_colors winning for tags; other values hash onto
+    ui.color_palette, or onto a hue when it is unset."""
+    ui = cfg.get("ui") or {}
+    colors = dict(ui.get("colors") or {})
+    if field_type == "tag":
+        colors.update(ui.get("tag_colors") or {})
+    palette = [c for c in map(_validate_color, ui.get("color_palette") or []) if c]
+    return {value: _validate_color(c) for value, c in colors.items()}, palette
 
-    file_exists = os.path.exists(p) if p else False
 
-    try:
-        # Store the last used tags and sources in the database for persistence
-        # Distinguish between AI-suggested and user-added tags/sources
-        global _ai_suggested_tags, _ai_suggested_sources
+def _suggestion_list(cfg, field_type: str, query: str, limit: int):
+    """Suggestions as JSON rows. Non-empty queries shorter than
+    suggestions.min_query_len return nothing without touching the database."""
+    suggestion_cfg = cfg.get("suggestions") or {}
+    min_query_len = int(suggestion_cfg.get("min_query_len", 0))
+    if query.strip() and len(query.strip()) < min_query_len:
+        return []
+    colors, palette = _value_colors(cfg, field_type)
+    suggestions = get_main_db(cfg).get_suggestions(
+        field_type,
+        query,
+        limit,
+        scoring=suggestion_cfg.get("scoring", "count"),
+        half_life_days=float(suggestion_cfg.get("half_life_days", 30)),
+        colors=colors,
+        palette=palette,
+    )
+    return [
+        {
+            "value": s.value,
+            "count": s.count,
+            "last_used": s.last_used.isoformat(),
+            "color": s.color,
+        }
+        for s in suggestions
+    ]
 
-        # Find which tags were AI-suggested vs user-added
-        user_tags = [tag for tag in tag_list if tag not in _ai_suggested_tags]
-        user_sources = [
-            source for source in src_list if source not in _ai_suggested_sources
-        ]
 
-        # Store both sets separately
-        get_main_db().store_last_used_values(
-            {"tags": user_tags, "sources": user_sources},
-            {
-                "tags": [
-                    tag for tag in tag_list if tag in _ai_suggested_tags
-                ],  # Only keep AI tags that were actually used
-                "sources": [
-                    source for source in src_list if source in _ai_suggested_sources
-                ],  # Only keep AI sources that were actually used
-            },
-        )
+@app.get("/api/suggestions")
+def api_suggestions_combined(
+    request: Request,
+    query: str = "",
+    tag_limit: Optional[int] = None,
+    source_limit: Optional[int] = None,
+    context_limit: Optional[int] = None,
+):
+    """Tag, source and context suggestions in one call, each with its own limit.
 
-        # Return a properly formatted JSON response
-        return {"saved_to": str(p), "verified": file_exists}
-    except Exception as e:
-        # Return a properly formatted JSON error response
-        return JSONResponse({"error": f"Save failed: {str(e)}"}, status_code=500)
+    Unset limits fall back to `suggestions.default_limits.<field>`, then 10.
+    """
+    cfg, error = _apply_vault_override(
+        request, normalize_config(load_config(_config_path))
+    )
+    if error:
+        return error
+    default_limits = (cfg.get("suggestions") or {}).get("default_limits") or {}
+    limits = {"tag": tag_limit, "source": source_limit, "context": context_limit}
+    result = {}
+    for field_type, limit in limits.items():
+        if limit is None:
+            limit = int(default_limits.get(field_type, 10))
+        if limit < 0:
+            raise ValidationError(f"{field_type}_limit must not be negative")
+        result[field_type] = _suggestion_list(cfg, field_type, query, limit)
+    return result
 
 
 @app.get("/api/suggestions/{field_type}")
-def api_suggestions(field_type: str, query: str = "", limit: int = 10):
-    if field_type not in ["tag", "source", "context"]:
+def api_suggestions(
+    request: Request, field_type: str, query: str = "", limit: int = 10
+):
+    if field_type not in ["tag", "source", "context", "modality", "collection"]:
         return JSONResponse({"error": "Invalid field type"}, status_code=400)
-    suggestions = get_main_db().get_suggestions(field_type, query, limit)
+    cfg, error = _apply_vault_override(
+        request, normalize_config(load_config(_config_path))
+    )
+    if error:
+        return error
+    return {"suggestions": _suggestion_list(cfg, field_type, query, limit)}
+
+
+@app.get("/api/suggestions/{field_type}/debug")
+def api_suggestions_debug(
+    field_type: str,
+    query: str = "",
+    scoring: str = "",
+    half_life_days: Optional[float] = None,
+    now: str = "",
+):
+    """Every candidate for `query` with its score components, for tuning ranking.
+
+    `scoring`/`half_life_days` override the configured values and `now` (RFC 3339)
+    pins the clock so a reported ordering can be reproduced.
+    """
+    if field_type not in ["tag", "source", "context", "modality"]:
+        return JSONResponse({"error": "Invalid field type"}, status_code=400)
+    if not query.strip():
+        return JSONResponse({"error": "query is required"}, status_code=400)
+    cfg = normalize_config(load_config(_config_path))
+    suggestion_cfg = cfg.get("suggestions") or {}
+    scoring = scoring or suggestion_cfg.get("scoring", "count")
+    if half_life_days is None:
+        half_life_days = float(suggestion_cfg.get("half_life_days", 30))
+    candidates = get_main_db().debug_suggestions(
+        field_type,
+        query,
+        scoring=scoring,
+        half_life_days=half_life_days,
+        now=_parse_capture_timestamp(now),
+    )
     return {
-        "suggestions": [
-            {
-                "value": s.value,
-                "count": s.count,
-                "last_used": s.last_used.isoformat(),
-                "color": s.color,
-            }
-            for s in suggestions
-        ]
+        "query": query,
+        "scoring": scoring,
+        "half_life_days": half_life_days,
+        "candidates": candidates,
     }
 
 
 @app.get("/api/suggestion-exists/{field_type}")
 def api_suggestion_exists(field_type: str, value: str):
     """Check if a suggestion value exists in the database."""
-    if field_type not in ["tag", "source", "context"]:
+    if field_type not in ["tag", "source", "context", "modality"]:
         return JSONResponse({"error": "Invalid field type"}, status_code=400)
 
     exists = get_main_db().suggestion_exists(value, field_type)
     return {"exists": exists}
 
 
+@app.post("/api/suggestion-exists/{field_type}")
+def api_suggestions_exist(field_type: str, payload: Dict[str, Any] = Body(...)):
+    """Check several values at once: {"values": [...]} -> {"exists": {value: bool}}."""
+    if field_type not in ["tag", "source", "context", "modality"]:
+        raise ValidationError("Invalid field type")
+    values = payload.get("values")
+    if not isinstance(values, list) or not all(isinstance(v, str) for v in values):
+        raise ValidationError("values must be a list of strings")
+    return {"exists": get_main_db().suggestions_exist(values, field_type)}
+
+
 @app.post("/api/ai-suggestions/feedback")
 async def api_ai_suggestions_feedback(
     field_type: str = Form(...),
@@ -498,24 +3958,40 @@ async def api_ai_suggestions_feedback(
 
 
 @app.get("/api/recent-values")
-def api_recent_values():
+def api_recent_values(request: Request):
     """Get the most recent values for field restoration."""
-    recent_values = get_main_db().get_most_recent_values()
+    cfg, error = _apply_vault_override(
+        request, normalize_config(load_config(_config_path))
+    )
+    if error:
+        return error
+    recent_values = get_main_db(cfg).get_most_recent_values()
     return {"recent_values": recent_values}
 
 
+# Recorder ids become part of the saved file name
+RECORDER_ID_RE = re.compile(r"^[A-Za-z0-9_-]+$")
+
+
 @app.post("/api/audio/start")
-def api_audio_start(recorder_type: str = Form(...), recorder_id: str = Form(...)):
-    """Start audio recording."""
+def api_audio_start(recorder_type: str = Form(...), recorder_id: str = Form("")):
+    """Start audio recording; without a recorder_id a unique one is generated."""
     if not AUDIO_RECORDING_AVAILABLE or not audio_manager:
         return JSONResponse({"error": "Audio recording is not available"}, status_code=503)
-    
+
+    recorder_id = recorder_id.strip() or f"{recorder_type}_{uuid.uuid4().hex[:12]}"
+    if not RECORDER_ID_RE.match(recorder_id):
+        raise ValidationError(
+            f"Invalid recorder_id {recorder_id!r}; use letters, digits, _ and -"
+        )
     if not audio_manager.create_recorder(recorder_type, recorder_id):
         if recorder_id in audio_manager.recorders:
             return JSONResponse({"error": "Recorder already exists"}, status_code=400)
         return JSONResponse({"error": "Invalid recorder type"}, status_code=400)
 
     if not audio_manager.start_recording(recorder_id):
+        # Free the id so the client can retry
+        audio_manager.cleanup_recorder(recorder_id)
         return JSONResponse({"error": "Failed to start recording"}, status_code=500)
 
     return {"status": "recording_started", "recorder_id": recorder_id}
@@ -523,12 +3999,15 @@ def api_audio_start(recorder_type: str = Form(...), recorder_id: str = Form(...)
 
 @app.post("/api/audio/stop")
 def api_audio_stop(recorder_id: str = Form(...)):
-    """Stop audio recording and save file."""
+    """Stop one recorder and save its own file; other recorders keep running."""
     if not AUDIO_RECORDING_AVAILABLE or not audio_manager:
         return JSONResponse({"error": "Audio recording is not available"}, status_code=503)
-    
+
+    if recorder_id not in audio_manager.recorders:
+        raise NotFoundError(f"No recorder {recorder_id!r}")
     if not audio_manager.stop_recording(recorder_id):
-        return JSONResponse({"error": "Failed to stop recording"}, status_code=500)
+        audio_manager.cleanup_recorder(recorder_id)
+        raise ConflictError(f"Recorder {recorder_id!r} is not recording")
 
     timestamp = datetime.now().strftime("%Y%m%d_%H%M%S_%f")[:-3]
     filename = f"audio_{recorder_id}_{timestamp}.wav"
@@ -538,15 +4017,18 @@ def api_audio_stop(recorder_id: str = Form(...)):
     )
     filepath.parent.mkdir(parents=True, exist_ok=True)
 
-    if not audio_manager.save_recording(recorder_id, filepath):
-        return JSONResponse({"error": "Failed to save recording"}, status_code=500)
-
+    saved = audio_manager.save_recording(recorder_id, filepath)
     audio_manager.cleanup_recorder(recorder_id)
+    if not saved:
+        return JSONResponse({"error": "Failed to save recording"}, status_code=500)
 
     return {
         "status": "recording_saved",
+        "recorder_id": recorder_id,
         "filename": filename,
         "filepath": str(filepath),
+        # Pass filepath as audio_paths on /api/capture to attach it
+        "media_file": {"path": str(filepath), "type": "audio", "name": filename},
     }
 
 
@@ -681,9 +4163,19 @@ def api_ai_suggestions(field_type: str, content: str = "", limit: int = 10):
     return {"ai": ai_items, "content_hash": h}
 
 
+@app.get("/api/audio/recorders")
+def api_audio_recorders():
+    """Status of every active recorder, keyed by recorder_id."""
+    if not AUDIO_RECORDING_AVAILABLE or not audio_manager:
+        return {"recorders": {}}
+    return {"recorders": audio_manager.list_recorders()}
+
+
 @app.get("/api/audio/status/{recorder_id}")
 def api_audio_status(recorder_id: str):
     """Get audio recording status."""
+    if not AUDIO_RECORDING_AVAILABLE or not audio_manager:
+        return {"exists": False}
     status = audio_manager.get_recording_status(recorder_id)
     return status
 
@@ -747,6 +4239,152 @@ def api_ai_health():
 
 if web_dist_path.exists():
     app.mount("/", StaticFiles(directory=str(web_dist_path), html=True), name="static")
+
+
+def _worker_threads(cfg) -> Optional[int]:
+    """Thread cap for sync handlers from KMS_WORKER_THREADS or server.worker_threads.
+
+    None keeps anyio's default pool size.
+    """
+    value = os.environ.get("KMS_WORKER_THREADS") or (cfg.get("server") or {}).get(
+        "worker_threads"
+    )
+    try:
+        threads = int(value) if value else 0
+    except ValueError:
+        print(f"⚠️  Ignoring invalid worker thread count {value!r}")
+        return None
+    return threads if threads > 0 else None
+
+
+def _bind_host(cfg) -> str:
+    """Address to listen on from server.host (default loopback only).
+
+    Binding anything else requires explicit server.cors_origins (without "*")
+    and server.rate_limit_per_minute, so a misconfigured daemon is never open.
+    """
+    server_cfg = cfg.get("server") or {}
+    host = str(server_cfg.get("host") or "127.0.0.1")
+    if host in LOOPBACK_HOSTS:
+        return host
+    origins = server_cfg.get("cors_origins") or []
+    if not origins or "*" in origins:
+        raise ValueError(
+            f"server.host {host!r} is not loopback; set server.cors_origins to the "
+            "allowed origins (\"*\" is not allowed)"
+        )
+    if not server_cfg.get("rate_limit_per_minute"):
+        raise ValueError(
+            f"server.host {host!r} is not loopback; set server.rate_limit_per_minute"
+        )
+    return host
+
+
+def _configure_http(cfg):
+    """CORS and rate limiting from the server config (must run before serving)."""
+    global rate_limiter
+    server_cfg = cfg.get("server") or {}
+    app.add_middleware(
+        CORSMiddleware,
+        allow_origins=server_cfg.get("cors_origins") or ["*"],
+        allow_credentials=True,
+        allow_methods=["*"],
+        allow_headers=["*"],
+    )
+    if server_cfg.get("rate_limit_per_minute"):
+        rate_limiter = RateLimiter(int(server_cfg["rate_limit_per_minute"]))
+
+
+class _SocketRequest:
+    """Stand-in for the HTTP request: no headers, so no vault override."""
+
+    headers: Dict[str, str] = {}
+    client = None
+
+
+async def _socket_capture(payload: Dict[str, Any]) -> Dict[str, Any]:
+    """Run one socket capture request through api_capture.
+
+    Fields are the api_capture form fields; tags, sources and modalities may
+    also be JSON lists. Media uploads are not supported over the socket.
+    """
+    if not isinstance(payload, dict):
+        return {"ok": False, "error": "Request must be a JSON object"}
+    try:
+        result = await _call_api_capture(_SocketRequest(), payload)
+    except KmsError as e:
+        return {"ok": False, "error": str(e)}
+    if isinstance(result, Response):
+        body = result.body
+        body = json.loads(body) if isinstance(body, bytes) else body
+        return {"ok": False, **body}
+    return {"ok": True, **result}
+
+
+async def _handle_socket_client(
+    reader: asyncio.StreamReader, writer: asyncio.StreamWriter
+):
+    try:
+        while line := await reader.readline():
+            if not line.strip():
+                continue
+            try:
+                response = await _socket_capture(json.loads(line))
+            except json.JSONDecodeError as e:
+                response = {"ok": False, "error": f"Invalid JSON: {e}"}
+            writer.write(json.dumps(response).encode() + b"\n")
+            await writer.drain()
+    finally:
+        writer.close()
+
+
+async def _start_unix_socket(path: str) -> asyncio.AbstractServer:
+    """Listen for newline-delimited JSON captures on server.unix_socket."""
+    socket_path = Path(path).expanduser()
+    socket_path.parent.mkdir(parents=True, exist_ok=True)
+    if socket_path.is_socket():
+        socket_path.unlink()
+    server = await asyncio.start_unix_server(
+        _handle_socket_client, path=str(socket_path)
+    )
+    socket_path.chmod(0o600)
+    print(f"Accepting captures on {socket_path}")
+    return server
+
+
+DEFAULT_EXPIRY_SWEEP_SECONDS = 300
+
+
+async def _expiry_sweeper(interval: float):
+    while True:
+        try:
+            await asyncio.to_thread(_sweep_expired)
+        except Exception as e:
+            print(f"⚠️  Expiry sweep failed: {e}")
+        await asyncio.sleep(interval)
+
+
+async def _serve(config: Config, cfg):
+    worker_threads = _worker_threads(cfg)
+    if worker_threads:
+        limiter = anyio.to_thread.current_default_thread_limiter()
+        limiter.total_tokens = worker_threads
+        print(f"Using {worker_threads} worker threads")
+    unix_socket = (cfg.get("server") or {}).get("unix_socket")
+    if unix_socket:
+        await _start_unix_socket(unix_socket)
+    # capture.expiry_sweep_seconds: 0 turns the sweeper off
+    interval = float(
+        cfg["capture"].get("expiry_sweep_seconds", DEFAULT_EXPIRY_SWEEP_SECONDS)
+    )
+    sweeper = asyncio.create_task(_expiry_sweeper(interval)) if interval > 0 else None
+    try:
+        await serve(app, config)
+    finally:
+        if sweeper:
+            sweeper.cancel()
+
+
 if __name__ == "__main__":
     parser = argparse.ArgumentParser(description="Knowledge Management System Server")
     parser.add_argument("--config", type=str, help="Path to config file")
@@ -760,14 +4398,25 @@ if __name__ == "__main__":
     db_dir = Path(db_path).parent
     db_dir.mkdir(parents=True, exist_ok=True)
 
-    main_db = MainDatabase(db_path)
+    main_db = _open_main_db(cfg)
 
     if cfg.get("is_dev"):
         print("🚧 RUNNING IN DEVELOPMENT MODE 🚧")
 
+    try:
+        host = _bind_host(cfg)
+        vault_problem = _check_vault_path(cfg)
+    except (ValueError, KmsError) as e:
+        sys.exit(f"Refusing to start: {e}")
+    if vault_problem:
+        print(f"⚠️  {vault_problem}")
+    _configure_http(cfg)
+
     config = Config()
-    config.bind = [f"0.0.0.0:{int(os.environ.get('PORT', '7123'))}"]
+    if ":" in host:
+        host = f"[{host}]"
+    config.bind = [f"{host}:{int(os.environ.get('PORT', '7123'))}"]
     config.use_reloader = False
     config.accesslog = "-"
 
-    asyncio.run(serve(app, config))
+    asyncio.run(_serve(config, cfg))
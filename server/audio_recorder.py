@@ -21,10 +21,22 @@ class AudioRecorder(ABC):
         self.audio_data: list = []
         self.waveform_callback: Optional[Callable[[list], None]] = None
         self.recording_thread: Optional[threading.Thread] = None
+        self.started_at: Optional[float] = None
+        self.stopped_at: Optional[float] = None
 
     def set_waveform_callback(self, callback: Callable[[list], None]):
         self.waveform_callback = callback
 
+    @property
+    def duration_seconds(self) -> float:
+        if self.started_at is None:
+            return 0.0
+        return (self.stopped_at or time.monotonic()) - self.started_at
+
+    @property
+    def samples_collected(self) -> int:
+        return sum(len(chunk) for chunk in list(self.audio_data))
+
     @abstractmethod
     def _get_device_info(self) -> Dict[str, Any]:
         pass
@@ -44,6 +56,8 @@ class AudioRecorder(ABC):
 
             self.is_recording = True
             self.audio_data = []
+            self.started_at = time.monotonic()
+            self.stopped_at = None
             self.recording_thread = threading.Thread(target=self._record_audio)
             self.recording_thread.start()
             return True
@@ -57,6 +71,7 @@ class AudioRecorder(ABC):
             return False
 
         self.is_recording = False
+        self.stopped_at = time.monotonic()
         if self.recording_thread:
             self.recording_thread.join()
 
@@ -102,6 +117,7 @@ class AudioRecorder(ABC):
         except Exception as e:
             print(f"Recording error: {e}")
             self.is_recording = False
+            self.stopped_at = time.monotonic()
 
     def _calculate_waveform(self, audio_chunk: np.ndarray) -> list:
         if len(audio_chunk.shape) > 1:
@@ -179,30 +195,38 @@ class SystemAudioRecorder(AudioRecorder):
         }
 
 
+RECORDER_TYPES = {
+    "microphone": MicrophoneRecorder,
+    "system": SystemAudioRecorder,
+}
+
+
 class AudioRecordingManager:
+    """Registry of recorders by id; several can record at once (e.g. mic + system)."""
+
     def __init__(self):
         self.recorders: Dict[str, AudioRecorder] = {}
+        self.recorder_types: Dict[str, str] = {}
         self.websocket_connections: Dict[str, set] = {}
         self.loop = None  # Will be set when first WebSocket connects
+        # Request handlers run on a thread pool, so registry changes are locked
+        self._lock = threading.Lock()
 
     def create_recorder(self, recorder_type: str, recorder_id: str) -> bool:
-        if recorder_id in self.recorders:
+        recorder_class = RECORDER_TYPES.get(recorder_type)
+        if recorder_class is None:
             return False
 
-        recorder: AudioRecorder
-        if recorder_type == "microphone":
-            recorder = MicrophoneRecorder()
-        elif recorder_type == "system":
-            recorder = SystemAudioRecorder()
-        else:
-            return False
-
-        recorder.set_waveform_callback(
-            lambda waveform: self._broadcast_waveform(recorder_id, waveform)
-        )
-
-        self.recorders[recorder_id] = recorder
-        self.websocket_connections[recorder_id] = set()
+        with self._lock:
+            if recorder_id in self.recorders:
+                return False
+            recorder = recorder_class()
+            recorder.set_waveform_callback(
+                lambda waveform: self._broadcast_waveform(recorder_id, waveform)
+            )
+            self.recorders[recorder_id] = recorder
+            self.recorder_types[recorder_id] = recorder_type
+            self.websocket_connections[recorder_id] = set()
         return True
 
     def start_recording(self, recorder_id: str) -> bool:
@@ -227,11 +251,22 @@ class AudioRecordingManager:
         recorder = self.recorders[recorder_id]
         return {
             "exists": True,
+            "type": self.recorder_types.get(recorder_id),
             "is_recording": recorder.is_recording,
+            "duration_seconds": round(recorder.duration_seconds, 2),
+            "samples_collected": recorder.samples_collected,
             "sample_rate": recorder.sample_rate,
             "channels": recorder.channels,
         }
 
+    def list_recorders(self) -> Dict[str, Dict[str, Any]]:
+        with self._lock:
+            recorder_ids = list(self.recorders)
+        return {
+            recorder_id: self.get_recording_status(recorder_id)
+            for recorder_id in recorder_ids
+        }
+
     def add_websocket_connection(self, recorder_id: str, websocket):
         if recorder_id not in self.websocket_connections:
             self.websocket_connections[recorder_id] = set()
@@ -256,7 +291,7 @@ class AudioRecordingManager:
         )
 
         disconnected = set()
-        for websocket in self.websocket_connections[recorder_id]:
+        for websocket in list(self.websocket_connections.get(recorder_id, ())):
             try:
                 # Schedule the coroutine to run in the event loop
                 asyncio.run_coroutine_threadsafe(websocket.send_text(message), self.loop)
@@ -265,12 +300,13 @@ class AudioRecordingManager:
                 disconnected.add(websocket)
 
         for websocket in disconnected:
-            self.websocket_connections[recorder_id].discard(websocket)
+            self.remove_websocket_connection(recorder_id, websocket)
 
     def cleanup_recorder(self, recorder_id: str):
-        if recorder_id in self.recorders:
-            self.recorders[recorder_id].stop_recording()
-            del self.recorders[recorder_id]
-
-        if recorder_id in self.websocket_connections:
-            del self.websocket_connections[recorder_id]
+        """Stop and forget one recorder; the others keep recording."""
+        with self._lock:
+            recorder = self.recorders.pop(recorder_id, None)
+            self.recorder_types.pop(recorder_id, None)
+            self.websocket_connections.pop(recorder_id, None)
+        if recorder:
+            recorder.stop_recording()
@@ -1,10 +1,228 @@
 import sqlite3
 import json
-from datetime import datetime, timezone
+from collections import Counter
+from contextlib import contextmanager
+from datetime import datetime, timedelta, timezone
 from pathlib import Path
-from typing import List, Dict, Any, Optional
+from typing import List, Dict, Any, Optional, Callable, Set, Tuple
 from dataclasses import dataclass
+import colorsys
 import difflib
+import hashlib
+import math
+import re
+import threading
+import unicodedata
+
+from kms_errors import DatabaseError, ValidationError
+
+# Collections live in a captures column; this exposes them like the value tables
+COLLECTIONS_SQL = (
+    "(SELECT collection AS value, capture_id, timestamp FROM captures "
+    "WHERE collection IS NOT NULL AND collection != '')"
+)
+
+# [[target]], [[target|label]] or [[target#heading]]; ![[...]] embeds are media
+WIKILINK_RE = re.compile(r"(?<!!)\[\[([^\]|#]+)(?:[|#][^\]]*)?\]\]")
+MAX_GRAPH_NODES = 2000
+SNIPPET_LENGTH = 160
+
+
+def wikilink_targets(text: str) -> List[str]:
+    """Distinct [[wikilink]] targets in a capture's text, in order."""
+    return list(dict.fromkeys(m.strip() for m in WIKILINK_RE.findall(text or "")))
+
+
+# Schema setup and migrations (ALTER TABLE, FTS triggers, path rewrites) are not
+# safe to run concurrently, so opening a database is serialized process-wide
+_init_lock = threading.Lock()
+
+
+def _match_key(value: str) -> str:
+    """Normalize a value for case-insensitive, composition-insensitive matching."""
+    return unicodedata.normalize("NFC", value).casefold()
+
+
+def _extends_grapheme(char: str) -> bool:
+    """Whether a character continues the preceding grapheme cluster."""
+    return (
+        unicodedata.combining(char) != 0
+        or char == "\u200d"  # zero-width joiner
+        or "\ufe00" <= char <= "\ufe0f"  # variation selectors
+        or "\U0001f3fb" <= char <= "\U0001f3ff"  # emoji skin tone modifiers
+    )
+
+
+def _is_grapheme_boundary(text: str, index: int) -> bool:
+    if index <= 0 or index >= len(text):
+        return True
+    return not _extends_grapheme(text[index])
+
+
+def _grapheme_prefix(value: str, query: str) -> bool:
+    return value.startswith(query) and _is_grapheme_boundary(value, len(query))
+
+
+def _grapheme_contains(value: str, query: str) -> bool:
+    start = value.find(query)
+    while start != -1:
+        if _is_grapheme_boundary(value, start) and _is_grapheme_boundary(
+            value, start + len(query)
+        ):
+            return True
+        start = value.find(query, start + 1)
+    return False
+
+
+def _haversine_km(lat1: float, lon1: float, lat2: float, lon2: float) -> float:
+    """Great-circle distance between two points in kilometres."""
+    phi1, phi2 = math.radians(lat1), math.radians(lat2)
+    dphi = phi2 - phi1
+    dlambda = math.radians(lon2 - lon1)
+    a = (
+        math.sin(dphi / 2) ** 2
+        + math.cos(phi1) * math.cos(phi2) * math.sin(dlambda / 2) ** 2
+    )
+    return 2 * 6371.0 * math.asin(math.sqrt(a))
+
+
+def _file_state(file_path: str) -> Tuple[Optional[int], Optional[str]]:
+    """(mtime_ns, sha256) of a capture file, or (None, None) if it can't be read."""
+    try:
+        path = Path(file_path)
+        return path.stat().st_mtime_ns, hashlib.sha256(path.read_bytes()).hexdigest()
+    except OSError:
+        return None, None
+
+
+def _snippet(content: str, query: str, width: int = SNIPPET_LENGTH) -> str:
+    """About `width` characters of content around the first match of query."""
+    text = " ".join(content.split())
+    if len(text) <= width:
+        return text
+    start = max(text.lower().find(query.lower()) - width // 4, 0) if query else 0
+    snippet = text[start : start + width].strip()
+    if start:
+        snippet = "…" + snippet
+    if start + width < len(text):
+        snippet += "…"
+    return snippet
+
+
+def value_color(value: str, palette: Optional[List[str]] = None) -> str:
+    """Hex colour for a tag/source value, the same on every machine: a hash of
+    the value picks a palette entry, or without a palette a hue."""
+    digest = int.from_bytes(hashlib.sha256(value.encode("utf-8")).digest()[:4], "big")
+    if palette:
+        return palette[digest % len(palette)]
+    red, green, blue = colorsys.hls_to_rgb((digest % 360) / 360, 0.5, 0.65)
+    return "#{:02x}{:02x}{:02x}".format(
+        round(red * 255), round(green * 255), round(blue * 255)
+    )
+
+
+def _trigrams(text: str) -> Set[str]:
+    """Character trigrams of whitespace-normalised, lower-cased text."""
+    text = " ".join(text.lower().split())
+    if len(text) < 3:
+        return {text} if text else set()
+    return {text[i : i + 3] for i in range(len(text) - 2)}
+
+
+def _add_importance(conn):
+    MainDatabase._ensure_column(conn, "captures", "importance", "REAL")
+
+
+def _add_locked(conn):
+    MainDatabase._ensure_column(
+        conn, "captures", "locked", "INTEGER NOT NULL DEFAULT 0"
+    )
+
+
+def _add_color(conn):
+    MainDatabase._ensure_column(conn, "captures", "color", "TEXT")
+
+
+def _add_processing_status(conn):
+    MainDatabase._ensure_column(
+        conn, "captures", "processing_status", "TEXT NOT NULL DEFAULT 'raw'"
+    )
+
+
+def _add_parent(conn):
+    MainDatabase._ensure_column(conn, "captures", "parent", "TEXT")
+    conn.execute("CREATE INDEX IF NOT EXISTS idx_captures_parent ON captures(parent)")
+
+
+def _add_priority_and_rating(conn):
+    MainDatabase._ensure_column(conn, "captures", "priority", "INTEGER")
+    MainDatabase._ensure_column(conn, "captures", "rating", "INTEGER")
+
+
+def _add_collection(conn):
+    MainDatabase._ensure_column(conn, "captures", "collection", "TEXT")
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_captures_collection ON captures(collection)"
+    )
+
+
+def _add_expires_at(conn):
+    MainDatabase._ensure_column(conn, "captures", "expires_at", "TEXT")
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_captures_expires_at ON captures(expires_at)"
+    )
+
+
+def _add_media_dimensions(conn):
+    """Screenshot/image metadata for the media gallery."""
+    MainDatabase._ensure_column(conn, "media_files", "width", "INTEGER")
+    MainDatabase._ensure_column(conn, "media_files", "height", "INTEGER")
+    MainDatabase._ensure_column(conn, "media_files", "output", "TEXT")
+
+
+def _add_file_state(conn):
+    """File state when last indexed, for drift detection."""
+    MainDatabase._ensure_column(conn, "captures", "file_mtime_ns", "INTEGER")
+    MainDatabase._ensure_column(conn, "captures", "file_sha256", "TEXT")
+
+
+# Schema migrations in order; PRAGMA user_version counts those applied, so only
+# append to this list. The columns after importance predate the list, so an older
+# database may already have some of them (_ensure_column skips those)
+MIGRATIONS = (
+    _add_importance,
+    _add_locked,
+    _add_color,
+    _add_processing_status,
+    _add_parent,
+    _add_priority_and_rating,
+    _add_collection,
+    _add_expires_at,
+    _add_media_dimensions,
+    _add_file_state,
+)
+
+
+def migrate(conn) -> int:
+    """Apply the migrations an existing database has not had yet and return its
+    schema version."""
+    version = conn.execute("PRAGMA user_version").fetchone()[0]
+    for number, migration in enumerate(MIGRATIONS[version:], start=version + 1):
+        migration(conn)
+        conn.execute(f"PRAGMA user_version = {number}")
+    return max(version, len(MIGRATIONS))
+
+
+# Per-capture value tables keyed by capture_id
+CAPTURE_CHILD_TABLES = (
+    "tags",
+    "sources",
+    "contexts",
+    "modalities",
+    "media_files",
+    "capture_fields",
+    "links",
+)
 
 
 @dataclass
@@ -18,9 +236,15 @@ class SuggestionItem:
 class MainDatabase:
     """Main database for comprehensive tracking of all capture data."""
 
-    def __init__(self, db_path: str = "main.db"):
+    def __init__(self, db_path: str = "main.db", media_root: Optional[str] = None):
+        """`media_root` (the vault) makes media paths under it stored relative to
+        it; without one they are stored as given."""
         self.db_path = Path(db_path)
-        self.init_database()
+        self.media_root = (
+            Path(media_root).expanduser().resolve() if media_root else None
+        )
+        with _init_lock:
+            self.init_database()
 
     def init_database(self):
         """Initialize the database with comprehensive tracking tables."""
@@ -79,6 +303,29 @@ class MainDatabase:
             """
             )
 
+            conn.execute(
+                """
+                CREATE TABLE IF NOT EXISTS modalities (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    value TEXT NOT NULL,
+                    capture_id TEXT NOT NULL,
+                    timestamp TEXT NOT NULL,
+                    FOREIGN KEY (capture_id) REFERENCES captures (capture_id)
+                )
+            """
+            )
+            if not conn.execute("SELECT 1 FROM modalities LIMIT 1").fetchone():
+                # Backfill from the JSON column for databases created before
+                # modalities had their own table
+                conn.execute(
+                    """
+                    INSERT INTO modalities (value, capture_id, timestamp)
+                    SELECT j.value, c.capture_id, c.timestamp
+                    FROM captures c, json_each(c.modalities) j
+                    WHERE json_valid(c.modalities) AND json_type(c.modalities) = 'array'
+                """
+                )
+
             conn.execute(
                 """
                 CREATE TABLE IF NOT EXISTS media_files (
@@ -93,6 +340,46 @@ class MainDatabase:
             """
             )
 
+            # [[wikilinks]] from each capture's text; targets are resolved to
+            # captures (by id or file name) when the graph is read
+            links_exist = conn.execute(
+                "SELECT 1 FROM sqlite_master WHERE name = 'links'"
+            ).fetchone()
+            conn.execute(
+                """
+                CREATE TABLE IF NOT EXISTS links (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    capture_id TEXT NOT NULL,
+                    target TEXT NOT NULL,
+                    FOREIGN KEY (capture_id) REFERENCES captures (capture_id)
+                )
+            """
+            )
+            if not links_exist:
+                conn.executemany(
+                    "INSERT INTO links (capture_id, target) VALUES (?, ?)",
+                    [
+                        (capture_id, target)
+                        for capture_id, content in conn.execute(
+                            "SELECT capture_id, content FROM captures"
+                        ).fetchall()
+                        for target in wikilink_targets(content)
+                    ],
+                )
+
+            # Custom fields; values are JSON so numbers and booleans keep their type
+            conn.execute(
+                """
+                CREATE TABLE IF NOT EXISTS capture_fields (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    capture_id TEXT NOT NULL,
+                    key TEXT NOT NULL,
+                    value TEXT NOT NULL,
+                    FOREIGN KEY (capture_id) REFERENCES captures (capture_id)
+                )
+            """
+            )
+
             conn.execute(
                 """
                 CREATE TABLE IF NOT EXISTS suggestion_feedback (
@@ -108,6 +395,54 @@ class MainDatabase:
             """
             )
 
+            conn.execute(
+                """
+                CREATE TABLE IF NOT EXISTS drafts (
+                    draft_id TEXT PRIMARY KEY,
+                    state TEXT NOT NULL,
+                    updated_at TEXT NOT NULL
+                )
+            """
+            )
+
+            # Frontend state (last vault, mode, window geometry) as JSON values
+            conn.execute(
+                """
+                CREATE TABLE IF NOT EXISTS ui_state (
+                    key TEXT PRIMARY KEY,
+                    value TEXT NOT NULL,
+                    updated_at TEXT NOT NULL
+                )
+            """
+            )
+
+            # Content as of each edit; kept apart from CAPTURE_CHILD_TABLES so
+            # re-storing a capture doesn't wipe its history
+            conn.execute(
+                """
+                CREATE TABLE IF NOT EXISTS capture_revisions (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    capture_id TEXT NOT NULL,
+                    revision INTEGER NOT NULL,
+                    content TEXT NOT NULL,
+                    created_at TEXT NOT NULL,
+                    UNIQUE (capture_id, revision)
+                )
+            """
+            )
+
+            conn.execute(
+                """
+                CREATE TABLE IF NOT EXISTS source_meta (
+                    value TEXT PRIMARY KEY,
+                    title TEXT,
+                    favicon_url TEXT,
+                    favicon_path TEXT,
+                    fetched_at TEXT NOT NULL
+                )
+            """
+            )
+
             conn.execute("CREATE INDEX IF NOT EXISTS idx_tags_value ON tags (value)")
             conn.execute(
                 "CREATE INDEX IF NOT EXISTS idx_sources_value ON sources (value)"
@@ -115,17 +450,139 @@ class MainDatabase:
             conn.execute(
                 "CREATE INDEX IF NOT EXISTS idx_contexts_value ON contexts (value)"
             )
+            conn.execute(
+                "CREATE INDEX IF NOT EXISTS idx_modalities_value ON modalities (value)"
+            )
             conn.execute(
                 "CREATE INDEX IF NOT EXISTS idx_captures_timestamp "
                 "ON captures (timestamp)"
             )
+            conn.execute(
+                "CREATE INDEX IF NOT EXISTS idx_capture_fields_key_value "
+                "ON capture_fields (key, value)"
+            )
+
+            migrate(conn)
+            self._init_fts(conn)
+            self._relativize_media_paths(conn)
 
             conn.commit()
 
+    def _stored_media_path(self, path: str) -> str:
+        """How a media path is kept in media_files: vault-relative (POSIX) when it
+        is under media_root, otherwise unchanged."""
+        if not self.media_root or not path or not Path(path).is_absolute():
+            return path
+        try:
+            return Path(path).resolve().relative_to(self.media_root).as_posix()
+        except ValueError:
+            return path
+
+    def resolve_media_path(self, stored: str) -> str:
+        """Absolute path of a media_files entry."""
+        if self.media_root and stored and not Path(stored).is_absolute():
+            return str(self.media_root / stored)
+        return stored
+
+    def _relativize_media_paths(self, conn):
+        """Migrate absolute media paths written before paths were stored
+        vault-relative."""
+        if not self.media_root:
+            return
+        rows = conn.execute(
+            "SELECT id, file_path FROM media_files WHERE file_path LIKE '/%'"
+        ).fetchall()
+        for row_id, file_path in rows:
+            stored = self._stored_media_path(file_path)
+            if stored != file_path:
+                conn.execute(
+                    "UPDATE media_files SET file_path = ? WHERE id = ?",
+                    (stored, row_id),
+                )
+
+    @staticmethod
+    def _init_fts(conn):
+        """Full-text index over captures.content/context, kept in sync by triggers.
+
+        captures_fts is an external-content table, so rows are read back from
+        captures; it is built from existing captures the first time it is created.
+        """
+        exists = conn.execute(
+            "SELECT 1 FROM sqlite_master WHERE name = 'captures_fts'"
+        ).fetchone()
+        conn.executescript(
+            """
+            CREATE VIRTUAL TABLE IF NOT EXISTS captures_fts USING fts5(
+                content, context, content='captures', content_rowid='id'
+            );
+            CREATE TRIGGER IF NOT EXISTS captures_fts_insert AFTER INSERT ON captures
+            BEGIN
+                INSERT INTO captures_fts(rowid, content, context)
+                VALUES (new.id, new.content, new.context);
+            END;
+            CREATE TRIGGER IF NOT EXISTS captures_fts_delete AFTER DELETE ON captures
+            BEGIN
+                INSERT INTO captures_fts(captures_fts, rowid, content, context)
+                VALUES ('delete', old.id, old.content, old.context);
+            END;
+            CREATE TRIGGER IF NOT EXISTS captures_fts_update AFTER UPDATE ON captures
+            BEGIN
+                INSERT INTO captures_fts(captures_fts, rowid, content, context)
+                VALUES ('delete', old.id, old.content, old.context);
+                INSERT INTO captures_fts(rowid, content, context)
+                VALUES (new.id, new.content, new.context);
+            END;
+            """
+        )
+        if not exists:
+            conn.execute("INSERT INTO captures_fts(captures_fts) VALUES ('rebuild')")
+
+    def rebuild_fts(self) -> int:
+        """Rebuild captures_fts from the captures table; returns the row count."""
+        with self._transaction() as conn:
+            conn.execute("INSERT INTO captures_fts(captures_fts) VALUES ('rebuild')")
+            return conn.execute("SELECT COUNT(*) FROM captures").fetchone()[0]
+
+    @contextmanager
+    def _transaction(self, before_commit: Optional[Callable[[], None]] = None):
+        """Yield a connection and commit once the block and `before_commit` succeed.
+
+        Any failure rolls back; sqlite errors are re-raised as DatabaseError.
+        """
+        conn = sqlite3.connect(self.db_path)
+        try:
+            yield conn
+            if before_commit:
+                before_commit()
+            conn.commit()
+        except sqlite3.Error as e:
+            conn.rollback()
+            raise DatabaseError(str(e)) from e
+        except Exception:
+            conn.rollback()
+            raise
+        finally:
+            conn.close()
+
+    @staticmethod
+    def _ensure_column(conn, table: str, column: str, definition: str):
+        """Add a column to an existing table if an older database lacks it."""
+        columns = [row[1] for row in conn.execute(f"PRAGMA table_info({table})")]
+        if column not in columns:
+            conn.execute(f"ALTER TABLE {table} ADD COLUMN {column} {definition}")
+
     def store_capture_data(self, capture_data: Dict[str, Any]):
-        """Store comprehensive capture data in the database."""
+        """Store comprehensive capture data in the database.
+
+        `revision_text` is what the revision history records for this write (the
+        capture file's body); without it the stored content is recorded.
+        """
         print(f"DEBUG: store_capture_data called with: {capture_data}")
-        timestamp = datetime.now(timezone.utc).isoformat()
+        ts_input = capture_data.get("timestamp")
+        if isinstance(ts_input, datetime):
+            timestamp = ts_input.isoformat()
+        else:
+            timestamp = datetime.now(timezone.utc).isoformat()
         
         # Ensure capture_id is never None - use timestamp as fallback
         capture_id = capture_data.get("capture_id")
@@ -136,25 +593,46 @@ class MainDatabase:
             
         print(f"DEBUG: Using capture_id: {capture_id}, timestamp: {timestamp}")
 
-        with sqlite3.connect(self.db_path) as conn:
-            content = capture_data.get("content", "")
+        with self._transaction() as conn:
+            content = self._searchable_content(capture_data)
             context = capture_data.get("context", "")
             tags = capture_data.get("tags", [])
             print(
                 f"DEBUG: Inserting capture with content: '{content}', "
                 f"context: '{context}', tags: {tags}"
             )
+            # Re-storing a capture replaces its tags/sources/contexts/media, but
+            # media rows are kept when the data has no media_files at all. The
+            # row itself is deleted explicitly (not via OR REPLACE) so the
+            # captures_fts delete trigger fires.
+            previous_media = {
+                row[0]: row[1:]
+                for row in conn.execute(
+                    "SELECT file_path, file_type, width, height, output "
+                    "FROM media_files WHERE capture_id = ?",
+                    (capture_id,),
+                )
+            }
+            replaced = [*CAPTURE_CHILD_TABLES, "captures"]
+            if "media_files" not in capture_data:
+                replaced.remove("media_files")
+            for table in replaced:
+                conn.execute(
+                    f"DELETE FROM {table} WHERE capture_id = ?", (capture_id,)
+                )
             conn.execute(
                 """
                 INSERT OR REPLACE INTO captures 
                 (capture_id, timestamp, content, context, modalities, location, 
-                 metadata, created_date, last_edited_date, file_path)
-                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                 metadata, created_date, last_edited_date, file_path, locked, color,
+                 processing_status, parent, file_mtime_ns, file_sha256, priority,
+                 rating, collection, expires_at, importance)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             """,
                 (
                     capture_id,
                     timestamp,
-                    capture_data.get("content", ""),
+                    content,
                     capture_data.get("context", ""),
                     json.dumps(capture_data.get("modalities", [])),
                     json.dumps(capture_data.get("location")),
@@ -162,9 +640,23 @@ class MainDatabase:
                     capture_data.get("created_date", ""),
                     capture_data.get("last_edited_date", ""),
                     capture_data.get("file_path", ""),
+                    int(bool(capture_data.get("locked"))),
+                    capture_data.get("color") or None,
+                    capture_data.get("processing_status") or "raw",
+                    capture_data.get("parent") or None,
+                    *_file_state(capture_data.get("file_path") or ""),
+                    capture_data.get("priority"),
+                    capture_data.get("rating"),
+                    capture_data.get("collection") or None,
+                    capture_data.get("expires_at") or None,
+                    capture_data.get("importance"),
                 ),
             )
             print("DEBUG: Capture inserted successfully")
+            revision = capture_data.get("revision_text")
+            if revision is None:
+                revision = content or ""
+            self._record_revision(conn, capture_id, revision)
 
             tags = capture_data.get("tags", [])
             if isinstance(tags, str):
@@ -192,6 +684,15 @@ class MainDatabase:
                         (source.strip(), capture_id, timestamp),
                     )
 
+            for modality in dict.fromkeys(capture_data.get("modalities") or []):
+                conn.execute(
+                    """
+                    INSERT INTO modalities (value, capture_id, timestamp)
+                    VALUES (?, ?, ?)
+                """,
+                    (modality, capture_id, timestamp),
+                )
+
             context = capture_data.get("context", "")
             if isinstance(context, str) and context.strip():
                 conn.execute(
@@ -202,25 +703,57 @@ class MainDatabase:
                     (context.strip(), capture_id, timestamp),
                 )
 
+            for target in wikilink_targets(content):
+                conn.execute(
+                    "INSERT INTO links (capture_id, target) VALUES (?, ?)",
+                    (capture_id, target),
+                )
+
+            for key, value in (capture_data.get("fields") or {}).items():
+                conn.execute(
+                    "INSERT INTO capture_fields (capture_id, key, value) "
+                    "VALUES (?, ?, ?)",
+                    (capture_id, key, json.dumps(value)),
+                )
+
             media_files = capture_data.get("media_files", [])
             for media_file in media_files:
+                stored_path = self._stored_media_path(media_file.get("path", ""))
+                # Media read back from the capture file has no screenshot
+                # metadata; keep what was recorded when it was captured
+                file_type, width, height, output = previous_media.get(
+                    stored_path, ("", None, None, None)
+                )
                 conn.execute(
                     """
-                    INSERT INTO media_files 
-                    (capture_id, file_path, file_type, file_name, timestamp)
-                    VALUES (?, ?, ?, ?, ?)
+                    INSERT INTO media_files
+                    (capture_id, file_path, file_type, file_name, timestamp,
+                     width, height, output)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?)
                 """,
                     (
                         capture_id,
-                        media_file.get("path", ""),
-                        media_file.get("type", ""),
+                        stored_path,
+                        media_file.get("type") or file_type,
                         media_file.get("name", ""),
                         timestamp,
+                        media_file.get("width", width),
+                        media_file.get("height", height),
+                        media_file.get("output", output),
                     ),
                 )
 
-            conn.commit()
-            print("DEBUG: Database transaction committed successfully")
+        print("DEBUG: Database transaction committed successfully")
+
+    @staticmethod
+    def _searchable_content(capture_data: Dict[str, Any]) -> str:
+        """Combine the main content and any content blocks into plain text."""
+        parts = [capture_data.get("content", "") or ""]
+        for block in capture_data.get("blocks", []) or []:
+            heading = block.get("heading", "")
+            body = block.get("body", "")
+            parts.append(f"{heading}\n{body}" if heading else body)
+        return "\n\n".join(p for p in parts if p.strip())
 
     def store_suggestion_feedback(self, field_type: str, value: str, action: str, confidence: Optional[float] = None, edited_value: Optional[str] = None, content_hash: Optional[str] = None):
         ts = datetime.now(timezone.utc).isoformat()
@@ -234,11 +767,15 @@ class MainDatabase:
             )
             conn.commit()
 
-    def get_suggestions(
-        self, field_type: str, query: str = "", limit: int = 10
-    ) -> List[SuggestionItem]:
-        """Get suggestions for a field type with fuzzy matching and sorting."""
-        table_map = {"tag": "tags", "source": "sources", "context": "contexts"}
+    def _all_suggestions(self, field_type: str) -> List[SuggestionItem]:
+        """Every value for a field type with usage count, most recently used first."""
+        table_map = {
+            "tag": "tags",
+            "source": "sources",
+            "context": "contexts",
+            "modality": "modalities",
+            "collection": COLLECTIONS_SQL,
+        }
 
         if field_type not in table_map:
             return []
@@ -268,47 +805,134 @@ class MainDatabase:
                 all_suggestions.append(
                     SuggestionItem(value=value, count=count, last_used=last_used_dt)
                 )
+        return all_suggestions
 
-        if not query.strip():
-            return all_suggestions[:limit]
+    @staticmethod
+    def _score_suggestion(
+        suggestion: SuggestionItem,
+        query_key: str,
+        scoring: str,
+        half_life_days: float,
+        now: datetime,
+    ) -> Optional[Dict[str, Any]]:
+        """Score components for one candidate, or None if it does not match."""
+        value_key = _match_key(suggestion.value)
 
-        query_lower = query.lower()
-        scored_suggestions = []
+        if value_key == query_key:
+            match, match_score = "exact", 1000
+        elif _grapheme_prefix(value_key, query_key):
+            match, match_score = "prefix", 800
+        elif _grapheme_contains(value_key, query_key):
+            match, match_score = "contains", 600
+        else:
+            similarity = difflib.SequenceMatcher(None, query_key, value_key).ratio()
+            if similarity <= 0.3:  # Only include if similarity is above threshold
+                return None
+            match, match_score = "fuzzy", int(similarity * 400)
 
-        for suggestion in all_suggestions:
-            value_lower = suggestion.value.lower()
+        count_boost = min(suggestion.count * 10, 100)
 
-            score = 0
+        age = now - suggestion.last_used
+        decay = 1.0
+        if scoring == "decay" and half_life_days > 0:
+            age_days = max(age.total_seconds(), 0) / 86400
+            decay = 0.5 ** (age_days / half_life_days)
 
-            if value_lower == query_lower:
-                score = 1000
-            elif value_lower.startswith(query_lower):
-                score = 800
-            elif query_lower in value_lower:
-                score = 600
-            else:
-                similarity = difflib.SequenceMatcher(
-                    None, query_lower, value_lower
-                ).ratio()
-                if similarity > 0.3:  # Only include if similarity is above threshold
-                    score = int(similarity * 400)
-                else:
-                    continue
+        recency_boost = max(0, 50 - age.days)
+
+        return {
+            "match": match,
+            "match_score": match_score,
+            "count_boost": count_boost,
+            "decay": decay,
+            "recency_boost": recency_boost,
+            "score": match_score + count_boost * decay + recency_boost,
+        }
+
+    def get_suggestions(
+        self,
+        field_type: str,
+        query: str = "",
+        limit: int = 10,
+        scoring: str = "count",
+        half_life_days: float = 30.0,
+        colors: Optional[Dict[str, str]] = None,
+        palette: Optional[List[str]] = None,
+    ) -> List[SuggestionItem]:
+        """Get suggestions for a field type with fuzzy matching and sorting.
 
-            count_boost = min(suggestion.count * 10, 100)
+        With scoring="decay" the count boost halves every `half_life_days` since
+        the value was last used, so current projects outrank old favourites.
+        Each item's color comes from `colors`, else from value_color.
+        """
+        all_suggestions = self._all_suggestions(field_type)
 
-            days_ago = (datetime.now(timezone.utc) - suggestion.last_used).days
-            recency_boost = max(0, 50 - days_ago)
+        if not query.strip():
+            suggestions = all_suggestions[:limit]
+        else:
+            suggestions = [
+                suggestion
+                for suggestion, _ in self.score_suggestions(
+                    all_suggestions, query, scoring, half_life_days
+                )[:limit]
+            ]
+        for suggestion in suggestions:
+            suggestion.color = (colors or {}).get(suggestion.value) or value_color(
+                suggestion.value, palette
+            )
+        return suggestions
 
-            final_score = score + count_boost + recency_boost
-            scored_suggestions.append((final_score, suggestion))
+    def score_suggestions(
+        self,
+        suggestions: List[SuggestionItem],
+        query: str,
+        scoring: str = "count",
+        half_life_days: float = 30.0,
+        now: Optional[datetime] = None,
+    ) -> List[Tuple[SuggestionItem, Dict[str, Any]]]:
+        """Matching candidates with their score components, best first."""
+        query_key = _match_key(query)
+        now = now or datetime.now(timezone.utc)
+        scored = []
+        for suggestion in suggestions:
+            components = self._score_suggestion(
+                suggestion, query_key, scoring, half_life_days, now
+            )
+            if components is not None:
+                scored.append((suggestion, components))
+        scored.sort(key=lambda x: x[1]["score"], reverse=True)
+        return scored
 
-        scored_suggestions.sort(key=lambda x: x[0], reverse=True)
-        return [suggestion for _, suggestion in scored_suggestions[:limit]]
+    def debug_suggestions(
+        self,
+        field_type: str,
+        query: str,
+        scoring: str = "count",
+        half_life_days: float = 30.0,
+        now: Optional[datetime] = None,
+    ) -> List[Dict[str, Any]]:
+        """Every matching candidate with the components of its ranking score."""
+        scored = self.score_suggestions(
+            self._all_suggestions(field_type), query, scoring, half_life_days, now
+        )
+        return [
+            {
+                "value": suggestion.value,
+                "count": suggestion.count,
+                "last_used": suggestion.last_used.isoformat(),
+                **components,
+            }
+            for suggestion, components in scored
+        ]
 
     def suggestion_exists(self, value: str, field_type: str) -> bool:
         """Check if a suggestion value exists in the database."""
-        table_map = {"tag": "tags", "source": "sources", "context": "contexts"}
+        table_map = {
+            "tag": "tags",
+            "source": "sources",
+            "context": "contexts",
+            "modality": "modalities",
+        }
 
         if field_type not in table_map:
             return False
@@ -326,6 +950,31 @@ class MainDatabase:
             count = cursor.fetchone()[0]
             return count > 0
 
+    def suggestions_exist(self, values: List[str], field_type: str) -> Dict[str, bool]:
+        """Which of `values` exist for a field type, in one query per 500 values."""
+        table = {
+            "tag": "tags",
+            "source": "sources",
+            "context": "contexts",
+            "modality": "modalities",
+        }.get(field_type)
+        found: Set[str] = set()
+        unique = list(dict.fromkeys(values))
+        if table:
+            with sqlite3.connect(self.db_path) as conn:
+                for start in range(0, len(unique), 500):
+                    chunk = unique[start : start + 500]
+                    placeholders = ", ".join("?" * len(chunk))
+                    found.update(
+                        row[0]
+                        for row in conn.execute(
+                            f"SELECT DISTINCT value FROM {table} "
+                            f"WHERE value IN ({placeholders})",
+                            chunk,
+                        )
+                    )
+        return {value: value in found for value in unique}
+
     def _ensure_last_used_table_exists(self):
         """Ensure the last_used_values table exists."""
         with sqlite3.connect(self.db_path) as conn:
@@ -499,6 +1148,866 @@ class MainDatabase:
 
         return result
 
+    @staticmethod
+    def _filter_clauses(capture_filter: Dict[str, Any]) -> Tuple[List[str], List[Any]]:
+        """SQL conditions on captures `c` for a capture filter dict.
+
+        Supported keys: capture_ids, tag, source, context, modality, status
+        (processing_status), exclude_status, parent, query (substring of content),
+        text (substring of content or context), from / before (timestamp bounds,
+        inclusive / exclusive), fts (an FTS5 query over content and context),
+        fields ((key, value) pairs of custom fields; "42" or "true" also match the
+        number or boolean).
+        """
+        clauses = []
+        params: List[Any] = []
+        if capture_filter.get("capture_ids"):
+            ids = list(capture_filter["capture_ids"])
+            clauses.append(f"c.capture_id IN ({','.join('?' * len(ids))})")
+            params.extend(ids)
+        for key, table in (
+            ("tag", "tags"),
+            ("source", "sources"),
+            ("context", "contexts"),
+            ("modality", "modalities"),
+        ):
+            if capture_filter.get(key):
+                clauses.append(
+                    f"c.capture_id IN (SELECT capture_id FROM {table} WHERE value = ?)"
+                )
+                params.append(capture_filter[key])
+        if capture_filter.get("status"):
+            clauses.append("c.processing_status = ?")
+            params.append(capture_filter["status"])
+        if capture_filter.get("exclude_status"):
+            clauses.append("c.processing_status != ?")
+            params.append(capture_filter["exclude_status"])
+        if capture_filter.get("parent"):
+            clauses.append("c.parent = ?")
+            params.append(capture_filter["parent"])
+        if capture_filter.get("collection"):
+            clauses.append("c.collection = ?")
+            params.append(capture_filter["collection"])
+        if capture_filter.get("query"):
+            clauses.append("c.content LIKE ?")
+            params.append(f"%{capture_filter['query']}%")
+        if capture_filter.get("text"):
+            clauses.append("(c.content LIKE ? OR c.context LIKE ?)")
+            params.extend([f"%{capture_filter['text']}%"] * 2)
+        if capture_filter.get("from"):
+            clauses.append("c.timestamp >= ?")
+            params.append(capture_filter["from"])
+        if capture_filter.get("before"):
+            clauses.append("c.timestamp < ?")
+            params.append(capture_filter["before"])
+        for key, value in capture_filter.get("fields") or []:
+            stored = {json.dumps(value)}
+            try:
+                parsed = json.loads(value)
+            except ValueError:
+                parsed = None
+            if isinstance(parsed, (int, float, bool)):
+                stored.add(json.dumps(parsed))
+            clauses.append(
+                "c.capture_id IN (SELECT capture_id FROM capture_fields "
+                f"WHERE key = ? AND value IN ({','.join('?' * len(stored))}))"
+            )
+            params.extend([key, *stored])
+        if capture_filter.get("fts"):
+            clauses.append(
+                "c.id IN (SELECT rowid FROM captures_fts WHERE captures_fts MATCH ?)"
+            )
+            params.append(capture_filter["fts"])
+        return clauses, params
+
+    def find_capture_ids(self, capture_filter: Dict[str, Any]) -> List[str]:
+        """Find capture ids matching all given filter criteria (see _filter_clauses).
+
+        An empty filter matches nothing, so bulk operations never hit every capture.
+        """
+        clauses, params = self._filter_clauses(capture_filter)
+        if not clauses:
+            return []
+
+        with sqlite3.connect(self.db_path) as conn:
+            try:
+                cursor = conn.execute(
+                    f"SELECT c.capture_id FROM captures c "
+                    f"WHERE {' AND '.join(clauses)} ORDER BY c.timestamp DESC",
+                    params,
+                )
+            except sqlite3.OperationalError as e:
+                if not capture_filter.get("fts"):
+                    raise
+                raise ValidationError(f"Invalid search query: {e}") from e
+            return [row[0] for row in cursor.fetchall()]
+
+    def list_captures(
+        self,
+        capture_filter: Dict[str, Any],
+        limit: int = 50,
+        offset: int = 0,
+        sort: str = "timestamp",
+        after: Optional[Tuple[str, str]] = None,
+    ) -> List[Dict[str, Any]]:
+        """Captures matching the filter with their tags/sources.
+
+        Newest first, or with sort="priority" by priority (1 first, unset last)
+        and then newest first. `after` is the (timestamp, capture_id) of the last
+        capture already seen, for keyset pagination in timestamp order.
+        """
+        clauses, params = self._filter_clauses(capture_filter)
+        if after:
+            clauses.append(
+                "(c.timestamp < ? OR (c.timestamp = ? AND c.capture_id < ?))"
+            )
+            params.extend([after[0], after[0], after[1]])
+        where = f"WHERE {' AND '.join(clauses)}" if clauses else ""
+        order = "c.timestamp DESC, c.capture_id DESC"
+        if sort == "priority":
+            order = f"c.priority IS NULL, c.priority, {order}"
+        with sqlite3.connect(self.db_path) as conn:
+            rows = conn.execute(
+                f"""
+                SELECT c.capture_id, c.timestamp, c.content, c.context, c.modalities,
+                       c.file_path, c.locked, c.color, c.processing_status, c.parent,
+                       c.priority, c.rating, c.collection, c.importance
+                FROM captures c {where}
+                ORDER BY {order}
+                LIMIT ? OFFSET ?
+                """,
+                [*params, limit, offset],
+            ).fetchall()
+            fields: Dict[str, Dict[str, Any]] = {row[0]: {} for row in rows}
+            if rows:
+                placeholders = ",".join("?" * len(fields))
+                for capture_id, key, value in conn.execute(
+                    f"SELECT capture_id, key, value FROM capture_fields "
+                    f"WHERE capture_id IN ({placeholders}) ORDER BY id",
+                    list(fields),
+                ):
+                    fields[capture_id][key] = json.loads(value)
+            captures = []
+            for row in rows:
+                capture_id, ts, content, context, modalities, file_path = row[:6]
+                values = {}
+                for key, table in (("tags", "tags"), ("sources", "sources")):
+                    values[key] = [
+                        r[0]
+                        for r in conn.execute(
+                            f"SELECT value FROM {table} WHERE capture_id = ? "
+                            "ORDER BY id",
+                            (capture_id,),
+                        )
+                    ]
+                try:
+                    modality_list = json.loads(modalities or "[]")
+                except json.JSONDecodeError:
+                    modality_list = []
+                captures.append(
+                    {
+                        "capture_id": capture_id,
+                        "timestamp": ts,
+                        "content": content,
+                        "context": context,
+                        "modalities": modality_list,
+                        "tags": values["tags"],
+                        "sources": values["sources"],
+                        "file_path": file_path,
+                        "locked": bool(row[6]),
+                        "color": row[7],
+                        "processing_status": row[8],
+                        "parent": row[9],
+                        "priority": row[10],
+                        "rating": row[11],
+                        "collection": row[12],
+                        "importance": row[13],
+                        "fields": fields[capture_id],
+                    }
+                )
+        return captures
+
+    def search_captures(
+        self,
+        query: str,
+        capture_filter: Optional[Dict[str, Any]] = None,
+        limit: int = 20,
+        offset: int = 0,
+    ) -> List[Dict[str, Any]]:
+        """Captures whose content or context contains `query`, newest first,
+        with a content snippet around the first match instead of the full text.
+        An empty query matches everything the filter allows."""
+        captures = self.list_captures(
+            {**(capture_filter or {}), "text": query.strip()}, limit, offset
+        )
+        return [
+            {
+                "capture_id": capture["capture_id"],
+                "timestamp": capture["timestamp"],
+                "snippet": _snippet(capture["content"] or "", query.strip()),
+                "context": capture["context"],
+                "tags": capture["tags"],
+                "sources": capture["sources"],
+                "file_path": capture["file_path"],
+            }
+            for capture in captures
+        ]
+
+    def captures_near(
+        self, lat: float, lon: float, radius_km: float, limit: int = 100
+    ) -> List[Dict[str, Any]]:
+        """Captures with a stored location within radius_km, nearest first."""
+        with sqlite3.connect(self.db_path) as conn:
+            rows = conn.execute(
+                """
+                SELECT capture_id,
+                       json_extract(location, '$.latitude'),
+                       json_extract(location, '$.longitude')
+                FROM captures
+                WHERE json_valid(location)
+                  AND json_extract(location, '$.latitude') IS NOT NULL
+                  AND json_extract(location, '$.longitude') IS NOT NULL
+            """
+            ).fetchall()
+
+        distances = {}
+        for capture_id, cap_lat, cap_lon in rows:
+            distance = _haversine_km(lat, lon, float(cap_lat), float(cap_lon))
+            if distance <= radius_km:
+                distances[capture_id] = distance
+        nearest = sorted(distances, key=distances.get)[:limit]
+        if not nearest:
+            return []
+
+        by_id = {
+            c["capture_id"]: c
+            for c in self.list_captures({"capture_ids": nearest}, limit=len(nearest))
+        }
+        return [
+            {**by_id[capture_id], "distance_km": round(distances[capture_id], 3)}
+            for capture_id in nearest
+        ]
+
+    def find_duplicates(
+        self, threshold: float, scan_limit: int = 2000
+    ) -> List[Dict[str, Any]]:
+        """Pairs among the newest scan_limit captures whose content trigram Jaccard
+        similarity is at least threshold, most similar first."""
+        with sqlite3.connect(self.db_path) as conn:
+            rows = conn.execute(
+                "SELECT capture_id, content FROM captures "
+                "WHERE TRIM(COALESCE(content, '')) != '' "
+                "ORDER BY timestamp DESC LIMIT ?",
+                (scan_limit,),
+            ).fetchall()
+
+        grams = sorted(
+            ((capture_id, _trigrams(content)) for capture_id, content in rows),
+            key=lambda item: len(item[1]),
+        )
+        # Prefix filtering: with trigrams ordered rarest first, two sets whose
+        # similarity reaches threshold share one of the first
+        # len - ceil(threshold * len) + 1 trigrams of each. Only captures sharing
+        # such a trigram are compared, instead of every pair.
+        frequency = Counter(gram for _, grams_a in grams for gram in grams_a)
+        index: Dict[str, List[int]] = {}
+        pairs = []
+        for i, (id_a, set_a) in enumerate(grams):
+            ordered = sorted(set_a, key=lambda gram: (frequency[gram], gram))
+            prefix = len(ordered) - math.ceil(threshold * len(ordered) - 1e-9) + 1
+            candidates = set()
+            for gram in ordered[:prefix]:
+                postings = index.setdefault(gram, [])
+                candidates.update(postings)
+                postings.append(i)
+            for j in sorted(candidates):
+                id_b, set_b = grams[j]
+                # Sets are sorted by size, so |b|/|a| bounds the similarity
+                if len(set_b) < threshold * len(set_a):
+                    continue
+                score = len(set_a & set_b) / len(set_a | set_b)
+                if score >= threshold:
+                    pairs.append(
+                        {"capture_ids": [id_b, id_a], "score": round(score, 4)}
+                    )
+        pairs.sort(key=lambda pair: pair["score"], reverse=True)
+        return pairs
+
+    def record_file_states(self, capture_ids: List[str]):
+        """Refresh the indexed file state after the app rewrote capture files."""
+        with self._transaction() as conn:
+            for capture_id in capture_ids:
+                row = conn.execute(
+                    "SELECT file_path FROM captures WHERE capture_id = ?",
+                    (capture_id,),
+                ).fetchone()
+                if row and row[0]:
+                    conn.execute(
+                        "UPDATE captures SET file_mtime_ns = ?, file_sha256 = ? "
+                        "WHERE capture_id = ?",
+                        (*_file_state(row[0]), capture_id),
+                    )
+
+    def find_drift(self) -> List[Dict[str, Any]]:
+        """Captures whose file is missing or changed on disk since last indexed.
+
+        Files with a new mtime but identical bytes are not reported.
+        """
+        with sqlite3.connect(self.db_path) as conn:
+            rows = conn.execute(
+                "SELECT capture_id, file_path, file_mtime_ns, file_sha256 "
+                "FROM captures WHERE COALESCE(file_path, '') != '' "
+                "ORDER BY timestamp DESC"
+            ).fetchall()
+
+        drifted = []
+        for capture_id, file_path, mtime_ns, sha256 in rows:
+            path = Path(file_path)
+            if not path.exists():
+                status = "missing"
+            elif path.stat().st_mtime_ns == mtime_ns:
+                continue
+            elif _file_state(file_path)[1] == sha256:
+                continue
+            else:
+                status = "modified"
+            drifted.append(
+                {"capture_id": capture_id, "file_path": file_path, "status": status}
+            )
+        return drifted
+
+    def expiring_captures(self, before: Optional[str] = None) -> List[Dict[str, Any]]:
+        """Captures with an expiry (optionally at or before `before`), soonest
+        first. Expiries are UTC ISO timestamps, so they compare as strings."""
+        query = (
+            "SELECT capture_id, expires_at, file_path, locked FROM captures "
+            "WHERE expires_at IS NOT NULL"
+        )
+        params: List[Any] = []
+        if before:
+            query += " AND expires_at <= ?"
+            params.append(before)
+        with sqlite3.connect(self.db_path) as conn:
+            rows = conn.execute(
+                query + " ORDER BY expires_at, capture_id", params
+            ).fetchall()
+        return [
+            {
+                "capture_id": capture_id,
+                "expires_at": expires_at,
+                "file_path": file_path,
+                "locked": bool(locked),
+            }
+            for capture_id, expires_at, file_path, locked in rows
+        ]
+
+    def available_capture_id(self, capture_id: str) -> str:
+        """capture_id, or the first capture_id_N no row uses yet."""
+        with sqlite3.connect(self.db_path) as conn:
+            candidate, counter = capture_id, 1
+            while conn.execute(
+                "SELECT 1 FROM captures WHERE capture_id = ?", (candidate,)
+            ).fetchone():
+                candidate = f"{capture_id}_{counter}"
+                counter += 1
+        return candidate
+
+    def get_capture_file_path(self, capture_id: str) -> Optional[str]:
+        with sqlite3.connect(self.db_path) as conn:
+            row = conn.execute(
+                "SELECT file_path FROM captures WHERE capture_id = ?", (capture_id,)
+            ).fetchone()
+        return row[0] if row and row[0] else None
+
+    def bulk_update_tags(
+        self,
+        capture_ids: List[str],
+        add: List[str],
+        remove: List[str],
+        before_commit: Optional[Callable[[], None]] = None,
+    ):
+        """Add and remove tags on many captures in a single transaction.
+
+        `before_commit` runs after the rows are updated; if it raises, the whole
+        update is rolled back.
+        """
+        ts = datetime.now(timezone.utc).isoformat()
+        with self._transaction(before_commit) as conn:
+            for capture_id in capture_ids:
+                if remove:
+                    conn.execute(
+                        f"DELETE FROM tags WHERE capture_id = ? "
+                        f"AND value IN ({','.join('?' * len(remove))})",
+                        [capture_id, *remove],
+                    )
+                for tag in add:
+                    exists = conn.execute(
+                        "SELECT 1 FROM tags WHERE capture_id = ? AND value = ?",
+                        (capture_id, tag),
+                    ).fetchone()
+                    if not exists:
+                        conn.execute(
+                            "INSERT INTO tags (value, capture_id, timestamp) "
+                            "VALUES (?, ?, ?)",
+                            (tag, capture_id, ts),
+                        )
+
+    def capture_exists(self, capture_id: str) -> bool:
+        with sqlite3.connect(self.db_path) as conn:
+            row = conn.execute(
+                "SELECT 1 FROM captures WHERE capture_id = ?", (capture_id,)
+            ).fetchone()
+        return row is not None
+
+    def parent_chain(self, capture_id: str) -> List[str]:
+        """Ancestors of a capture, nearest first; stops at a repeated id."""
+        chain: List[str] = []
+        with sqlite3.connect(self.db_path) as conn:
+            while True:
+                row = conn.execute(
+                    "SELECT parent FROM captures WHERE capture_id = ?", (capture_id,)
+                ).fetchone()
+                if not row or not row[0] or row[0] in chain:
+                    return chain
+                capture_id = row[0]
+                chain.append(capture_id)
+
+    def is_locked(self, capture_id: str) -> bool:
+        with sqlite3.connect(self.db_path) as conn:
+            row = conn.execute(
+                "SELECT locked FROM captures WHERE capture_id = ?", (capture_id,)
+            ).fetchone()
+        return bool(row and row[0])
+
+    def set_locked(
+        self,
+        capture_id: str,
+        locked: bool,
+        before_commit: Optional[Callable[[], None]] = None,
+    ):
+        with self._transaction(before_commit) as conn:
+            conn.execute(
+                "UPDATE captures SET locked = ? WHERE capture_id = ?",
+                (int(locked), capture_id),
+            )
+
+    def captures_before(
+        self, before: datetime, include_locked: bool = False
+    ) -> List[str]:
+        """Ids of captures not yet archived that were taken before `before`."""
+        with sqlite3.connect(self.db_path) as conn:
+            rows = conn.execute(
+                "SELECT capture_id, timestamp, locked FROM captures "
+                "WHERE processing_status != 'archived' ORDER BY timestamp"
+            ).fetchall()
+        capture_ids = []
+        for capture_id, timestamp, locked in rows:
+            try:
+                taken = datetime.fromisoformat(timestamp)
+            except (TypeError, ValueError):
+                continue
+            if taken.tzinfo is None:
+                taken = taken.replace(tzinfo=timezone.utc)
+            if taken < before and (include_locked or not locked):
+                capture_ids.append(capture_id)
+        return capture_ids
+
+    def capture_counts(self, bucket_sql: str, start: str, end: str) -> Dict[str, int]:
+        """Capture counts per bucket for captures taken between two dates.
+
+        `bucket_sql` is a SQLite date expression over `timestamp` that yields
+        the bucket's start date.
+        """
+        with sqlite3.connect(self.db_path) as conn:
+            rows = conn.execute(
+                f"SELECT {bucket_sql} AS bucket, COUNT(*) FROM captures "
+                "WHERE date(timestamp) BETWEEN ? AND ? "
+                "GROUP BY bucket ORDER BY bucket",
+                (start, end),
+            ).fetchall()
+        return {bucket: count for bucket, count in rows if bucket}
+
+    def rating_averages(
+        self, bucket_sql: str, start: str, end: str
+    ) -> Dict[str, Tuple[float, int]]:
+        """(average rating, rated captures) per bucket between two dates."""
+        with sqlite3.connect(self.db_path) as conn:
+            rows = conn.execute(
+                f"SELECT {bucket_sql} AS bucket, AVG(rating), COUNT(*) "
+                "FROM captures WHERE rating IS NOT NULL "
+                "AND date(timestamp) BETWEEN ? AND ? "
+                "GROUP BY bucket ORDER BY bucket",
+                (start, end),
+            ).fetchall()
+        return {bucket: (average, count) for bucket, average, count in rows if bucket}
+
+    def first_capture_date(self) -> Optional[str]:
+        with sqlite3.connect(self.db_path) as conn:
+            (first,) = conn.execute(
+                "SELECT date(MIN(timestamp)) FROM captures"
+            ).fetchone()
+        return first
+
+    def archive_capture(
+        self,
+        capture_id: str,
+        file_path: str,
+        before_commit: Optional[Callable[[], None]] = None,
+    ):
+        """Point a capture at its archived file and mark it archived."""
+        with self._transaction(before_commit) as conn:
+            conn.execute(
+                "UPDATE captures SET file_path = ?, processing_status = 'archived' "
+                "WHERE capture_id = ?",
+                (file_path, capture_id),
+            )
+
+    def capture_files(self) -> List[Dict[str, Any]]:
+        """Every capture's file path and recorded media paths."""
+        with sqlite3.connect(self.db_path) as conn:
+            captures = conn.execute(
+                "SELECT capture_id, file_path FROM captures ORDER BY timestamp DESC"
+            ).fetchall()
+            media = conn.execute(
+                "SELECT capture_id, file_path FROM media_files ORDER BY id"
+            ).fetchall()
+        media_by_capture: Dict[str, List[str]] = {}
+        for capture_id, file_path in media:
+            media_by_capture.setdefault(capture_id, []).append(
+                self.resolve_media_path(file_path)
+            )
+        return [
+            {
+                "capture_id": capture_id,
+                "file_path": file_path,
+                "media_paths": media_by_capture.get(capture_id, []),
+            }
+            for capture_id, file_path in captures
+        ]
+
+    def get_media_paths(self, capture_id: str) -> List[str]:
+        with sqlite3.connect(self.db_path) as conn:
+            rows = conn.execute(
+                "SELECT file_path FROM media_files WHERE capture_id = ? ORDER BY id",
+                (capture_id,),
+            ).fetchall()
+        return [self.resolve_media_path(row[0]) for row in rows]
+
+    def move_capture(
+        self,
+        capture_id: str,
+        new_capture_id: str,
+        file_path: str,
+        media_paths: Dict[str, str],
+        before_commit: Optional[Callable[[], None]] = None,
+    ):
+        """Point a capture's rows at its new file (and id) after a vault move."""
+        with self._transaction(before_commit) as conn:
+            conn.execute(
+                "UPDATE captures SET capture_id = ?, file_path = ? "
+                "WHERE capture_id = ?",
+                (new_capture_id, file_path, capture_id),
+            )
+            for table in (*CAPTURE_CHILD_TABLES, "capture_revisions"):
+                conn.execute(
+                    f"UPDATE {table} SET capture_id = ? WHERE capture_id = ?",
+                    (new_capture_id, capture_id),
+                )
+            for old_path, new_path in media_paths.items():
+                conn.execute(
+                    "UPDATE media_files SET file_path = ? "
+                    "WHERE capture_id = ? AND file_path = ?",
+                    (
+                        self._stored_media_path(new_path),
+                        new_capture_id,
+                        self._stored_media_path(old_path),
+                    ),
+                )
+
+    def export_capture(self, capture_id: str) -> Dict[str, List[Dict[str, Any]]]:
+        """A capture's rows by table, without row ids, for import_capture into
+        another database. Media paths are absolute."""
+        rows: Dict[str, List[Dict[str, Any]]] = {}
+        with sqlite3.connect(self.db_path) as conn:
+            conn.row_factory = sqlite3.Row
+            for table in ("captures", *CAPTURE_CHILD_TABLES, "capture_revisions"):
+                rows[table] = [
+                    {key: row[key] for key in row.keys() if key != "id"}
+                    for row in conn.execute(
+                        f"SELECT * FROM {table} WHERE capture_id = ? ORDER BY id",
+                        (capture_id,),
+                    )
+                ]
+        for media_file in rows["media_files"]:
+            media_file["file_path"] = self.resolve_media_path(media_file["file_path"])
+        return rows
+
+    def import_capture(
+        self,
+        rows: Dict[str, List[Dict[str, Any]]],
+        capture_id: str,
+        file_path: str,
+        before_commit: Optional[Callable[[], None]] = None,
+    ):
+        """Insert rows from another database's export_capture under capture_id.
+        Columns this database lacks are dropped."""
+        with self._transaction(before_commit) as conn:
+            for table, table_rows in rows.items():
+                info = conn.execute(f"PRAGMA table_info({table})")
+                columns = {row[1] for row in info}
+                for row in table_rows:
+                    row = {k: v for k, v in row.items() if k in columns}
+                    row["capture_id"] = capture_id
+                    if table == "captures":
+                        row["file_path"] = file_path
+                    elif table == "media_files":
+                        row["file_path"] = self._stored_media_path(row["file_path"])
+                    conn.execute(
+                        f"INSERT INTO {table} ({', '.join(row)}) "
+                        f"VALUES ({', '.join('?' * len(row))})",
+                        list(row.values()),
+                    )
+
+    @staticmethod
+    def _record_revision(conn, capture_id: str, content: str):
+        """Add a revision when the content differs from the latest one."""
+        latest = conn.execute(
+            "SELECT revision, content FROM capture_revisions WHERE capture_id = ? "
+            "ORDER BY revision DESC LIMIT 1",
+            (capture_id,),
+        ).fetchone()
+        if latest and latest[1] == content:
+            return
+        conn.execute(
+            "INSERT INTO capture_revisions (capture_id, revision, content, created_at) "
+            "VALUES (?, ?, ?, ?)",
+            (
+                capture_id,
+                latest[0] + 1 if latest else 1,
+                content,
+                datetime.now(timezone.utc).isoformat(),
+            ),
+        )
+
+    def list_revisions(self, capture_id: str) -> List[Dict[str, Any]]:
+        """A capture's revisions, oldest first (revision 1 is the original)."""
+        with sqlite3.connect(self.db_path) as conn:
+            rows = conn.execute(
+                "SELECT revision, created_at, length(content) FROM capture_revisions "
+                "WHERE capture_id = ? ORDER BY revision",
+                (capture_id,),
+            ).fetchall()
+        return [
+            {"revision": revision, "created_at": created_at, "length": length}
+            for revision, created_at, length in rows
+        ]
+
+    def get_revision(self, capture_id: str, revision: int) -> Optional[str]:
+        with sqlite3.connect(self.db_path) as conn:
+            row = conn.execute(
+                "SELECT content FROM capture_revisions "
+                "WHERE capture_id = ? AND revision = ?",
+                (capture_id, revision),
+            ).fetchone()
+        return row[0] if row else None
+
+    def delete_capture(
+        self, capture_id: str, before_commit: Optional[Callable[[], None]] = None
+    ):
+        """Remove a capture and its tags/sources/contexts/media/revision rows."""
+        with self._transaction(before_commit) as conn:
+            for table in (*CAPTURE_CHILD_TABLES, "capture_revisions", "captures"):
+                conn.execute(
+                    f"DELETE FROM {table} WHERE capture_id = ?", (capture_id,)
+                )
+
+    def merge_captures(
+        self,
+        keep_id: str,
+        other_ids: List[str],
+        before_commit: Optional[Callable[[], None]] = None,
+    ):
+        """Fold other captures into keep_id in a single transaction.
+
+        Content is concatenated, tags/sources/contexts/media are re-pointed at
+        the kept capture (deduplicated) and the other capture rows are deleted.
+        `before_commit` runs last; if it raises, nothing is changed.
+        """
+        all_ids = [keep_id, *other_ids]
+        placeholders = ",".join("?" * len(other_ids))
+        with self._transaction(before_commit) as conn:
+            rows = conn.execute(
+                f"SELECT capture_id, content, modalities FROM captures "
+                f"WHERE capture_id IN ({','.join('?' * len(all_ids))})",
+                all_ids,
+            ).fetchall()
+            by_id = {row[0]: row for row in rows}
+
+            contents = [by_id[i][1] for i in all_ids if i in by_id and by_id[i][1]]
+            modalities: List[str] = []
+            for capture_id in all_ids:
+                if capture_id not in by_id:
+                    continue
+                try:
+                    for m in json.loads(by_id[capture_id][2] or "[]"):
+                        if m not in modalities:
+                            modalities.append(m)
+                except json.JSONDecodeError:
+                    pass
+
+            merged_content = "\n\n".join(c for c in contents if c.strip())
+            conn.execute(
+                "UPDATE captures SET content = ?, modalities = ? WHERE capture_id = ?",
+                (merged_content, json.dumps(modalities), keep_id),
+            )
+            # The kept capture's own fields win over the merged ones
+            conn.execute(
+                f"DELETE FROM capture_fields WHERE capture_id IN ({placeholders}) "
+                "AND key IN (SELECT key FROM capture_fields WHERE capture_id = ?)",
+                [*other_ids, keep_id],
+            )
+            for table in CAPTURE_CHILD_TABLES:
+                conn.execute(
+                    f"UPDATE {table} SET capture_id = ? "
+                    f"WHERE capture_id IN ({placeholders})",
+                    [keep_id, *other_ids],
+                )
+            for table in ("tags", "sources", "contexts", "modalities"):
+                conn.execute(
+                    f"""
+                    DELETE FROM {table} WHERE capture_id = ? AND id NOT IN (
+                        SELECT MIN(id) FROM {table} WHERE capture_id = ? GROUP BY value
+                    )
+                    """,
+                    (keep_id, keep_id),
+                )
+            conn.execute(
+                """
+                DELETE FROM capture_fields WHERE capture_id = ? AND id NOT IN (
+                    SELECT MIN(id) FROM capture_fields WHERE capture_id = ? GROUP BY key
+                )
+                """,
+                (keep_id, keep_id),
+            )
+            self._record_revision(conn, keep_id, merged_content)
+            for table in ("capture_revisions", "captures"):
+                conn.execute(
+                    f"DELETE FROM {table} WHERE capture_id IN ({placeholders})",
+                    other_ids,
+                )
+
+    def get_source_meta(self, value: str) -> Optional[Dict[str, Any]]:
+        with sqlite3.connect(self.db_path) as conn:
+            row = conn.execute(
+                """
+                SELECT value, title, favicon_url, favicon_path, fetched_at
+                FROM source_meta WHERE value = ?
+                """,
+                (value,),
+            ).fetchone()
+        if not row:
+            return None
+        return {
+            "value": row[0],
+            "title": row[1],
+            "favicon_url": row[2],
+            "favicon_path": row[3],
+            "fetched_at": row[4],
+        }
+
+    def store_source_meta(self, value: str, meta: Dict[str, Any]):
+        ts = datetime.now(timezone.utc).isoformat()
+        with sqlite3.connect(self.db_path) as conn:
+            conn.execute(
+                """
+                INSERT OR REPLACE INTO source_meta
+                (value, title, favicon_url, favicon_path, fetched_at)
+                VALUES (?, ?, ?, ?, ?)
+                """,
+                (
+                    value,
+                    meta.get("title"),
+                    meta.get("favicon_url"),
+                    meta.get("favicon_path"),
+                    ts,
+                ),
+            )
+            conn.commit()
+
+    def save_draft(self, draft_id: str, state: Dict[str, Any]):
+        """Insert or update an in-progress capture draft."""
+        ts = datetime.now(timezone.utc).isoformat()
+        with sqlite3.connect(self.db_path) as conn:
+            conn.execute(
+                """
+                INSERT OR REPLACE INTO drafts (draft_id, state, updated_at)
+                VALUES (?, ?, ?)
+                """,
+                (draft_id, json.dumps(state), ts),
+            )
+            conn.commit()
+
+    def get_latest_draft(self) -> Optional[Dict[str, Any]]:
+        """Get the most recently updated draft, if any."""
+        with sqlite3.connect(self.db_path) as conn:
+            row = conn.execute(
+                """
+                SELECT draft_id, state, updated_at FROM drafts
+                ORDER BY updated_at DESC LIMIT 1
+                """
+            ).fetchone()
+        if not row:
+            return None
+        try:
+            state = json.loads(row[1])
+        except json.JSONDecodeError:
+            state = {}
+        return {"draft_id": row[0], "state": state, "updated_at": row[2]}
+
+    def delete_draft(self, draft_id: str):
+        with sqlite3.connect(self.db_path) as conn:
+            conn.execute("DELETE FROM drafts WHERE draft_id = ?", (draft_id,))
+            conn.commit()
+
+    def prune_drafts(self, max_age_days: float) -> int:
+        """Delete drafts not updated within max_age_days; returns the count removed."""
+        cutoff = datetime.now(timezone.utc) - timedelta(days=max_age_days)
+        with sqlite3.connect(self.db_path) as conn:
+            cursor = conn.execute(
+                "DELETE FROM drafts WHERE updated_at < ?", (cutoff.isoformat(),)
+            )
+            conn.commit()
+            return cursor.rowcount
+
+    def get_ui_state(self) -> Dict[str, Any]:
+        with sqlite3.connect(self.db_path) as conn:
+            rows = conn.execute("SELECT key, value FROM ui_state ORDER BY key")
+            state = {}
+            for key, value in rows:
+                try:
+                    state[key] = json.loads(value)
+                except json.JSONDecodeError:
+                    continue
+        return state
+
+    def update_ui_state(self, values: Dict[str, Any]) -> Dict[str, Any]:
+        """Set each key to its JSON value; a key set to None is removed.
+        Returns the full state."""
+        ts = datetime.now(timezone.utc).isoformat()
+        with self._transaction() as conn:
+            for key, value in values.items():
+                if value is None:
+                    conn.execute("DELETE FROM ui_state WHERE key = ?", (key,))
+                else:
+                    conn.execute(
+                        """
+                        INSERT OR REPLACE INTO ui_state (key, value, updated_at)
+                        VALUES (?, ?, ?)
+                        """,
+                        (key, json.dumps(value), ts),
+                    )
+        return self.get_ui_state()
+
     def get_capture_statistics(self) -> Dict[str, Any]:
         """Get comprehensive statistics about captures."""
         with sqlite3.connect(self.db_path) as conn:
@@ -539,3 +2048,199 @@ class MainDatabase:
             stats["top_sources"] = cursor.fetchall()
 
             return stats
+
+    def get_tag_graph(self, min_weight: int = 1) -> Dict[str, Any]:
+        """Tags as nodes and same-capture co-occurrence counts as edges."""
+        with sqlite3.connect(self.db_path) as conn:
+            nodes = conn.execute(
+                """
+                SELECT value, COUNT(DISTINCT capture_id) AS count
+                FROM tags
+                GROUP BY value
+                ORDER BY count DESC, value
+            """
+            ).fetchall()
+            edges = conn.execute(
+                """
+                SELECT a.value, b.value, COUNT(DISTINCT a.capture_id) AS weight
+                FROM tags a
+                JOIN tags b ON a.capture_id = b.capture_id AND a.value < b.value
+                GROUP BY a.value, b.value
+                HAVING weight >= ?
+                ORDER BY weight DESC, a.value, b.value
+            """,
+                (min_weight,),
+            ).fetchall()
+        return {
+            "nodes": [{"id": value, "count": count} for value, count in nodes],
+            "edges": [
+                {"source": a, "target": b, "weight": weight}
+                for a, b, weight in edges
+            ],
+        }
+
+    def get_capture_graph(
+        self,
+        root: Optional[str] = None,
+        depth: int = 1,
+        include_tags: bool = False,
+        limit: int = 500,
+        label: Optional[Callable[[str], Optional[str]]] = None,
+    ) -> Dict[str, Any]:
+        """Captures as nodes and [[wikilinks]] between them as edges.
+
+        With `root`, only captures within `depth` links of it (either direction)
+        are included. At most `limit` captures are returned, nearest to the root
+        or newest first; `truncated` says whether any were left out. With
+        `include_tags`, tags become nodes linked to their captures. `label` turns
+        a capture's content into its node label; the capture id is the fallback.
+        """
+        limit = max(1, min(limit, MAX_GRAPH_NODES))
+        with sqlite3.connect(self.db_path) as conn:
+            captures = conn.execute(
+                "SELECT capture_id, content, file_path FROM captures "
+                "ORDER BY timestamp DESC, capture_id DESC"
+            ).fetchall()
+            by_name: Dict[str, str] = {}
+            for capture_id, _, file_path in captures:
+                if file_path:
+                    by_name.setdefault(Path(file_path).stem, capture_id)
+            by_name.update({capture_id: capture_id for capture_id, _, _ in captures})
+            edges = {
+                (source, by_name[target])
+                for source, target in conn.execute(
+                    "SELECT capture_id, target FROM links"
+                )
+                if target in by_name and by_name[target] != source
+            }
+
+            if root is None:
+                ids = [capture_id for capture_id, _, _ in captures]
+            else:
+                neighbours: Dict[str, Set[str]] = {}
+                for source, target in edges:
+                    neighbours.setdefault(source, set()).add(target)
+                    neighbours.setdefault(target, set()).add(source)
+                ids, frontier = [root], [root]
+                for _ in range(max(depth, 0)):
+                    frontier = sorted(
+                        {n for c in frontier for n in neighbours.get(c, ())} - set(ids)
+                    )
+                    ids.extend(frontier)
+            truncated = len(ids) > limit
+            included = set(ids[:limit])
+
+            content = {capture_id: text for capture_id, text, _ in captures}
+            nodes = [
+                {
+                    "id": capture_id,
+                    "type": "capture",
+                    "label": (label and label(content.get(capture_id) or ""))
+                    or capture_id,
+                }
+                for capture_id in ids[:limit]
+            ]
+            graph_edges = [
+                {"source": source, "target": target, "type": "link"}
+                for source, target in sorted(edges)
+                if source in included and target in included
+            ]
+            if include_tags and included:
+                placeholders = ",".join("?" * len(included))
+                tag_rows = conn.execute(
+                    f"SELECT DISTINCT capture_id, value FROM tags "
+                    f"WHERE capture_id IN ({placeholders}) ORDER BY value, capture_id",
+                    sorted(included),
+                ).fetchall()
+                for tag in dict.fromkeys(value for _, value in tag_rows):
+                    nodes.append({"id": f"tag:{tag}", "type": "tag", "label": tag})
+                graph_edges.extend(
+                    {"source": capture_id, "target": f"tag:{tag}", "type": "tag"}
+                    for capture_id, tag in tag_rows
+                )
+        return {"nodes": nodes, "edges": graph_edges, "truncated": truncated}
+
+    def top_tags(
+        self, limit: int = 8, since: Optional[datetime] = None
+    ) -> List[Dict[str, Any]]:
+        """Most-used tags by capture count, most recently used first on ties.
+        With `since`, only captures tagged from then on are counted."""
+        params: List[Any] = []
+        where = ""
+        if since is not None:
+            where = "WHERE timestamp >= ?"
+            params.append(since.astimezone(timezone.utc).isoformat())
+        with sqlite3.connect(self.db_path) as conn:
+            rows = conn.execute(
+                f"""
+                SELECT value, COUNT(DISTINCT capture_id) AS count,
+                       MAX(timestamp) AS last_used
+                FROM tags {where}
+                GROUP BY value
+                ORDER BY count DESC, last_used DESC, value
+                LIMIT ?
+            """,
+                [*params, limit],
+            ).fetchall()
+        return [
+            {"value": value, "count": count, "last_used": last_used}
+            for value, count, last_used in rows
+        ]
+
+    def _value_counts(self, table: str, order_by: str) -> List[Dict[str, Any]]:
+        """Every distinct value in `table` with its capture count and when it was
+        last used, ordered by `last_used` (newest first) or `count` (most used)."""
+        order = {
+            "last_used": "last_used DESC, value",
+            "count": "count DESC, last_used DESC, value",
+        }[order_by]
+        with sqlite3.connect(self.db_path) as conn:
+            rows = conn.execute(
+                f"""
+                SELECT value, COUNT(DISTINCT capture_id) AS count,
+                       MAX(timestamp) AS last_used
+                FROM {table}
+                GROUP BY value
+                ORDER BY {order}
+            """
+            ).fetchall()
+        return [
+            {"value": value, "count": count, "last_used": last_used}
+            for value, count, last_used in rows
+        ]
+
+    def context_counts(self, order_by: str = "last_used") -> List[Dict[str, Any]]:
+        return self._value_counts("contexts", order_by)
+
+    def collection_counts(self, order_by: str = "last_used") -> List[Dict[str, Any]]:
+        return self._value_counts(COLLECTIONS_SQL, order_by)
+
+    def browse_tags(self, recent: int = 3) -> List[Dict[str, Any]]:
+        """Every tag with its capture count and its `recent` most recent capture
+        ids (none with recent=0)."""
+        with sqlite3.connect(self.db_path) as conn:
+            # Every tag's newest row is kept so recent=0 still lists the counts
+            rows = conn.execute(
+                """
+                SELECT value, capture_id, count, rank FROM (
+                    SELECT t.value, t.capture_id,
+                           COUNT(*) OVER (PARTITION BY t.value) AS count,
+                           ROW_NUMBER() OVER (
+                               PARTITION BY t.value ORDER BY c.timestamp DESC
+                           ) AS rank
+                    FROM (SELECT DISTINCT value, capture_id FROM tags) t
+                    JOIN captures c ON c.capture_id = t.capture_id
+                )
+                WHERE rank <= MAX(?, 1)
+                ORDER BY count DESC, value, rank
+            """,
+                (recent,),
+            ).fetchall()
+        tags: Dict[str, Dict[str, Any]] = {}
+        for value, capture_id, count, rank in rows:
+            tag = tags.setdefault(
+                value, {"tag": value, "count": count, "recent_capture_ids": []}
+            )
+            if rank <= recent:
+                tag["recent_capture_ids"].append(capture_id)
+        return list(tags.values())
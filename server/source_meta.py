@@ -0,0 +1,115 @@
+"""
+Fetch page titles and favicons for URL sources.
+"""
+
+import fnmatch
+import hashlib
+import urllib.request
+from html.parser import HTMLParser
+from pathlib import Path
+from typing import Iterable, Optional, Dict, Any
+from urllib.parse import parse_qsl, urlencode, urljoin, urlparse, urlsplit, urlunsplit
+
+USER_AGENT = "kms-capture/1.0 (+source metadata)"
+MAX_PAGE_BYTES = 512 * 1024
+MAX_FAVICON_BYTES = 256 * 1024
+
+
+def is_url(value: str) -> bool:
+    parsed = urlparse(value)
+    return parsed.scheme in ("http", "https") and bool(parsed.netloc)
+
+
+# Tracking parameters dropped with `sources.strip_params: true`
+DEFAULT_STRIP_PARAMS = (
+    "utm_*",
+    "fbclid",
+    "gclid",
+    "dclid",
+    "msclkid",
+    "mc_cid",
+    "mc_eid",
+    "igshid",
+    "ref_src",
+)
+
+
+def strip_query_params(url: str, patterns: Iterable[str]) -> str:
+    """The URL without query parameters whose names match a glob pattern
+    (case-insensitive). Anything that isn't an http(s) URL is returned as is."""
+    if not is_url(url):
+        return url
+    patterns = [p.lower() for p in patterns]
+    parts = urlsplit(url)
+    params = parse_qsl(parts.query, keep_blank_values=True)
+    kept = [
+        (name, value)
+        for name, value in params
+        if not any(fnmatch.fnmatchcase(name.lower(), p) for p in patterns)
+    ]
+    if len(kept) == len(params):
+        return url
+    return urlunsplit(parts._replace(query=urlencode(kept)))
+
+
+class _HeadParser(HTMLParser):
+    def __init__(self):
+        super().__init__()
+        self.title = ""
+        self.icon_href: Optional[str] = None
+        self._in_title = False
+
+    def handle_starttag(self, tag, attrs):
+        attrs = dict(attrs)
+        if tag == "title":
+            self._in_title = True
+        elif tag == "link" and self.icon_href is None:
+            rel = (attrs.get("rel") or "").lower().split()
+            if "icon" in rel and attrs.get("href"):
+                self.icon_href = attrs["href"]
+
+    def handle_endtag(self, tag):
+        if tag == "title":
+            self._in_title = False
+
+    def handle_data(self, data):
+        if self._in_title:
+            self.title += data
+
+
+def _get(url: str, limit: int, timeout: float):
+    request = urllib.request.Request(url, headers={"User-Agent": USER_AGENT})
+    with urllib.request.urlopen(request, timeout=timeout) as response:
+        return response.read(limit), response.headers
+
+
+def fetch_source_meta(
+    url: str, favicon_dir: Path, timeout: float = 5.0
+) -> Dict[str, Any]:
+    """Fetch a page's <title> and download its favicon into favicon_dir."""
+    body, headers = _get(url, MAX_PAGE_BYTES, timeout)
+    charset = headers.get_content_charset() or "utf-8"
+    parser = _HeadParser()
+    parser.feed(body.decode(charset, errors="replace"))
+
+    favicon_url = urljoin(url, parser.icon_href or "/favicon.ico")
+    if not is_url(favicon_url):
+        # e.g. href="file:///etc/passwd" must not be read from disk
+        favicon_url = urljoin(url, "/favicon.ico")
+    favicon_path = None
+    try:
+        icon, _ = _get(favicon_url, MAX_FAVICON_BYTES, timeout)
+        if icon:
+            favicon_dir.mkdir(parents=True, exist_ok=True)
+            suffix = Path(urlparse(favicon_url).path).suffix or ".ico"
+            name = hashlib.sha256(favicon_url.encode("utf-8")).hexdigest()[:16]
+            favicon_path = favicon_dir / f"{name}{suffix}"
+            favicon_path.write_bytes(icon)
+    except Exception as e:
+        print(f"Favicon fetch failed for {favicon_url}: {e}")
+
+    return {
+        "title": " ".join(parser.title.split()) or None,
+        "favicon_url": favicon_url,
+        "favicon_path": str(favicon_path) if favicon_path else None,
+    }
@@ -0,0 +1,39 @@
+import sys
+from pathlib import Path
+
+import pytest
+
+ROOT = Path(__file__).parent.parent
+sys.path.insert(0, str(ROOT))
+sys.path.insert(0, str(ROOT / "server"))
+
+
+@pytest.fixture
+def server(tmp_path, monkeypatch):
+    """The FastAPI app running in-process against a temporary vault and database."""
+    monkeypatch.setenv("KMS_CONFIG_PATH", str(tmp_path / "config.yaml"))
+    monkeypatch.setenv("KMS_VAULT_PATH", str(tmp_path / "vault"))
+    monkeypatch.setenv("KMS_DB_PATH", str(tmp_path / "main.db"))
+    monkeypatch.delenv("KMS_DATA_DIR", raising=False)
+    monkeypatch.setenv("XDG_DATA_HOME", str(tmp_path / "data"))
+
+    import app
+
+    monkeypatch.setattr(app, "main_db", None)
+    monkeypatch.setattr(app, "_vault_dbs", {})
+    monkeypatch.setattr(app, "_config_path", None)
+    monkeypatch.setattr(app, "get_location", lambda config: None)
+    return app
+
+
+@pytest.fixture
+def client(server):
+    from fastapi.testclient import TestClient
+
+    with TestClient(server.app) as test_client:
+        yield test_client
+
+
+@pytest.fixture
+def vault(tmp_path):
+    return tmp_path / "vault"
@@ -0,0 +1,2742 @@
+import asyncio
+import base64
+import json
+import os
+import sqlite3
+import subprocess
+import time
+from concurrent.futures import ThreadPoolExecutor
+from datetime import datetime, timezone
+from pathlib import Path
+
+import pytest
+
+
+def capture(client, **fields):
+    response = client.post("/api/capture", data={"content": "note", **fields})
+    assert response.status_code == 200, response.json()
+    return Path(response.json()["saved_to"])
+
+
+class TestCaptureEndpoint:
+    def test_capture_writes_file_and_database_row(self, client, vault):
+        path = capture(client, content="hello world", tags="alpha,beta")
+
+        assert path.parent == vault / "capture" / "raw_capture"
+        assert "hello world" in path.read_text()
+        listed = client.get("/api/captures", params={"tag": "alpha"}).json()
+        assert [c["capture_id"] for c in listed["captures"]] == [path.stem]
+        assert listed["captures"][0]["tags"] == ["alpha", "beta"]
+
+    def test_empty_capture_is_rejected(self, client):
+        response = client.post("/api/capture", data={"content": ""})
+        assert response.status_code == 400
+
+
+class TestBlocks:
+    def test_invalid_blocks_return_json_error(self, client):
+        response = client.post("/api/capture", data={"blocks": "{not json"})
+        assert response.status_code == 400
+        assert response.json()["error"].startswith("Invalid blocks")
+
+    def test_blocks_without_a_heading_are_rejected(self, client):
+        blocks = json.dumps([{"heading": " ", "body": "- item"}])
+        response = client.post("/api/capture", data={"content": "x", "blocks": blocks})
+        assert response.status_code == 400
+        assert "heading" in response.json()["error"]
+
+
+class TestCaptureColor:
+    def test_color_is_stored_and_validated(self, client):
+        path = capture(client, color="#FF8800")
+
+        assert "color: '#ff8800'" in path.read_text()
+        listed = client.get("/api/captures").json()["captures"]
+        assert listed[0]["color"] == "#ff8800"
+        response = client.post("/api/capture", data={"content": "x", "color": "red"})
+        assert response.status_code == 400
+
+
+class TestCaptureStream:
+    def test_stream_returns_one_json_line_per_capture(self, client):
+        capture(client, tags="keep")
+        capture(client, content="other", tags="keep")
+        capture(client, content="skipped")
+
+        response = client.get("/api/captures/stream", params={"tag": "keep"})
+
+        assert response.headers["content-type"].startswith("application/x-ndjson")
+        rows = [json.loads(line) for line in response.text.splitlines()]
+        assert len(rows) == 2
+        assert all(row["tags"] == ["keep"] for row in rows)
+
+    def test_captures_saved_mid_stream_do_not_shift_pages(
+        self, server, client, monkeypatch
+    ):
+        for day in range(1, 4):
+            capture(client, capture_id=f"c{day}", timestamp=f"2025-01-0{day}T00:00:00Z")
+        monkeypatch.setattr(server, "STREAM_PAGE_SIZE", 2)
+        db = server.get_main_db()
+        list_captures = db.list_captures
+
+        def list_then_capture(*args, **kwargs):
+            page = list_captures(*args, **kwargs)
+            if not db.capture_exists("new"):
+                capture(client, capture_id="new")
+            return page
+
+        monkeypatch.setattr(db, "list_captures", list_then_capture)
+
+        response = client.get("/api/captures/stream")
+
+        ids = [json.loads(line)["capture_id"] for line in response.text.splitlines()]
+        assert ids == ["c3", "c2", "c1"]
+
+
+class TestInlineImages:
+    def test_inline_data_uri_images_are_saved_as_media(self, client, vault):
+        path = capture(
+            client, content="Look: ![chart](data:image/png;base64,iVBORw0KGgo=) done"
+        )
+
+        text = path.read_text()
+        assert "data:image" not in text
+        assert "![chart](media/" in text
+        assert "## Image" not in text
+        (saved,) = (vault / "capture" / "raw_capture" / "media").glob("*_inline_1.png")
+        assert saved.read_bytes() == b"\x89PNG\r\n\x1a\n"
+
+    def test_inline_images_are_not_written_for_rejected_captures(
+        self, client, tmp_path, vault
+    ):
+        (tmp_path / "config.yaml").write_text("capture:\n  duplicate_ids: reject\n")
+        capture(client, capture_id="taken")
+
+        response = client.post(
+            "/api/capture",
+            data={
+                "content": "![a](data:image/png;base64,iVBORw0KGgo=)",
+                "capture_id": "taken",
+            },
+        )
+
+        assert response.status_code == 409
+        media_dir = vault / "capture" / "raw_capture" / "media"
+        assert list(media_dir.glob("*_inline_*")) == []
+
+    def test_inline_svg_is_not_saved(self, client, vault):
+        svg = "![x](data:image/svg+xml;base64,PHN2Zy8+)"
+
+        path = capture(client, content=f"logo {svg}")
+
+        assert svg in path.read_text()
+        media_dir = vault / "capture" / "raw_capture" / "media"
+        assert list(media_dir.glob("*_inline_*")) == []
+
+
+class TestWrittenSections:
+    def test_response_lists_sections_written(self, client):
+        response = client.post(
+            "/api/capture",
+            data={"content": "hi", "clipboard": "```\n## not a section\n```"},
+        )
+        assert response.json()["sections"] == ["Content", "Clipboard"]
+
+
+class TestCursorPagination:
+    def test_cursor_pagination_is_stable_across_new_captures(self, client):
+        for n in range(3):
+            capture(client, content=f"c{n}", timestamp=f"2025-01-0{n + 1}T00:00:00Z")
+
+        first = client.get("/api/captures", params={"limit": "2"}).json()
+        capture(client, content="arrived mid-scroll")
+        second = client.get(
+            "/api/captures", params={"limit": "2", "after": first["next_cursor"]}
+        ).json()
+
+        seen = [c["timestamp"][:10] for c in first["captures"] + second["captures"]]
+        assert seen == ["2025-01-03", "2025-01-02", "2025-01-01"]
+        assert second["next_cursor"] is None
+        assert client.get("/api/captures", params={"after": "nope"}).status_code == 400
+
+
+class TestValueDedup:
+    def test_duplicate_and_blank_values_are_dropped(self, client):
+        path = capture(
+            client, tags="foo, Foo,,  ,bar,foo,", sources="a,a", context="home, Home"
+        )
+
+        listed = client.get("/api/captures").json()["captures"][0]
+        assert listed["tags"] == ["foo", "bar"]
+        assert listed["sources"] == ["a"]
+        assert listed["context"] == "home"
+        assert path.read_text().count("- foo") == 1
+
+    def test_case_sensitive_dedup_is_configurable(self, client, tmp_path):
+        (tmp_path / "config.yaml").write_text(
+            "capture:\n  dedup_case_insensitive: false\n"
+        )
+        capture(client, tags="Foo,foo,foo")
+        listed = client.get("/api/captures").json()["captures"][0]
+        assert listed["tags"] == ["Foo", "foo"]
+
+
+class TestMediaEmbedding:
+    def test_media_is_tracked_when_not_embedded(self, server, client, tmp_path):
+        (tmp_path / "config.yaml").write_text("vault:\n  embed_media: false\n")
+        response = client.post(
+            "/api/capture",
+            data={"content": "text first"},
+            files={"media": ("pic.png", b"png", "image/png")},
+        )
+
+        path = Path(response.json()["saved_to"])
+        assert "pic.png" not in path.read_text()
+        (media,) = server.get_main_db().get_media_paths(path.stem)
+        assert media.endswith("pic.png")
+
+
+class TestMediaPaths:
+    def test_database_media_path_matches_markdown_link(self, server, client, vault):
+        response = client.post(
+            "/api/capture",
+            data={"content": "pic"},
+            files={"media": ("pic.png", b"png", "image/png")},
+        )
+        path = Path(response.json()["saved_to"])
+
+        assert "](media/pic.png)" in path.read_text()
+        with sqlite3.connect(server.get_main_db().db_path) as conn:
+            (stored,) = conn.execute("SELECT file_path FROM media_files").fetchone()
+        assert stored == "capture/raw_capture/media/pic.png"
+        assert (vault / stored).resolve() == (path.parent / "media/pic.png").resolve()
+
+
+class TestCaptureAliases:
+    def test_explicit_aliases_without_capture_id(self, client, tmp_path):
+        (tmp_path / "config.yaml").write_text("capture:\n  aliases: none\n")
+        path = capture(client, alias="Standup", aliases="standup, Standup, sync")
+
+        assert "aliases:\n- Standup\n- standup\n- sync\n" in path.read_text()
+
+
+class TestDatabaseInit:
+    def test_concurrent_first_captures_share_one_database(
+        self, client, server, monkeypatch
+    ):
+        opened = []
+        open_main_db = server._open_main_db
+        monkeypatch.setattr(
+            server, "_open_main_db", lambda cfg: opened.append(cfg) or open_main_db(cfg)
+        )
+
+        def post(n):
+            return client.post("/api/capture", data={"content": f"burst {n}"})
+
+        with ThreadPoolExecutor(max_workers=16) as pool:
+            responses = list(pool.map(post, range(32)))
+
+        assert [r.status_code for r in responses] == [200] * 32
+        listed = client.get("/api/captures", params={"limit": 100}).json()
+        assert listed["count"] == 32
+        assert len(opened) == 1
+
+    def test_cached_database_is_returned_without_the_lock(self, server, client):
+        db = server.get_main_db()
+
+        with ThreadPoolExecutor(max_workers=1) as pool, server._main_db_lock:
+            assert pool.submit(server.get_main_db).result(timeout=5) is db
+
+
+class TestFilenamePattern:
+    def test_filename_pattern_keeps_capture_id_for_linking(self, client, tmp_path):
+        (tmp_path / "config.yaml").write_text(
+            "vault:\n  filename_pattern: '{date}-{slug}'\n"
+        )
+
+        response = client.post("/api/capture", data={"content": "Call the dentist"})
+
+        saved = response.json()
+        path = Path(saved["saved_to"])
+        assert path.name.endswith("-call-the-dentist.md")
+        assert saved["capture_id"] != path.stem
+        client.put(f"/api/captures/{saved['capture_id']}", json={"tags": ["todo"]})
+        assert "- todo\n" in path.read_text()
+        listed = client.get("/api/captures", params={"tag": "todo"}).json()
+        assert listed["captures"][0]["capture_id"] == saved["capture_id"]
+        assert listed["captures"][0]["file_path"] == str(path)
+
+
+class TestContextInheritance:
+    def test_omitted_context_inherits_the_last_one(self, client, tmp_path):
+        config = "capture:\n  inherit_last_context: true\n"
+        (tmp_path / "config.yaml").write_text(config)
+
+        capture(client, context="thesis writing")
+        inherited = capture(client, content="next thought")
+        overridden = capture(client, content="aside", context="errands")
+        after_override = capture(client, content="more errands")
+
+        assert "- thesis writing" in inherited.read_text()
+        assert "- errands" in overridden.read_text()
+        assert "- errands" in after_override.read_text()
+
+    def test_context_is_not_inherited_by_default(self, client):
+        capture(client, context="thesis writing")
+        assert "context: []" in capture(client, content="next").read_text()
+
+
+class TestMirrorPaths:
+    def test_mirrors_are_refreshed_after_edits(self, client, tmp_path):
+        mirror = tmp_path / "mirror"
+        (tmp_path / "config.yaml").write_text(f"vault:\n  mirror_paths: [{mirror}]\n")
+        path = capture(client, content="draft")
+        mirrored = mirror / "capture" / "raw_capture" / path.name
+        assert "draft" in mirrored.read_text()
+
+        response = client.put(f"/api/captures/{path.stem}", json={"content": "final"})
+
+        assert response.json()["mirrored_to"] == [str(mirror)]
+        assert mirrored.read_text() == path.read_text()
+        assert "final" in mirrored.read_text()
+
+
+class TestDuplicateIds:
+    def configure(self, tmp_path, mode=None):
+        config = "vault:\n  filename_pattern: '{slug}'\n"
+        if mode:
+            config += f"capture:\n  duplicate_ids: {mode}\n"
+        (tmp_path / "config.yaml").write_text(config)
+
+    def listed(self, client):
+        captures = client.get("/api/captures").json()["captures"]
+        return sorted((c["capture_id"], c["content"]) for c in captures)
+
+    def test_resubmitted_id_edits_the_capture_by_default(self, client, tmp_path):
+        self.configure(tmp_path)
+
+        first = capture(client, content="first", capture_id="meeting")
+        second = capture(client, content="second", capture_id="meeting")
+
+        assert second == first and "second" in first.read_text()
+        assert self.listed(client) == [("meeting", "second")]
+
+    def test_generated_ids_never_overwrite(self, client, server, monkeypatch):
+        monkeypatch.setattr(
+            server.SafeMarkdownWriter,
+            "find_capture_file",
+            lambda writer, capture_id: pytest.fail("looked up a generated id"),
+        )
+        for content in ("first", "second"):
+            capture(client, content=content, timestamp="2025-01-01T00:00:00Z")
+
+        assert [content for _, content in self.listed(client)] == ["first", "second"]
+
+    def test_suffix_gives_a_shared_id_a_distinct_row(self, client, tmp_path):
+        self.configure(tmp_path, "suffix")
+
+        first = capture(client, content="first", capture_id="meeting")
+        second = capture(client, content="second", capture_id="meeting")
+
+        assert first != second and first.exists() and second.exists()
+        assert "capture_id: meeting_1" in second.read_text()
+        assert self.listed(client) == [
+            ("meeting", "first"),
+            ("meeting_1", "second"),
+        ]
+
+    def test_row_survives_when_its_file_is_gone(self, client, tmp_path):
+        self.configure(tmp_path, "suffix")
+        first = capture(client, content="first", capture_id="meeting")
+        first.unlink()
+
+        capture(client, content="second", capture_id="meeting")
+
+        assert self.listed(client) == [
+            ("meeting", "first"),
+            ("meeting_1", "second"),
+        ]
+
+    def test_replace_overwrites_file_and_row(self, client, tmp_path):
+        self.configure(tmp_path, "replace")
+
+        first = capture(client, content="first", capture_id="meeting")
+        second = capture(client, content="second", capture_id="meeting")
+
+        assert second == first and "second" in first.read_text()
+        assert self.listed(client) == [("meeting", "second")]
+
+    def test_replace_is_an_edit_of_the_capture(self, client, tmp_path, vault):
+        self.configure(tmp_path, "replace")
+        first = capture(
+            client, content="first", capture_id="meeting", created_date="2020-01-01"
+        )
+
+        capture(client, content="second", capture_id="meeting")
+
+        text = first.read_text()
+        today = datetime.now(timezone.utc).strftime("%Y-%m-%d")
+        assert "created_date: '2020-01-01'" in text
+        assert f"last_edited_date: '{today}'" in text
+        assert list(first.parent.glob("*.md")) == [first]
+
+    def test_replace_updates_a_file_the_database_lacks(self, client, tmp_path, vault):
+        (tmp_path / "config.yaml").write_text("capture:\n  duplicate_ids: replace\n")
+        capture_dir = vault / "capture" / "raw_capture"
+        capture_dir.mkdir(parents=True, exist_ok=True)
+        (capture_dir / "handmade.md").write_text(
+            "---\ncapture_id: handmade\ncreated_date: '2019-05-05'\n---\nold\n"
+        )
+
+        saved = capture(client, content="new", capture_id="handmade")
+
+        assert saved == capture_dir / "handmade.md"
+        assert "created_date: '2019-05-05'" in saved.read_text()
+        assert list(capture_dir.glob("*.md")) == [saved]
+
+    def test_replace_refuses_a_locked_capture_without_force(self, client, tmp_path):
+        self.configure(tmp_path, "replace")
+        first = capture(client, content="first", capture_id="meeting")
+        assert client.post("/api/captures/meeting/lock").status_code == 200
+
+        response = client.post(
+            "/api/capture", data={"content": "second", "capture_id": "meeting"}
+        )
+
+        assert response.status_code == 409
+        assert "first" in first.read_text()
+        assert self.listed(client) == [("meeting", "first")]
+        capture(client, content="second", capture_id="meeting", force="true")
+        assert self.listed(client) == [("meeting", "second")]
+
+    def test_concurrent_replaces_keep_one_file(self, client, tmp_path, vault):
+        self.configure(tmp_path, "replace")
+
+        def post(n):
+            data = {"content": f"take {n}", "capture_id": "meeting"}
+            return client.post("/api/capture", data=data)
+
+        with ThreadPoolExecutor(max_workers=8) as pool:
+            responses = list(pool.map(post, range(8)))
+
+        assert [r.status_code for r in responses] == [200] * 8
+        assert len(list((vault / "capture" / "raw_capture").glob("*.md"))) == 1
+        assert len(self.listed(client)) == 1
+
+    def test_reject_refuses_the_second_capture(self, client, tmp_path):
+        self.configure(tmp_path, "reject")
+        capture(client, content="first", capture_id="meeting")
+
+        response = client.post(
+            "/api/capture", data={"content": "second", "capture_id": "meeting"}
+        )
+
+        assert response.status_code == 409
+        assert self.listed(client) == [("meeting", "first")]
+
+
+class TestBackdatedTimestamp:
+    def test_explicit_timestamp_is_used_everywhere(self, client):
+        response = client.post(
+            "/api/capture",
+            data={"content": "past", "timestamp": "2020-05-01T12:30:00+02:00"},
+        )
+
+        path = Path(response.json()["saved_to"])
+        assert path.stem == "2020-05-01T10:30:00+00:00"
+        assert "timestamp: '2020-05-01T10:30:00+00:00'" in path.read_text()
+        (row,) = client.get("/api/captures").json()["captures"]
+        assert row["timestamp"].startswith("2020-05-01T10:30:00")
+
+    def test_invalid_timestamp_falls_back_to_now_with_warning(self, client):
+        response = client.post(
+            "/api/capture", data={"content": "now", "timestamp": "yesterday"}
+        ).json()
+
+        assert response["warnings"] == [
+            "Ignoring invalid timestamp 'yesterday'; using current time"
+        ]
+        assert Path(response["saved_to"]).stem.startswith(
+            datetime.now(timezone.utc).strftime("%Y-%m-%d")
+        )
+
+
+class TestImageCapture:
+    PNG = b"\x89PNG\r\n\x1a\nrest"
+
+    def post(self, client, data, mime="image/png"):
+        return client.post(
+            "/api/capture/image",
+            json={
+                "data_base64": base64.b64encode(data).decode(),
+                "mime": mime,
+                "tags": "web,clip",
+            },
+        )
+
+    def test_image_is_saved_and_captured(self, client):
+        response = self.post(client, self.PNG).json()
+
+        image = Path(response["image_path"])
+        assert image.suffix == ".png" and image.read_bytes() == self.PNG
+        text = Path(response["saved_to"]).read_text()
+        assert f"media/{image.name})" in text
+        assert "tags:\n- web\n- clip\n" in text
+
+    def test_type_mismatch_and_size_are_checked(self, client, tmp_path):
+        assert self.post(client, self.PNG, mime="image/svg+xml").status_code == 415
+        assert self.post(client, b"GIF89a", mime="image/png").status_code == 400
+        (tmp_path / "config.yaml").write_text("capture:\n  max_image_bytes: 4\n")
+        assert self.post(client, self.PNG).status_code == 413
+
+
+class TestModalityInference:
+    def test_submitted_clipboard_and_audio_are_added(self, server, client):
+        response = client.post(
+            "/api/capture",
+            data={"content": "memo", "clipboard": "copied", "modalities": "text"},
+            files={"media": ("memo.ogg", b"ogg", "application/octet-stream")},
+        )
+
+        text = Path(response.json()["saved_to"]).read_text()
+        assert "modalities:\n- text\n- clipboard\n- audio\n" in text
+        assert "## Audio\n" in text
+        with sqlite3.connect(server.get_main_db().db_path) as conn:
+            (file_type,) = conn.execute("SELECT file_type FROM media_files").fetchone()
+        assert file_type == "audio"
+
+    def test_inference_can_be_turned_off(self, client, tmp_path):
+        (tmp_path / "config.yaml").write_text(
+            "capture:\n  auto_detect_modalities: false\n"
+        )
+        text = capture(client, clipboard="copied", modalities="text").read_text()
+        assert "modalities:\n- text\n" in text and "- clipboard\n" not in text
+
+
+class TestQuickCapture:
+    def test_applies_configured_defaults_and_last_context(self, client, tmp_path):
+        (tmp_path / "config.yaml").write_text(
+            "capture:\n  inherit_last_context: true\n  quick:\n    tags: [inbox]\n"
+        )
+        capture(client, context="deep work")
+
+        response = client.post("/api/quick", json={"content": "stash this"})
+
+        quick = response.json()
+        assert list(quick) == ["capture_id", "saved_to"]
+        assert "stash this" in Path(quick["saved_to"]).read_text()
+        (row,) = client.get("/api/captures", params={"tag": "inbox"}).json()[
+            "captures"
+        ]
+        assert row["capture_id"] == quick["capture_id"]
+        assert "context:\n- deep work\n" in Path(quick["saved_to"]).read_text()
+
+    def test_context_follows_inherit_last_context(self, client):
+        capture(client, context="deep work")
+
+        quick = client.post("/api/quick", json={"content": "stash this"}).json()
+
+        assert "context: []" in Path(quick["saved_to"]).read_text()
+
+    def test_requires_content(self, client):
+        response = client.post("/api/quick", json={"content": "  "})
+        assert response.status_code == 400
+
+
+class TestClipboardEndpoint:
+    def paste(self, server, monkeypatch, tmp_path, policy, raw):
+        (tmp_path / "config.yaml").write_text(
+            f"capture:\n  clipboard_utf8_policy: {policy}\n"
+        )
+        monkeypatch.setattr(
+            server.subprocess,
+            "run",
+            lambda *args, **kwargs: subprocess.CompletedProcess(args, 0, raw, b""),
+        )
+
+    def test_invalid_utf8_is_replaced_by_default(
+        self, server, client, monkeypatch, tmp_path
+    ):
+        self.paste(server, monkeypatch, tmp_path, "lossy", b"caf\xe9")
+        assert client.get("/api/clipboard").json()["content"] == "caf\ufffd"
+
+    def test_reject_and_base64_policies(self, server, client, monkeypatch, tmp_path):
+        self.paste(server, monkeypatch, tmp_path, "reject", b"caf\xe9")
+        assert client.get("/api/clipboard").status_code == 400
+
+        self.paste(server, monkeypatch, tmp_path, "base64", b"caf\xe9")
+        assert client.get("/api/clipboard").json() == {
+            "content": "```base64\nY2Fm6Q==\n```",
+            "type": "base64",
+        }
+
+
+class TestCaptureToolFallback:
+    def install(self, server, monkeypatch, installed, calls, failing=()):
+        def run(command, timeout=None, **kwargs):
+            calls.append(command[0])
+            if command[0] not in installed:
+                raise FileNotFoundError(command[0])
+            if command[0] in failing:
+                return subprocess.CompletedProcess(command, 1, b"", b"no display")
+            if command[-1].endswith(".png"):
+                Path(command[-1]).write_bytes(b"png")
+            return subprocess.CompletedProcess(command, 0, b"copied", b"")
+
+        monkeypatch.setattr(server.subprocess, "run", run)
+
+    def test_clipboard_falls_back_to_the_next_tool(self, server, client, monkeypatch):
+        calls = []
+        self.install(server, monkeypatch, {"xclip"}, calls)
+
+        assert client.get("/api/clipboard").json() == {
+            "content": "copied",
+            "type": "text",
+        }
+        assert calls == ["wl-paste", "xclip"]
+
+    def test_missing_clipboard_tools_are_named(self, server, client, monkeypatch):
+        self.install(server, monkeypatch, set(), [])
+
+        response = client.get("/api/clipboard").json()
+
+        assert response["content"] == ""
+        assert "wl-paste, xclip, xsel, pbpaste" in response["error"]
+
+    def test_config_forces_a_tool(self, server, client, monkeypatch, tmp_path):
+        (tmp_path / "config.yaml").write_text(
+            "capture:\n  clipboard_tools: [pbpaste]\n"
+            "  screenshot_tools: [maim]\n"
+        )
+        calls = []
+        self.install(server, monkeypatch, {"pbpaste", "maim", "wl-paste"}, calls)
+
+        assert client.get("/api/clipboard").json()["content"] == "copied"
+        assert client.post("/api/screenshot").json()["success"]
+        assert calls == ["pbpaste", "maim"]
+
+    def test_missing_screenshot_tools_are_named(
+        self, server, client, monkeypatch, vault
+    ):
+        self.install(server, monkeypatch, set(), [])
+
+        response = client.post("/api/screenshot").json()
+
+        assert not response["success"]
+        assert "grimblast, spectacle, maim" in response["error"]
+        media_dir = vault / "capture" / "raw_capture" / "media"
+        assert list(media_dir.glob("*_screenshot.png")) == []
+
+    def test_failing_screenshot_tool_falls_back_to_the_next(
+        self, server, client, monkeypatch
+    ):
+        calls = []
+        installed = {"grimblast", "maim", "import"}
+        self.install(server, monkeypatch, installed, calls, failing={"grimblast"})
+
+        response = client.post("/api/screenshot").json()
+
+        assert response["success"]
+        assert Path(response["path"]).read_bytes() == b"png"
+        assert calls == ["grimblast", "spectacle", "maim"]
+
+    def test_failed_screenshot_tools_are_named(self, server, client, monkeypatch):
+        installed = {"grimblast", "maim"}
+        self.install(server, monkeypatch, installed, [], failing=installed)
+
+        response = client.post("/api/screenshot").json()
+
+        assert not response["success"]
+        assert response["error"] == "Screenshot failed; tried grimblast, maim"
+
+    def test_unknown_tool_is_rejected(self, client, tmp_path):
+        (tmp_path / "config.yaml").write_text("capture:\n  clipboard_tools: [xerox]\n")
+        assert client.get("/api/clipboard").status_code == 400
+
+
+class TestScreenshotOutputs:
+    def fake_compositor(self, server, monkeypatch, calls):
+        outputs = [
+            {"name": "DP-1", "make": "Dell", "model": "U2720Q", "focused": True},
+            {"name": "HDMI-A-1", "description": "TV", "active": False},
+        ]
+
+        def run(command, timeout=None, **kwargs):
+            calls.append(command)
+            if command[0] == "swaymsg":
+                return subprocess.CompletedProcess(command, 0, json.dumps(outputs), "")
+            Path(command[-1]).write_bytes(b"png")
+            return subprocess.CompletedProcess(command, 0, b"", b"")
+
+        monkeypatch.setattr(server.subprocess, "run", run)
+
+    def test_lists_active_outputs(self, server, client, monkeypatch):
+        self.fake_compositor(server, monkeypatch, [])
+
+        assert client.get("/api/outputs").json() == {
+            "outputs": [{"name": "DP-1", "description": "Dell U2720Q", "focused": True}]
+        }
+
+    def test_output_captures_one_monitor(self, server, client, monkeypatch):
+        calls = []
+        self.fake_compositor(server, monkeypatch, calls)
+
+        response = client.post("/api/screenshot", params={"output": "DP-1"}).json()
+
+        assert (response["mode"], response["output"]) == ("output", "DP-1")
+        assert calls[0][:3] == ["grim", "-o", "DP-1"]
+
+
+class TestWindowScreenshot:
+    def fake_tools(self, server, monkeypatch, tree, calls):
+        def run(command, timeout=None, **kwargs):
+            calls.append(command[0])
+            if command[0] == "swaymsg":
+                return subprocess.CompletedProcess(command, 0, json.dumps(tree), "")
+            if command[0] == "hyprctl":
+                raise FileNotFoundError(command[0])
+            if command[-1].endswith(".png"):
+                Path(command[-1]).write_bytes(b"png")
+            return subprocess.CompletedProcess(command, 0, b"png", b"")
+
+        monkeypatch.setattr(server.subprocess, "run", run)
+
+    def test_focused_window_geometry_is_captured(self, server, client, monkeypatch):
+        rect = {"x": 10, "y": 20, "width": 300, "height": 200}
+        window = {"focused": True, "rect": rect}
+        calls = []
+        self.fake_tools(server, monkeypatch, {"nodes": [window]}, calls)
+
+        response = client.post("/api/screenshot", params={"mode": "window"}).json()
+
+        assert response["success"] and response["mode"] == "window"
+        assert server._active_window_geometry() == "10,20 300x200"
+        assert calls[:2] == ["swaymsg", "grim"]
+
+    def test_falls_back_to_region_without_a_focused_window(
+        self, server, client, monkeypatch
+    ):
+        calls = []
+        self.fake_tools(server, monkeypatch, {"nodes": []}, calls)
+
+        response = client.post("/api/screenshot", params={"mode": "window"}).json()
+
+        assert response["success"] and response["mode"] == "region"
+        assert "grim" not in calls and "grimblast" in calls
+
+
+class TestCommandTimeouts:
+    def hang(self, server, monkeypatch, calls):
+        def run(command, timeout=None, **kwargs):
+            calls.append((command[0], timeout))
+            raise subprocess.TimeoutExpired(command, timeout)
+
+        monkeypatch.setattr(server.subprocess, "run", run)
+
+    def test_hung_clipboard_returns_timeout_error(
+        self, server, client, monkeypatch, tmp_path
+    ):
+        (tmp_path / "config.yaml").write_text(
+            "capture:\n  clipboard_timeout_seconds: 0.5\n"
+        )
+        calls = []
+        self.hang(server, monkeypatch, calls)
+
+        response = client.get("/api/clipboard")
+
+        assert response.status_code == 504
+        assert "timed out" in response.json()["error"]
+        assert calls == [("wl-paste", 0.5)]
+
+    def test_hung_screenshot_returns_timeout_error(
+        self, server, client, monkeypatch, vault
+    ):
+        calls = []
+        self.hang(server, monkeypatch, calls)
+
+        response = client.post("/api/screenshot")
+
+        assert response.status_code == 504
+        assert calls == [("grimblast", 60.0)]
+        media_dir = vault / "capture" / "raw_capture" / "media"
+        assert list(media_dir.glob("*_screenshot.png")) == []
+
+
+class TestServerConfig:
+    def test_loopback_is_the_default_bind(self, server):
+        assert server._bind_host({}) == "127.0.0.1"
+
+    def test_public_bind_requires_cors_and_rate_limit(self, server):
+        public = {"host": "0.0.0.0", "cors_origins": ["*"]}
+        with pytest.raises(ValueError, match="cors_origins"):
+            server._bind_host({"server": public})
+        public["cors_origins"] = ["http://phone.lan"]
+        with pytest.raises(ValueError, match="rate_limit_per_minute"):
+            server._bind_host({"server": public})
+        public["rate_limit_per_minute"] = 60
+        assert server._bind_host({"server": public}) == "0.0.0.0"
+
+    def test_worker_threads_from_env_or_config(self, server, monkeypatch):
+        monkeypatch.delenv("KMS_WORKER_THREADS", raising=False)
+        assert server._worker_threads({}) is None
+        assert server._worker_threads({"server": {"worker_threads": 4}}) == 4
+        assert server._worker_threads({"server": {"worker_threads": "lots"}}) is None
+        monkeypatch.setenv("KMS_WORKER_THREADS", "2")
+        assert server._worker_threads({"server": {"worker_threads": 4}}) == 2
+
+    def test_serve_caps_the_worker_thread_pool(self, server, monkeypatch):
+        monkeypatch.delenv("KMS_WORKER_THREADS", raising=False)
+        seen = []
+
+        async def serve(app, config):
+            limiter = server.anyio.to_thread.current_default_thread_limiter()
+            seen.append(limiter.total_tokens)
+
+        monkeypatch.setattr(server, "serve", serve)
+        cfg = {"server": {"worker_threads": 3}, "capture": {"expiry_sweep_seconds": 0}}
+
+        asyncio.run(server._serve(None, cfg))
+
+        assert seen == [3]
+
+    def test_rate_limiter_resets_each_minute(self, server):
+        limiter = server.RateLimiter(2)
+        assert [limiter.allow("phone", now=0) for _ in range(3)] == [
+            True,
+            True,
+            False,
+        ]
+        assert limiter.allow("laptop", now=0)
+        assert limiter.allow("phone", now=60)
+
+
+class TestDatabasePathTemplate:
+    def test_template_per_mode_and_env_precedence(self, server, tmp_path, monkeypatch):
+        monkeypatch.delenv("KMS_DB_PATH")
+        templates = {"dev": "{data_dir}/{mode}.db", "prod": str(tmp_path / "p.db")}
+
+        def db_path(mode):
+            cfg = {
+                "development": {"mode": mode},
+                "database": {"path_template": templates},
+            }
+            return server.normalize_config(cfg)["database"]["path"]
+
+        assert db_path("dev") == str(tmp_path / "data" / "kms-capture" / "dev.db")
+        assert db_path("prod") == str(tmp_path / "p.db")
+        monkeypatch.setenv("KMS_DB_PATH", str(tmp_path / "env.db"))
+        assert db_path("dev") == str(tmp_path / "env.db")
+
+    def test_unknown_placeholder_is_rejected(self, server, monkeypatch):
+        monkeypatch.delenv("KMS_DB_PATH")
+        with pytest.raises(ValueError, match="Unknown placeholder"):
+            server.normalize_config({"database": {"path_template": "{nope}/main.db"}})
+
+
+class TestConfigDiscovery:
+    def test_env_then_xdg_then_repo_root(self, server, tmp_path, monkeypatch):
+        xdg_config = tmp_path / "xdg" / "kms-capture" / "config.yaml"
+        monkeypatch.setenv("XDG_CONFIG_HOME", str(tmp_path / "xdg"))
+
+        assert server._discover_config_path() == tmp_path / "config.yaml"
+        monkeypatch.delenv("KMS_CONFIG_PATH")
+        repo_config = Path(server.__file__).resolve().parent.parent / "config.yaml"
+        assert server._discover_config_path() == repo_config
+        xdg_config.parent.mkdir(parents=True)
+        xdg_config.write_text("vault: {}\n")
+        assert server._discover_config_path() == xdg_config
+
+    def test_home_config_dir_without_xdg(self, server, tmp_path, monkeypatch):
+        monkeypatch.delenv("KMS_CONFIG_PATH")
+        monkeypatch.delenv("XDG_CONFIG_HOME", raising=False)
+        monkeypatch.setenv("HOME", str(tmp_path))
+        home_config = tmp_path / ".config" / "kms-capture" / "config.yaml"
+        home_config.parent.mkdir(parents=True)
+        home_config.touch()
+
+        assert server._discover_config_path() == home_config
+
+
+class TestRawConfig:
+    def test_round_trips_and_reports_restart_sections(
+        self, server, client, tmp_path, monkeypatch
+    ):
+        monkeypatch.setattr(server, "LOOPBACK_HOSTS", {"testclient"})
+        config_file = tmp_path / "config.yaml"
+        config_file.write_text("capture:\n  aliases: none\n")
+        assert client.get("/api/config/raw").text == config_file.read_text()
+
+        text = "# edited in app\ncapture:\n  aliases: title\nserver:\n  port: 9000\n"
+        response = client.put("/api/config/raw", json={"content": text})
+
+        assert response.json() == {
+            "saved_to": str(config_file),
+            "restart_required": ["server"],
+        }
+        assert config_file.read_text() == text
+        assert client.get("/api/config").json()["capture"]["aliases"] == "title"
+
+    def test_invalid_yaml_reports_position_and_keeps_file(
+        self, server, client, tmp_path, monkeypatch
+    ):
+        monkeypatch.setattr(server, "LOOPBACK_HOSTS", {"testclient"})
+        config_file = tmp_path / "config.yaml"
+        config_file.write_text("capture: {}\n")
+
+        response = client.put(
+            "/api/config/raw", json={"content": "vault:\n  path: [unclosed\n"}
+        )
+
+        assert response.status_code == 400
+        assert (response.json()["line"], response.json()["column"]) == (3, 1)
+        assert client.put("/api/config/raw", json={"content": "- a list"}).json() == {
+            "error": "Config must be a YAML mapping"
+        }
+        assert config_file.read_text() == "capture: {}\n"
+        assert list(tmp_path.glob("*.tmp")) == []
+
+    def test_only_loopback_clients_may_write(self, client, tmp_path):
+        config_file = tmp_path / "config.yaml"
+        config_file.write_text("capture: {}\n")
+
+        response = client.put(
+            "/api/config/raw",
+            json={"content": "capture:\n  post_save_hook: 'rm -rf ~'\n"},
+        )
+
+        assert response.status_code == 403
+        assert config_file.read_text() == "capture: {}\n"
+
+
+class TestCaptureSchema:
+    def test_describes_the_capture_form_fields(self, client):
+        schema = client.get("/api/schema/capture").json()
+
+        assert schema["title"] == "CaptureForm"
+        assert schema["required"] == []
+        properties = schema["properties"]
+        assert properties["content"]["type"] == "string"
+        assert properties["timestamp"]["default"] == ""
+        assert "media" in properties and "capture_id" in properties
+
+
+class TestTemplatePreview:
+    def test_renders_sample_and_lists_unresolved(self, client, tmp_path, vault):
+        template = vault / "templates" / "audio.md"
+        template.parent.mkdir(parents=True)
+        template.write_text("{{sections}}Tags: {{tags}} {{mood}} {{ weather }}\n")
+        (tmp_path / "config.yaml").write_text(
+            "capture:\n  template_file:\n    audio: templates/audio.md\n"
+        )
+
+        preview = client.get("/api/templates/audio/preview").json()
+
+        assert preview["template"] == str(template)
+        assert preview["unresolved"] == ["mood", "weather"]
+        assert "## Audio\n" in preview["markdown"]
+        assert preview["markdown"].endswith("Tags: sample-tag  \n")
+        assert not list((vault / "capture" / "raw_capture").glob("*.md"))
+
+        text = client.get("/api/templates/text/preview").json()
+        assert text["template"] is None and text["unresolved"] == []
+        assert "## Content\nSample capture content\n" in text["markdown"]
+        assert client.get("/api/templates/video/preview").status_code == 400
+
+
+class TestUnixSocket:
+    def test_newline_delimited_captures_round_trip(self, server, vault, tmp_path):
+        async def exchange(lines):
+            listener = await server._start_unix_socket(str(tmp_path / "kms.sock"))
+            reader, writer = await asyncio.open_unix_connection(
+                str(tmp_path / "kms.sock")
+            )
+            writer.write(b"".join(line.encode() + b"\n" for line in lines))
+            writer.write_eof()
+            replies = [json.loads(line) for line in (await reader.read()).splitlines()]
+            writer.close()
+            listener.close()
+            await listener.wait_closed()
+            return replies
+
+        ok, bad_json, bad_field = asyncio.run(
+            exchange(
+                [
+                    json.dumps({"content": "from shell", "tags": ["cli", "x"]}),
+                    "{not json",
+                    json.dumps({"content": "x", "color": "blue"}),
+                ]
+            )
+        )
+
+        assert ok["ok"] is True
+        saved = Path(ok["saved_to"])
+        assert saved.parent == vault / "capture" / "raw_capture"
+        assert "from shell" in saved.read_text()
+        assert "- cli" in saved.read_text()
+        assert bad_json["ok"] is False and "Invalid JSON" in bad_json["error"]
+        assert bad_field["ok"] is False
+
+
+class TestAudioRecorders:
+    @pytest.fixture
+    def recorders(self, server, monkeypatch):
+        audio_recorder = pytest.importorskip("audio_recorder")
+        np = pytest.importorskip("numpy")
+
+        class FakeRecorder(audio_recorder.AudioRecorder):
+            def _get_device_info(self):
+                return {"name": "fake"}
+
+            def _get_stream_params(self):
+                return {}
+
+            def _record_audio(self):
+                while self.is_recording:
+                    self.audio_data.append(np.zeros((64, 1), dtype=np.float32))
+                    time.sleep(0.01)
+
+        monkeypatch.setitem(audio_recorder.RECORDER_TYPES, "fake", FakeRecorder)
+        manager = audio_recorder.AudioRecordingManager()
+        monkeypatch.setattr(server, "audio_manager", manager)
+        monkeypatch.setattr(server, "AUDIO_RECORDING_AVAILABLE", True)
+        return manager
+
+    def start(self, client, recorder_id):
+        return client.post(
+            "/api/audio/start",
+            data={"recorder_type": "fake", "recorder_id": recorder_id},
+        )
+
+    def test_unsafe_recorder_id_is_rejected(self, server, client, monkeypatch):
+        monkeypatch.setattr(server, "AUDIO_RECORDING_AVAILABLE", True)
+        monkeypatch.setattr(server, "audio_manager", object())
+
+        for recorder_id in ("../../escape", "mic/1", "mic 1"):
+            response = self.start(client, recorder_id)
+            assert response.status_code == 400
+            assert "recorder_id" in response.json()["error"]
+
+    def test_stopping_one_leaves_the_others_recording(self, client, recorders):
+        assert self.start(client, "mic").status_code == 200
+        assert self.start(client, "system").status_code == 200
+        assert self.start(client, "mic").json() == {"error": "Recorder already exists"}
+        time.sleep(0.05)
+
+        mic = client.post("/api/audio/stop", data={"recorder_id": "mic"}).json()
+
+        assert Path(mic["filepath"]).exists() and "_mic_" in mic["filename"]
+        assert list(client.get("/api/audio/recorders").json()["recorders"]) == [
+            "system"
+        ]
+        assert client.get("/api/audio/status/system").json()["is_recording"]
+
+        system = client.post("/api/audio/stop", data={"recorder_id": "system"}).json()
+        assert system["filepath"] != mic["filepath"]
+        assert Path(system["filepath"]).exists()
+        assert client.get("/api/audio/recorders").json() == {"recorders": {}}
+
+    def test_unknown_recorder_is_not_found(self, client, recorders):
+        response = client.post("/api/audio/stop", data={"recorder_id": "nope"})
+        assert response.status_code == 404
+
+    def test_generated_ids_are_distinct(self, client, recorders):
+        ids = {self.start(client, "").json()["recorder_id"] for _ in range(3)}
+        assert len(ids) == 3
+        for recorder_id in ids:
+            client.post("/api/audio/stop", data={"recorder_id": recorder_id})
+
+    def test_status_reports_progress(self, client, recorders):
+        self.start(client, "mic")
+        time.sleep(0.05)
+
+        status = client.get("/api/audio/status/mic").json()
+
+        assert status["is_recording"]
+        assert status["duration_seconds"] > 0
+        assert status["samples_collected"] > 0
+        client.post("/api/audio/stop", data={"recorder_id": "mic"})
+
+    def test_stopped_recorder_is_a_conflict(self, client, recorders):
+        self.start(client, "mic")
+        recorders.recorders["mic"].is_recording = False
+
+        response = client.post("/api/audio/stop", data={"recorder_id": "mic"})
+
+        assert response.status_code == 409
+        assert "not recording" in response.json()["error"]
+
+    def test_recording_is_attached_as_audio(self, client, recorders):
+        self.start(client, "mic")
+        time.sleep(0.05)
+        stopped = client.post("/api/audio/stop", data={"recorder_id": "mic"}).json()
+
+        saved = capture(client, audio_paths=stopped["filepath"])
+
+        assert "## Audio\n" in saved.read_text()
+        assert stopped["filename"] in saved.read_text()
+        outside = client.post(
+            "/api/capture", data={"content": "x", "audio_paths": "/etc/passwd"}
+        )
+        assert outside.status_code == 400
+
+
+class TestKeybindings:
+    def test_defaults_are_returned_without_config(self, client):
+        body = client.get("/api/keybindings").json()
+        assert body["bindings"]["save"] == "Ctrl+Enter"
+        assert body["warnings"] == []
+
+    def test_bindings_are_normalized(self, client, tmp_path):
+        (tmp_path / "config.yaml").write_text(
+            "keybindings:\n  save: shift+ctrl+s\n  reset: esc\n"
+        )
+        bindings = client.get("/api/keybindings").json()["bindings"]
+        assert bindings["save"] == "Ctrl+Shift+S"
+        assert bindings["reset"] == "Escape"
+
+    def test_bad_entries_warn_and_keep_defaults(self, client, tmp_path):
+        (tmp_path / "config.yaml").write_text(
+            "keybindings:\n  launch_rockets: ctrl+r\n  save: hyper+s\n"
+            "  toggle_help: ctrl+1\n"
+        )
+        response = client.get("/api/keybindings")
+
+        assert response.status_code == 200
+        body = response.json()
+        assert body["bindings"]["save"] == "Ctrl+Enter"
+        assert "launch_rockets" not in body["bindings"]
+        assert body["conflicts"] == [
+            {"key": "Ctrl+1", "actions": ["toggle_help", "toggle_text"]}
+        ]
+        assert len(body["warnings"]) == 3
+        assert client.get("/api/config").status_code == 200
+
+
+class TestContexts:
+    def test_lists_every_context_with_counts_and_last_use(self, client):
+        capture(client, context="work", timestamp="2025-01-01T09:00:00Z")
+        capture(client, context="home", timestamp="2025-01-02T09:00:00Z")
+        capture(client, context="work", timestamp="2025-01-03T09:00:00Z")
+        capture(client, context="home", timestamp="2025-01-03T08:00:00Z")
+        capture(client, context="gym", timestamp="2025-01-01T07:00:00Z")
+
+        listed = client.get("/api/contexts").json()
+        assert listed["total"] == 3
+        assert [(c["value"], c["count"]) for c in listed["contexts"]] == [
+            ("work", 2),
+            ("home", 2),
+            ("gym", 1),
+        ]
+        assert listed["contexts"][0]["last_used"].startswith("2025-01-03T09:00")
+
+        by_count = client.get("/api/contexts", params={"sort": "count"}).json()
+        assert [c["value"] for c in by_count["contexts"]] == ["work", "home", "gym"]
+        assert client.get("/api/contexts", params={"sort": "name"}).status_code == 400
+
+
+class TestCollections:
+    def test_capture_filter_list_and_suggest_collections(self, client):
+        research = capture(client, collection=" Research ")
+        capture(client, collection="Journal", timestamp="2025-01-01T00:00:00Z")
+        capture(client, content="loose")
+
+        assert "collection: Research" in research.read_text()
+        listed = client.get("/api/captures", params={"collection": "Research"}).json()
+        assert [c["capture_id"] for c in listed["captures"]] == [research.stem]
+        assert listed["captures"][0]["collection"] == "Research"
+        collections = client.get("/api/collections").json()
+        assert [(c["value"], c["count"]) for c in collections["collections"]] == [
+            ("Research", 1),
+            ("Journal", 1),
+        ]
+        suggested = client.get("/api/suggestions/collection", params={"query": "jo"})
+        assert [s["value"] for s in suggested.json()["suggestions"]] == ["Journal"]
+
+    def test_update_moves_or_clears_collection(self, client):
+        path = capture(client, collection="Research")
+
+        client.put(f"/api/captures/{path.stem}", json={"collection": "Journal"})
+        assert "collection: Journal" in path.read_text()
+        client.put(f"/api/captures/{path.stem}", json={"collection": ""})
+
+        assert "collection" not in path.read_text()
+        assert client.get("/api/collections").json()["total"] == 0
+
+
+class TestCustomFields:
+    def test_fields_are_written_stored_and_filterable(self, client):
+        book = capture(
+            client, fields=json.dumps({"author": "Le Guin", "page": 42, "read": True})
+        )
+        capture(client, content="other", fields=json.dumps({"author": "Borges"}))
+
+        text = book.read_text()
+        assert "field_author: Le Guin\nfield_page: 42\nfield_read: true\n" in text
+
+        def matching(*field):
+            listed = client.get("/api/captures", params={"field": list(field)})
+            return [c["capture_id"] for c in listed.json()["captures"]]
+
+        assert matching("author:Le Guin") == [book.stem]
+        assert matching("page:42", "read:true") == [book.stem]
+        assert matching("author:Le Guin", "page:7") == []
+        listed = client.get("/api/captures", params={"field": "page:42"}).json()
+        assert listed["captures"][0]["fields"] == {
+            "author": "Le Guin",
+            "page": 42,
+            "read": True,
+        }
+        assert client.get("/api/captures", params={"field": "page"}).status_code == 400
+
+    def test_update_merges_and_removes_fields(self, client):
+        path = capture(client, fields=json.dumps({"author": "x", "page": 1}))
+
+        client.put(
+            f"/api/captures/{path.stem}", json={"fields": {"page": 2, "author": None}}
+        )
+
+        assert "field_page: 2" in path.read_text()
+        assert "field_author" not in path.read_text()
+        listed = client.get("/api/captures", params={"field": "page:2"}).json()
+        assert listed["captures"][0]["fields"] == {"page": 2}
+
+    def test_invalid_fields_are_rejected(self, client):
+        for fields in ['{"bad key": 1}', '{"tags": [1]}', "[1]", "not json"]:
+            response = client.post(
+                "/api/capture", data={"content": "note", "fields": fields}
+            )
+            assert response.status_code == 400, fields
+
+
+class TestGraph:
+    def test_local_graph_around_a_capture(self, client):
+        target = capture(client, content="target", capture_id="target")
+        capture(client, content="links [[target]]", capture_id="source")
+        capture(client, content="unrelated", capture_id="loner")
+
+        graph = client.get("/api/graph", params={"root": target.stem}).json()
+
+        assert [n["id"] for n in graph["nodes"]] == ["target", "source"]
+        assert graph["edges"] == [
+            {"source": "source", "target": "target", "type": "link"}
+        ]
+        assert client.get("/api/graph", params={"root": "nope"}).status_code == 404
+
+
+class TestSearch:
+    def test_query_tag_and_day_range(self, client):
+        for day, content, tags in (
+            (1, "alpha", "work"),
+            (2, "alpha beta", "home"),
+            (3, "gamma", "work"),
+        ):
+            timestamp = f"2025-03-0{day}T09:00Z"
+            capture(client, content=content, tags=tags, timestamp=timestamp)
+
+        def search(**params):
+            response = client.get("/api/search", params=params)
+            assert response.status_code == 200, response.json()
+            return [r["snippet"] for r in response.json()["results"]]
+
+        assert search() == ["gamma", "alpha beta", "alpha"]
+        assert search(q="alpha") == ["alpha beta", "alpha"]
+        assert search(tag="work") == ["gamma", "alpha"]
+        assert search(**{"from": "2025-03-02", "to": "2025-03-02"}) == ["alpha beta"]
+        assert client.get("/api/search", params={"to": "March"}).status_code == 400
+
+
+class TestLocation:
+    @pytest.fixture
+    def saved(self, server, client, monkeypatch, tmp_path):
+        """Frontmatter of a capture saved with the given config."""
+        import geolocation
+
+        monkeypatch.setattr(server, "get_location", geolocation.get_location)
+
+        def save(config):
+            (tmp_path / "config.yaml").write_text(config)
+            return capture(client).read_text().split("---\n")[1]
+
+        return save
+
+    def test_off_keeps_location_null(self, saved):
+        assert "location: null" in saved("location:\n  mode: off\n")
+
+    def test_static_location_is_written_and_stored(self, client, saved):
+        frontmatter = saved(
+            "location:\n  mode: static\n  latitude: 52.5\n  longitude: 13.4\n"
+        )
+
+        assert "location:\n  latitude: 52.5\n  longitude: 13.4\n" in frontmatter
+        assert "  source: static\n" in frontmatter
+        near = client.get(
+            "/api/captures/near", params={"lat": 52.5, "lon": 13.4, "radius_km": 1}
+        )
+        assert near.status_code == 200 and near.json()["count"] == 1
+
+    def test_command_output_is_parsed(self, saved):
+        frontmatter = saved(
+            "location:\n  mode: command\n  command: echo 48.1,11.5,30\n"
+        )
+        assert "  accuracy: 30.0\n" in frontmatter
+        assert "  source: command\n" in frontmatter
+
+    def test_slow_provider_degrades_to_null(self, saved):
+        started = time.monotonic()
+        frontmatter = saved(
+            "location:\n  mode: command\n  command: sleep 5\n  timeout_seconds: 0.2\n"
+        )
+        assert "location: null" in frontmatter
+        assert time.monotonic() - started < 3
+
+    def test_geolocation_enabled_false_turns_lookups_off(self, server):
+        cfg = server.normalize_config({"capture": {"geolocation_enabled": False}})
+        assert cfg["location"] == {"mode": "off"}
+
+
+class TestSourceApp:
+    def test_configured_command_sets_source_app(self, client, tmp_path):
+        (tmp_path / "config.yaml").write_text(
+            "capture:\n  source_app_detection: true\n"
+            "  source_app_command: echo firefox\n"
+        )
+        assert "source_app: firefox\n" in capture(client).read_text()
+
+    def test_failing_or_disabled_detection_is_null(self, client, tmp_path):
+        assert "source_app: null\n" in capture(client).read_text()
+        (tmp_path / "config.yaml").write_text(
+            "capture:\n  source_app_detection: true\n"
+            "  source_app_command: exit 1\n"
+        )
+        assert "source_app: null\n" in capture(client, content="two").read_text()
+
+
+class TestDrafts:
+    def test_latest_draft_is_restored_and_cleared_by_capture(self, client):
+        saved = client.post("/api/drafts", data={"state": '{"content": "a"}'})
+        draft_id = saved.json()["draft_id"]
+        client.post(
+            "/api/drafts", data={"state": '{"content": "ab"}', "draft_id": draft_id}
+        )
+
+        latest = client.get("/api/drafts/latest").json()["draft"]
+        assert (latest["draft_id"], latest["state"]) == (draft_id, {"content": "ab"})
+
+        capture(client, content="ab", draft_id=draft_id)
+        assert client.get("/api/drafts/latest").json()["draft"] is None
+
+    def test_old_drafts_are_pruned_and_bad_state_rejected(self, client, tmp_path):
+        client.post("/api/drafts", data={"state": "{}", "draft_id": "old"})
+        (tmp_path / "config.yaml").write_text("capture:\n  draft_retention_days: 0\n")
+        client.post("/api/drafts", data={"state": "{}", "draft_id": "new"})
+        capture(client, draft_id="new")
+
+        assert client.get("/api/drafts/latest").json()["draft"] is None
+        response = client.post("/api/drafts", data={"state": "{nope"})
+        assert response.status_code == 400
+
+
+class TestUiState:
+    def test_state_persists_and_merges(self, client):
+        assert client.get("/api/ui-state").json() == {"state": {}}
+
+        response = client.put(
+            "/api/ui-state",
+            json={"last_vault": "work", "window": {"width": 800, "height": 600}},
+        )
+        assert response.status_code == 200
+        client.put("/api/ui-state", json={"last_mode": "quick", "last_vault": None})
+
+        assert client.get("/api/ui-state").json()["state"] == {
+            "last_mode": "quick",
+            "window": {"width": 800, "height": 600},
+        }
+
+    def test_rejects_empty_keys(self, client):
+        assert client.put("/api/ui-state", json={" ": 1}).status_code == 400
+
+
+class TestStorageStats:
+    def test_counts_captures_media_and_database_and_caches(self, client):
+        client.post(
+            "/api/capture",
+            data={"content": "note"},
+            files={"media": ("pic.png", b"png", "image/png")},
+        )
+
+        stats = client.get("/api/stats/storage").json()
+
+        assert stats["captures"]["files"] == 1
+        assert (stats["media"]["files"], stats["media"]["bytes"]) == (1, 3)
+        assert stats["database"]["files"] == 1
+        assert stats["total_bytes"] == sum(
+            stats[key]["bytes"] for key in ("captures", "media", "database")
+        )
+        capture(client, content="more")
+        assert client.get("/api/stats/storage").json() == stats
+        refreshed = client.get("/api/stats/storage", params={"refresh": True}).json()
+        assert refreshed["captures"]["files"] == 2
+
+
+class TestTimelineStats:
+    def test_buckets_are_zero_filled(self, client):
+        for ts in ("2025-01-01T09", "2025-01-01T17", "2025-01-03T08", "2025-01-14T12"):
+            capture(client, timestamp=f"{ts}:00:00Z")
+
+        def series(**params):
+            response = client.get("/api/stats/timeline", params=params)
+            return [(s["bucket_start"], s["count"]) for s in response.json()["series"]]
+
+        assert series(bucket="day", **{"from": "2025-01-01", "to": "2025-01-04"}) == [
+            ("2025-01-01", 2),
+            ("2025-01-02", 0),
+            ("2025-01-03", 1),
+            ("2025-01-04", 0),
+        ]
+        assert series(bucket="week", **{"from": "2025-01-01", "to": "2025-01-14"}) == [
+            ("2024-12-30", 3),
+            ("2025-01-06", 0),
+            ("2025-01-13", 1),
+        ]
+        assert series(bucket="month", to="2025-02-10") == [
+            ("2025-01-01", 4),
+            ("2025-02-01", 0),
+        ]
+
+    def test_rating_averages_skip_unrated_captures(self, client):
+        capture(client, rating="2", timestamp="2025-03-01T08:00:00Z")
+        path = capture(client, rating="5", timestamp="2025-03-01T20:00:00Z")
+        capture(client, timestamp="2025-03-02T08:00:00Z")
+
+        response = client.get(
+            "/api/stats/rating", params={"from": "2025-03-01", "to": "2025-03-02"}
+        )
+
+        assert "rating: 5" in path.read_text()
+        assert response.json()["series"] == [
+            {"bucket_start": "2025-03-01", "average": 3.5, "count": 2},
+            {"bucket_start": "2025-03-02", "average": None, "count": 0},
+        ]
+        bad = client.post("/api/capture", data={"content": "x", "rating": "6"})
+        assert bad.status_code == 400
+
+    def test_invalid_parameters_are_rejected(self, client):
+        assert client.get("/api/stats/timeline?bucket=year").status_code == 400
+        assert client.get("/api/stats/timeline?from=soon").status_code == 400
+        response = client.get("/api/stats/timeline?from=2025-02-01&to=2025-01-01")
+        assert response.status_code == 400
+
+
+class TestMetrics:
+    def test_counts_captures_errors_and_requests(
+        self, server, client, tmp_path, monkeypatch
+    ):
+        monkeypatch.setattr(server, "metrics", server.Metrics())
+        capture(client)
+        (tmp_path / "gone").symlink_to(tmp_path / "missing")
+        monkeypatch.setenv("KMS_VAULT_PATH", str(tmp_path / "gone"))
+        client.post("/api/capture", data={"content": "lost"})
+
+        response = client.get("/api/metrics")
+
+        assert response.headers["content-type"].startswith("text/plain")
+        lines = response.text.splitlines()
+        assert "kms_captures_written_total 1" in lines
+        assert "kms_capture_errors_total 1" in lines
+        assert "kms_screenshots_total 0" in lines
+        assert (
+            'kms_http_requests_total{endpoint="/api/capture",method="POST",'
+            'status="200"} 1'
+        ) in lines
+
+    def test_static_and_unmatched_paths_share_labels(self, server, monkeypatch):
+        monkeypatch.setattr(server, "metrics", server.Metrics())
+
+        class FakeRequest:
+            method = "GET"
+
+            def __init__(self, path, scope):
+                self.url = type("URL", (), {"path": path})()
+                self.scope = scope
+
+        async def call_next(request):
+            return server.Response(status_code=404)
+
+        for path in ("/nope/1", "/nope/2"):
+            asyncio.run(server.count_requests(FakeRequest(path, {}), call_next))
+        static = FakeRequest("/assets/app.js", {"endpoint": object()})
+        asyncio.run(server.count_requests(static, call_next))
+
+        lines = server.metrics.render().splitlines()
+        assert (
+            'kms_http_requests_total{endpoint="unmatched",method="GET",'
+            'status="404"} 2'
+        ) in lines
+        assert (
+            'kms_http_requests_total{endpoint="static",method="GET",'
+            'status="404"} 1'
+        ) in lines
+
+    def test_label_values_are_escaped(self, server):
+        metrics = server.Metrics()
+        metrics.inc("kms_http_requests_total", {"endpoint": 'a"b\\c'})
+        assert 'kms_http_requests_total{endpoint="a\\"b\\\\c"} 1' in (
+            metrics.render().splitlines()
+        )
+
+
+class TestDiagnostics:
+    def test_reports_missing_tools(self, server, client, monkeypatch):
+        installed = {"grim": "/usr/bin/grim", "wl-paste": "/usr/bin/wl-paste"}
+        monkeypatch.setattr(server.shutil, "which", installed.get)
+        monkeypatch.setattr(server, "_list_outputs", lambda: [])
+        monkeypatch.setenv("WAYLAND_DISPLAY", "wayland-1")
+
+        report = client.get("/api/diagnostics").json()
+
+        assert report["tools"]["grim"]["path"] == "/usr/bin/grim"
+        assert not report["tools"]["slurp"]["installed"]
+        assert report["compositor"] == {"responding": False, "outputs": 0}
+        assert not report["screenshot_ready"]
+        assert report["clipboard_ready"] and report["clipboard_tool"] == "wl-paste"
+
+    def test_x11_and_macos_tools_count_as_ready(self, server, client, monkeypatch):
+        installed = {"maim": "/usr/bin/maim", "xsel": "/usr/bin/xsel"}
+        monkeypatch.setattr(server.shutil, "which", installed.get)
+        monkeypatch.setattr(server, "_list_outputs", lambda: [])
+        monkeypatch.delenv("WAYLAND_DISPLAY", raising=False)
+
+        report = client.get("/api/diagnostics").json()
+
+        assert report["screenshot_ready"] and report["screenshot_tool"] == "maim"
+        assert report["clipboard_ready"] and report["clipboard_tool"] == "xsel"
+
+
+class TestScreenshotCompression:
+    def screenshot(self, tmp_path):
+        path = tmp_path / "shot.png"
+        path.write_bytes(b"p" * 1000)
+        return path
+
+    def test_webp_replaces_png_and_reports_sizes(self, server, monkeypatch, tmp_path):
+        def fake_cwebp(command, **kwargs):
+            Path(command[command.index("-o") + 1]).write_bytes(b"w" * 200)
+            return subprocess.CompletedProcess(command, 0, b"", b"")
+
+        monkeypatch.setattr(server.shutil, "which", lambda tool: f"/usr/bin/{tool}")
+        monkeypatch.setattr(server.subprocess, "run", fake_cwebp)
+        path = self.screenshot(tmp_path)
+
+        new_path, report = server._compress_screenshot(
+            path, {"screenshot_compression": "webp", "screenshot_webp_quality": 60}
+        )
+
+        assert new_path == tmp_path / "shot.webp" and not path.exists()
+        assert report == {
+            "method": "webp",
+            "original_bytes": 1000,
+            "compressed_bytes": 200,
+        }
+
+    def test_missing_or_failing_tool_keeps_original(
+        self, server, monkeypatch, tmp_path
+    ):
+        path = self.screenshot(tmp_path)
+        monkeypatch.setattr(server.shutil, "which", lambda tool: None)
+        kept, report = server._compress_screenshot(
+            path, {"screenshot_compression": "oxipng"}
+        )
+        assert kept == path and report["skipped"] == "oxipng is not installed"
+
+        monkeypatch.setattr(server.shutil, "which", lambda tool: f"/usr/bin/{tool}")
+        monkeypatch.setattr(
+            server.subprocess,
+            "run",
+            lambda command, **kw: subprocess.CompletedProcess(command, 1, b"", b"bad"),
+        )
+        kept, report = server._compress_screenshot(
+            path, {"screenshot_compression": "webp"}
+        )
+        assert kept == path and path.read_bytes() == b"p" * 1000
+        assert not (tmp_path / "shot.webp").exists()
+        assert report["skipped"] == "cwebp failed"
+
+    def test_disabled_by_default(self, server, tmp_path):
+        path = self.screenshot(tmp_path)
+        assert server._compress_screenshot(path, {}) == (path, None)
+
+
+class TestScreenshotMetadata:
+    @staticmethod
+    def png(width, height):
+        ihdr = width.to_bytes(4, "big") + height.to_bytes(4, "big") + b"\x08\x06"
+        return b"\x89PNG\r\n\x1a\n" + b"\x00\x00\x00\x0dIHDR" + ihdr
+
+    def test_reads_png_and_webp_dimensions(self, server, tmp_path):
+        png = tmp_path / "shot.png"
+        png.write_bytes(self.png(2560, 1440))
+        webp = tmp_path / "shot.webp"
+        size = (1919).to_bytes(3, "little") + (1079).to_bytes(3, "little")
+        webp.write_bytes(b"RIFF\x00\x00\x00\x00WEBPVP8X" + b"\x00" * 8 + size)
+        other = tmp_path / "notes.txt"
+        other.write_text("not an image")
+
+        assert server._image_dimensions(png) == (2560, 1440)
+        assert server._image_dimensions(webp) == (1920, 1080)
+        assert server._image_dimensions(other) is None
+        assert server._image_dimensions(tmp_path / "missing.png") is None
+
+    def test_capture_stores_dimensions_and_output(self, client, server, tmp_path):
+        shot = tmp_path / "shot.png"
+        shot.write_bytes(self.png(1920, 1080))
+        path = capture(
+            client,
+            screenshot_path=str(shot),
+            screenshot_type="screenshot",
+            screenshot_output="DP-1",
+        )
+
+        with sqlite3.connect(server.get_main_db().db_path) as conn:
+            row = conn.execute(
+                "SELECT width, height, output FROM media_files WHERE capture_id = ?",
+                (path.stem,),
+            ).fetchone()
+        assert row == (1920, 1080, "DP-1")
+
+
+class TestSourceMeta:
+    URL = "/api/sources/https://example.com/page/meta"
+
+    def test_metadata_is_fetched_once_and_cached(self, server, client, monkeypatch):
+        fetched = []
+
+        def fetch(url, favicon_dir):
+            fetched.append(url)
+            if len(fetched) > 2:
+                raise OSError("offline")
+            return {"title": "Example", "favicon_url": None, "favicon_path": None}
+
+        monkeypatch.setattr(server, "fetch_source_meta", fetch)
+
+        assert client.get(self.URL).json()["title"] == "Example"
+        assert client.get(self.URL).json()["title"] == "Example"
+        assert fetched == ["https://example.com/page"]
+        client.get(self.URL, params={"refresh": True})
+        stale = client.get(self.URL, params={"refresh": True}).json()
+        assert len(fetched) == 3
+        assert stale["stale"] and stale["title"] == "Example"
+
+    def test_non_url_sources_are_rejected(self, client):
+        assert client.get("/api/sources/notes/meta").status_code == 400
+
+    def test_favicon_must_be_an_http_url(self, monkeypatch, tmp_path):
+        import source_meta
+
+        page = b'<title>Page</title><link rel="icon" href="file:///etc/passwd">'
+        fetched = []
+
+        class Headers:
+            def get_content_charset(self):
+                return None
+
+        def get(url, limit, timeout):
+            fetched.append(url)
+            return (page if len(fetched) == 1 else b"icon"), Headers()
+
+        monkeypatch.setattr(source_meta, "_get", get)
+
+        meta = source_meta.fetch_source_meta("https://example.com/page", tmp_path)
+
+        favicon = "https://example.com/favicon.ico"
+        assert fetched == ["https://example.com/page", favicon]
+        assert meta["favicon_url"] == favicon
+        assert Path(meta["favicon_path"]).read_bytes() == b"icon"
+
+
+class TestSourceTrackingParams:
+    def test_tracking_params_are_stripped_and_links_deduplicated(
+        self, client, tmp_path
+    ):
+        (tmp_path / "config.yaml").write_text(
+            "sources:\n  strip_params: true\n  keep_original_urls: true\n"
+        )
+        tracked = "https://example.com/post?id=7&utm_source=x&UTM_Medium=y&fbclid=z"
+
+        path = capture(client, sources=f"{tracked},https://example.com/post?id=7,book")
+
+        listed = client.get("/api/captures").json()["captures"][0]
+        assert listed["sources"] == ["https://example.com/post?id=7", "book"]
+        assert f"  https://example.com/post?id=7: {tracked}" in path.read_text()
+
+    def test_custom_patterns_and_off_by_default(self, client, tmp_path):
+        url = "https://example.com/?ref=feed&keep=1"
+        capture(client, sources=url)
+        (tmp_path / "config.yaml").write_text("sources:\n  strip_params: [ref]\n")
+        path = capture(client, content="second", sources=url)
+
+        sources = [c["sources"] for c in client.get("/api/captures").json()["captures"]]
+        assert sources == [["https://example.com/?keep=1"], [url]]
+        assert "original_sources" not in path.read_text()
+
+
+class TestContentNormalization:
+    PASTED = "\n  \n```py\ndef f():  \n    return 1\t\n```\n\n"
+
+    def test_none_keeps_content_as_is(self, server):
+        assert server._normalize_content(self.PASTED, "none") == self.PASTED
+
+    def test_trim_drops_surrounding_blank_lines(self, server):
+        assert server._normalize_content(self.PASTED, "trim") == (
+            "```py\ndef f():  \n    return 1\t\n```"
+        )
+
+    def test_strip_trailing_ws_keeps_indentation(self, server):
+        assert server._normalize_content(self.PASTED, "strip_trailing_ws") == (
+            "```py\ndef f():\n    return 1\n```"
+        )
+
+    def test_configured_mode_applies_to_captures(self, client, tmp_path):
+        (tmp_path / "config.yaml").write_text(
+            "capture:\n  normalize_content: strip_trailing_ws\n"
+        )
+        path = capture(client, content="\nline one   \n  indented\t\n\n")
+        assert "## Content\nline one\n  indented\n" in path.read_text()
+
+    def test_unknown_mode_is_rejected(self, server):
+        with pytest.raises(server.ValidationError):
+            server._normalize_content("x", "tidy")
+
+
+class TestSuggestionsEndpoint:
+    def test_suggests_previously_used_tags(self, client):
+        capture(client, tags="project-x")
+        capture(client, tags="project-x,reading")
+
+        response = client.get("/api/suggestions/tag", params={"query": "proj"})
+
+        suggestions = response.json()["suggestions"]
+        assert suggestions[0]["value"] == "project-x"
+        assert suggestions[0]["count"] == 2
+
+    def test_combined_endpoint_uses_per_field_limits(self, client):
+        capture(client, tags="a1,a2,a3", sources="s1,s2")
+
+        response = client.get(
+            "/api/suggestions", params={"tag_limit": "2", "source_limit": "1"}
+        )
+
+        body = response.json()
+        assert len(body["tag"]) == 2
+        assert len(body["source"]) == 1
+        assert body["context"] == []
+
+    def test_queries_below_min_length_return_nothing(self, client, tmp_path):
+        (tmp_path / "config.yaml").write_text("suggestions:\n  min_query_len: 2\n")
+        capture(client, tags="project-x")
+
+        def values(query):
+            response = client.get("/api/suggestions/tag", params={"query": query})
+            return [s["value"] for s in response.json()["suggestions"]]
+
+        assert values("p") == []
+        assert values("pr") == ["project-x"]
+        assert values("") == ["project-x"]
+
+    def test_every_suggestion_has_a_stable_color(self, client, tmp_path):
+        (tmp_path / "config.yaml").write_text(
+            "ui:\n  colors:\n    work: '#FF0000'\n    book: '#00ff00'\n"
+            "  tag_colors:\n    book: '#0000ff'\n"
+        )
+        capture(client, tags="work,home,book", sources="book")
+
+        def colors(field_type):
+            response = client.get(f"/api/suggestions/{field_type}")
+            return {s["value"]: s["color"] for s in response.json()["suggestions"]}
+
+        tags = colors("tag")
+        assert tags["work"] == "#ff0000" and tags["book"] == "#0000ff"
+        assert tags["home"].startswith("#") and len(tags["home"]) == 7
+        assert colors("tag")["home"] == tags["home"]
+        assert colors("source") == {"book": "#00ff00"}
+
+    def test_top_tags_by_count_with_colors(self, client, tmp_path):
+        (tmp_path / "config.yaml").write_text(
+            "ui:\n  tag_colors:\n    work: '#ff0000'\n"
+        )
+        capture(client, tags="work,old", timestamp="2020-01-01T00:00:00Z")
+        capture(client, tags="work,home")
+        capture(client, tags="work,home")
+
+        tags = client.get("/api/tags/top", params={"limit": "2"}).json()["tags"]
+        suggested = client.get("/api/suggestions/tag").json()["suggestions"]
+        home_color = {s["value"]: s["color"] for s in suggested}["home"]
+        assert [(t["value"], t["count"], t["color"]) for t in tags] == [
+            ("work", 3, "#ff0000"),
+            ("home", 2, home_color),
+        ]
+        recent = client.get("/api/tags/top", params={"recent_days": "30"}).json()
+        assert sorted((t["value"], t["count"]) for t in recent["tags"]) == [
+            ("home", 2),
+            ("work", 2),
+        ]
+
+    def test_batch_exists_check(self, client):
+        capture(client, tags="alpha,beta")
+
+        response = client.post(
+            "/api/suggestion-exists/tag", json={"values": ["alpha", "gamma", "beta"]}
+        )
+
+        assert response.json() == {
+            "exists": {"alpha": True, "gamma": False, "beta": True}
+        }
+        single = client.get("/api/suggestion-exists/tag", params={"value": "alpha"})
+        assert single.json() == {"exists": True}
+        bad = client.post("/api/suggestion-exists/tag", json={"values": "alpha"})
+        assert bad.status_code == 400
+        bad_type = client.post("/api/suggestion-exists/mood", json={"values": []})
+        assert bad_type.status_code == 400
+        assert bad_type.json() == {"error": "Invalid field type"}
+
+    def test_unknown_field_type_is_rejected(self, client):
+        assert client.get("/api/suggestions/colour").status_code == 400
+
+    def test_debug_endpoint_reports_score_components(self, client):
+        capture(client, tags="project-x")
+
+        response = client.get(
+            "/api/suggestions/tag/debug",
+            params={"query": "project-x", "scoring": "decay", "half_life_days": "7"},
+        )
+
+        candidate = response.json()["candidates"][0]
+        assert candidate["value"] == "project-x"
+        assert candidate["match"] == "exact"
+        assert candidate["match_score"] == 1000
+        assert candidate["count_boost"] == 10
+        assert 0 < candidate["decay"] <= 1
+        assert candidate["score"] == (
+            1000 + 10 * candidate["decay"] + candidate["recency_boost"]
+        )
+
+
+class TestBrowseTags:
+    def test_counts_with_recent_capture_ids(self, client):
+        capture(client, tags="work", timestamp="2025-01-01T00:00:00Z")
+        second = capture(client, tags="work,home", timestamp="2025-01-02T00:00:00Z")
+
+        tags = client.get("/api/browse/tags", params={"recent": "1"}).json()["tags"]
+        assert tags == [
+            {"tag": "work", "count": 2, "recent_capture_ids": [second.stem]},
+            {"tag": "home", "count": 1, "recent_capture_ids": [second.stem]},
+        ]
+
+    def test_recent_zero_still_counts_tags(self, client):
+        capture(client, tags="work")
+
+        tags = client.get("/api/browse/tags", params={"recent": "0"}).json()["tags"]
+        assert tags == [{"tag": "work", "count": 1, "recent_capture_ids": []}]
+
+
+class TestBulkTags:
+    def test_adds_and_removes_tags_in_database_and_files(self, client):
+        first = capture(client, content="one", tags="x")
+        second = capture(client, content="two", tags="x,y")
+        other = capture(client, content="three", tags="y")
+
+        response = client.post(
+            "/api/tags/bulk",
+            json={"filter": {"tag": "x"}, "add": ["z"], "remove": ["y"]},
+        ).json()
+
+        assert sorted(response["capture_ids"]) == sorted([first.stem, second.stem])
+        assert "tags:\n- x\n- z\n" in second.read_text()
+        assert "tags:\n- y\n" in other.read_text()
+        tagged = client.get("/api/captures", params={"tag": "z"}).json()["captures"]
+        assert all(c["tags"] == ["x", "z"] for c in tagged) and len(tagged) == 2
+
+    def test_locked_captures_are_skipped_without_force(self, client):
+        locked = capture(client, content="one", tags="x")
+        client.post(f"/api/captures/{locked.stem}/lock")
+        body = {"filter": {"tag": "x"}, "add": ["z"]}
+
+        skipped = client.post("/api/tags/bulk", json=body).json()
+        assert (skipped["capture_ids"], skipped["locked"]) == ([], [locked.stem])
+        assert "- z\n" not in locked.read_text()
+
+        forced = client.post("/api/tags/bulk", params={"force": "true"}, json=body)
+        assert forced.json()["capture_ids"] == [locked.stem]
+        assert "- z\n" in locked.read_text()
+
+    def test_requires_changes_and_a_filter(self, client):
+        capture(client, tags="x")
+        no_changes = client.post("/api/tags/bulk", json={"filter": {"tag": "x"}})
+        no_filter = client.post("/api/tags/bulk", json={"add": ["z"]})
+        assert no_changes.status_code == no_filter.status_code == 400
+
+
+class TestSearchTag:
+    def test_tags_every_full_text_match(self, client):
+        sqlite = capture(client, content="notes on sqlite indexes", tags="db")
+        wal = capture(client, content="sqlite write-ahead log", tags="todo")
+        capture(client, content="unrelated grocery list")
+
+        response = client.post(
+            "/api/search/tag", json={"query": "sqlite", "add": ["db", "sql"]}
+        )
+
+        body = response.json()
+        assert body["matched"] == 2
+        assert sorted(body["capture_ids"]) == sorted([sqlite.stem, wal.stem])
+        assert body["count"] == 2
+        assert "- sql\n" in sqlite.read_text() and "- sql\n" in wal.read_text()
+        listed = client.get("/api/captures", params={"tag": "sql"}).json()
+        assert {c["capture_id"] for c in listed["captures"]} == {sqlite.stem, wal.stem}
+
+    def test_requires_query_and_tags_and_valid_syntax(self, client):
+        capture(client, content="anything")
+
+        for payload in [{"add": ["x"]}, {"query": "anything"}]:
+            assert client.post("/api/search/tag", json=payload).status_code == 400
+        response = client.post(
+            "/api/search/tag", json={"query": '"unbalanced', "add": ["x"]}
+        )
+        assert response.status_code == 400
+        assert "Invalid search query" in response.json()["error"]
+
+
+class TestCaptureEditing:
+    def test_locked_capture_refuses_edits_without_force(self, client):
+        capture_id = capture(client, tags="draft").stem
+        assert client.post(f"/api/captures/{capture_id}/lock").status_code == 200
+
+        response = client.put(f"/api/captures/{capture_id}", json={"tags": ["final"]})
+        assert response.status_code == 409
+        assert client.delete(f"/api/captures/{capture_id}").status_code == 409
+
+        forced = client.put(
+            f"/api/captures/{capture_id}",
+            params={"force": "true"},
+            json={"tags": ["final"]},
+        )
+        assert forced.status_code == 200
+
+    def test_edit_keeps_screenshot_media(self, client, server, tmp_path):
+        shot = tmp_path / "shot.png"
+        shot.write_bytes(TestScreenshotMetadata.png(1920, 1080))
+        path = capture(
+            client,
+            screenshot_path=str(shot),
+            screenshot_type="screenshot",
+            screenshot_output="DP-1",
+        )
+
+        response = client.put(f"/api/captures/{path.stem}", json={"tags": ["done"]})
+
+        assert response.status_code == 200
+        with sqlite3.connect(server.get_main_db().db_path) as conn:
+            rows = conn.execute(
+                "SELECT file_type, width, height, output FROM media_files "
+                "WHERE capture_id = ?",
+                (path.stem,),
+            ).fetchall()
+        assert rows == [("screenshot", 1920, 1080, "DP-1")]
+
+    def test_delete_removes_file_and_row(self, client):
+        path = capture(client)
+
+        assert client.delete(f"/api/captures/{path.stem}").status_code == 200
+        assert not path.exists()
+        assert client.get("/api/captures").json()["count"] == 0
+
+    def test_missing_capture_is_404(self, client):
+        assert client.delete("/api/captures/nope").status_code == 404
+
+
+class TestProcessingStatus:
+    def test_processing_status_can_be_set_and_filtered(self, client):
+        raw_id = capture(client).stem
+        capture_id = capture(client, content="other").stem
+
+        response = client.put(
+            f"/api/captures/{capture_id}", json={"processing_status": "processed"}
+        )
+        assert response.status_code == 200
+        listed = client.get("/api/captures", params={"status": "raw"}).json()
+        assert [c["capture_id"] for c in listed["captures"]] == [raw_id]
+        bad = client.put(f"/api/captures/{raw_id}", json={"processing_status": "x"})
+        assert bad.status_code == 400
+
+
+class TestParentCaptures:
+    def test_children_lists_replies_to_a_capture(self, client):
+        parent_id = capture(client).stem
+        reply = client.post(
+            "/api/capture", data={"content": "reply", "parent": parent_id}
+        ).json()
+        orphan = client.post(
+            "/api/capture", data={"content": "orphan", "parent": "missing"}
+        ).json()
+
+        assert f"parent: '{parent_id}'" in Path(reply["saved_to"]).read_text()
+        assert orphan["warnings"] == ["Parent capture 'missing' does not exist"]
+        children = client.get(f"/api/captures/{parent_id}/children").json()
+        assert [c["capture_id"] for c in children["captures"]] == [
+            Path(reply["saved_to"]).stem
+        ]
+
+    def test_parent_cycles_are_rejected(self, client):
+        capture(client, capture_id="a")
+        capture(client, content="b", capture_id="b", parent="a")
+        capture(client, content="c", capture_id="c", parent="b")
+
+        for parent in ("a", "c"):
+            response = client.put("/api/captures/a", json={"parent": parent})
+            assert response.status_code == 400
+        response = client.post(
+            "/api/capture", data={"content": "a", "capture_id": "a", "parent": "c"}
+        )
+        assert response.status_code == 400
+        assert "cycle" in response.json()["error"]
+        assert client.put("/api/captures/c", json={"parent": "a"}).status_code == 200
+
+
+class TestContentHash:
+    def test_verify_detects_external_edits(self, client, tmp_path):
+        (tmp_path / "config.yaml").write_text("capture:\n  content_hash: true\n")
+        path = capture(client, content="original")
+
+        verify = client.get(f"/api/captures/{path.stem}/verify").json()
+        assert verify["signed"] and verify["matches"]
+
+        path.write_text(path.read_text().replace("original", "tampered"))
+        assert not client.get(f"/api/captures/{path.stem}/verify").json()["matches"]
+
+
+class TestDrift:
+    def test_drift_and_selected_reindex(self, client):
+        path = capture(client, content="original")
+        assert client.get("/api/captures/drift").json()["count"] == 0
+
+        path.write_text(path.read_text().replace("original", "edited"))
+        os.utime(path, ns=(0, 0))
+        drift = client.get("/api/captures/drift").json()["captures"]
+        assert drift == [
+            {"capture_id": path.stem, "file_path": str(path), "status": "modified"}
+        ]
+
+        response = client.post(
+            "/api/reindex/selected", json={"capture_ids": [path.stem]}
+        )
+        assert response.json() == {"indexed": [path.stem], "failed": []}
+        assert client.get("/api/captures/drift").json()["count"] == 0
+        listed = client.get("/api/captures", params={"query": "edited"}).json()
+        assert listed["count"] == 1
+
+
+class TestPriority:
+    def test_priority_sort_and_validation(self, client):
+        low = capture(client, priority="4").stem
+        unset = capture(client, content="later").stem
+        high = capture(client, content="urgent", priority="1").stem
+
+        listed = client.get("/api/captures", params={"sort": "priority"}).json()
+        assert [c["capture_id"] for c in listed["captures"]] == [high, low, unset]
+        bad = client.put(f"/api/captures/{unset}", json={"priority": 6})
+        assert bad.status_code == 400
+
+
+class TestImportance:
+    def test_importance_is_optional(self, client):
+        important = capture(client, importance="0.8")
+        unset = capture(client, content="later")
+
+        assert "importance: 0.8" in important.read_text()
+        assert "importance" not in unset.read_text()
+        listed = client.get("/api/captures").json()["captures"]
+        assert {c["capture_id"]: c["importance"] for c in listed} == {
+            important.stem: 0.8,
+            unset.stem: None,
+        }
+        bad = client.post("/api/capture", data={"content": "x", "importance": "nan"})
+        assert bad.status_code == 400
+
+
+class TestReindex:
+    def test_reindex_stores_content_like_a_new_capture(self, client, server):
+        response = client.post(
+            "/api/capture",
+            data={"content": "hello"},
+            files={"media": ("pic.png", b"png", "image/png")},
+        )
+        capture_id = Path(response.json()["saved_to"]).stem
+        db = server.get_main_db()
+        db.delete_capture(capture_id)
+
+        result = client.post("/api/reindex").json()
+
+        assert result["indexed"] == 1 and result["failed"] == []
+        with sqlite3.connect(db.db_path) as conn:
+            (content,) = conn.execute("SELECT content FROM captures").fetchone()
+        assert content == "hello"
+        assert [Path(p).name for p in db.get_media_paths(capture_id)] == ["pic.png"]
+        listed = client.get("/api/captures", params={"query": "Content"}).json()
+        assert listed["count"] == 0
+
+
+class TestRevisions:
+    def test_edits_are_diffed_between_revisions(self, client):
+        capture_id = capture(client, content="first line\nsecond line").stem
+        for content in ("first line\nsecond line edited", "first line\nthird"):
+            client.put(f"/api/captures/{capture_id}", json={"content": content})
+        client.put(f"/api/captures/{capture_id}", json={"tags": ["no-content-change"]})
+
+        revisions = client.get(f"/api/captures/{capture_id}/revisions").json()
+        assert [r["revision"] for r in revisions["revisions"]] == [1, 2, 3]
+
+        latest = client.get(f"/api/captures/{capture_id}/revisions/diff").json()
+        assert (latest["from"], latest["to"]) == (2, 3)
+        assert "-second line edited\n+third" in latest["diff"]
+
+        lines = client.get(
+            f"/api/captures/{capture_id}/revisions/diff",
+            params={"from": 1, "to": 3, "format": "lines"},
+        ).json()["lines"]
+        assert [(line["op"], line["text"]) for line in lines] == [
+            ("equal", "## Content"),
+            ("equal", "first line"),
+            ("delete", "second line"),
+            ("insert", "third"),
+        ]
+
+    def test_missing_revision_is_not_found(self, client):
+        capture_id = capture(client).stem
+        response = client.get(
+            f"/api/captures/{capture_id}/revisions/diff", params={"to": 7}
+        )
+        assert response.status_code == 404
+
+
+class TestMergeCaptures:
+    def test_merges_bodies_and_tags_into_the_kept_capture(self, client):
+        kept = capture(client, content="first half", tags="a", capture_id="k")
+        other = capture(client, content="second half", tags="b", capture_id="o")
+
+        response = client.post(
+            "/api/captures/merge", json={"ids": ["k", "o"], "keep": "k"}
+        ).json()
+
+        assert (response["capture_id"], response["merged"]) == ("k", ["o"])
+        assert not other.exists()
+        text = kept.read_text()
+        assert text.index("first half") < text.index("second half")
+        assert "tags:\n- a\n- b\n" in text and "- o\n" in text
+        ids = [c["capture_id"] for c in client.get("/api/captures").json()["captures"]]
+        assert ids == ["k"]
+
+    def test_invalid_requests_are_rejected(self, client):
+        capture(client, capture_id="k")
+
+        def merge(ids, keep):
+            body = {"ids": ids, "keep": keep}
+            return client.post("/api/captures/merge", json=body).status_code
+
+        assert merge(["k"], "k") == 400
+        assert merge(["k", "x"], "y") == 400
+        assert merge(["k", "x"], "k") == 404
+
+    def test_failed_merge_keeps_the_error_status_and_file(
+        self, server, client, monkeypatch
+    ):
+        kept = capture(client, content="first", capture_id="k")
+        capture(client, content="second", capture_id="o")
+        original = kept.read_text()
+
+        def merge_captures(keep, others, before_commit):
+            before_commit()
+            raise server.ConflictError("merged elsewhere")
+
+        monkeypatch.setattr(server.get_main_db(), "merge_captures", merge_captures)
+        response = client.post(
+            "/api/captures/merge", json={"ids": ["k", "o"], "keep": "k"}
+        )
+
+        assert response.status_code == 409
+        assert response.json() == {"error": "merged elsewhere"}
+        assert kept.read_text() == original
+
+    def test_locked_capture_survives_a_merge(self, client):
+        kept = capture(client, content="first", capture_id="k")
+        locked = capture(client, content="second", capture_id="o")
+        client.post("/api/captures/o/lock")
+
+        response = client.post(
+            "/api/captures/merge", json={"ids": ["k", "o"], "keep": "k"}
+        )
+
+        assert response.status_code == 409
+        assert locked.exists() and "second" not in kept.read_text()
+        ids = {c["capture_id"] for c in client.get("/api/captures").json()["captures"]}
+        assert ids == {"k", "o"}
+
+
+class TestMoveCapture:
+    def allow_vault(self, server, monkeypatch, root):
+        normalize = server.normalize_config
+
+        def with_allowed_roots(cfg):
+            normalized = normalize(cfg)
+            normalized["vault"]["allowed_roots"] = [str(root)]
+            return normalized
+
+        monkeypatch.setattr(server, "normalize_config", with_allowed_roots)
+
+    def test_moves_file_and_media_into_target_vault(
+        self, server, client, tmp_path, monkeypatch
+    ):
+        self.allow_vault(server, monkeypatch, tmp_path / "other")
+        response = client.post(
+            "/api/capture",
+            data={"content": "with image"},
+            files={"media": [("photo.png", b"png-bytes", "image/png")]},
+        )
+        source = Path(response.json()["saved_to"])
+
+        moved = client.post(
+            f"/api/captures/{source.stem}/move",
+            json={"target_vault": str(tmp_path / "other")},
+        )
+
+        assert moved.status_code == 200, moved.json()
+        target = Path(moved.json()["file_path"])
+        assert not source.exists()
+        assert target.parent == tmp_path / "other" / "capture" / "raw_capture"
+        assert (target.parent / "media" / "photo.png").read_bytes() == b"png-bytes"
+        assert "(media/photo.png)" in target.read_text()
+        listed = client.get("/api/captures").json()["captures"]
+        assert listed[0]["file_path"] == str(target)
+
+    def test_collision_gets_suffixed_id(self, server, client, tmp_path, monkeypatch):
+        self.allow_vault(server, monkeypatch, tmp_path / "other")
+        source = capture(client)
+        existing = tmp_path / "other" / "capture" / "raw_capture" / source.name
+        existing.parent.mkdir(parents=True)
+        existing.write_text("already here")
+
+        moved = client.post(
+            f"/api/captures/{source.stem}/move",
+            json={"target_vault": str(tmp_path / "other")},
+        ).json()
+
+        assert moved["capture_id"] == f"{source.stem}_1"
+        assert existing.read_text() == "already here"
+
+    def test_moves_rows_into_target_vault_database(
+        self, server, client, tmp_path, monkeypatch
+    ):
+        other = tmp_path / "other"
+        (tmp_path / "config.yaml").write_text(
+            "vault:\n  allowed_roots:\n"
+            f"    - path: {other}\n      database: .kms/main.db\n"
+        )
+        monkeypatch.setattr(server, "LOOPBACK_HOSTS", {"testclient"})
+        response = client.post(
+            "/api/capture",
+            data={"content": "with image", "tags": "moving"},
+            files={"media": [("photo.png", b"png-bytes", "image/png")]},
+        )
+        source = Path(response.json()["saved_to"])
+
+        moved = client.post(
+            f"/api/captures/{source.stem}/move", json={"target_vault": str(other)}
+        )
+
+        assert moved.status_code == 200, moved.json()
+        target = Path(moved.json()["file_path"])
+        assert (target.parent / "media" / "photo.png").read_bytes() == b"png-bytes"
+        assert client.get("/api/captures").json()["captures"] == []
+        listed = client.get(
+            "/api/captures", headers={"X-KMS-Vault": str(other)}
+        ).json()["captures"]
+        assert [(c["file_path"], c["tags"]) for c in listed] == [
+            (str(target), ["moving"])
+        ]
+
+    def test_target_outside_allowed_roots_is_forbidden(self, client, tmp_path):
+        source = capture(client)
+        response = client.post(
+            f"/api/captures/{source.stem}/move",
+            json={"target_vault": str(tmp_path / "elsewhere")},
+        )
+        assert response.status_code == 403
+        assert source.exists()
+
+
+class TestBrokenMedia:
+    def test_reports_links_and_rows_for_missing_files(self, client, vault):
+        response = client.post(
+            "/api/capture",
+            data={"content": "two pics"},
+            files={
+                "media": [
+                    ("kept.png", b"png", "image/png"),
+                    ("gone.png", b"png", "image/png"),
+                ]
+            },
+        )
+        capture_id = Path(response.json()["saved_to"]).stem
+        assert client.get("/api/media/broken").json()["count"] == 0
+
+        (vault / "capture" / "raw_capture" / "media" / "gone.png").unlink()
+
+        broken = client.get("/api/media/broken").json()["broken"]
+        assert sorted(
+            (b["capture_id"], b["source"], Path(b["resolved"]).name) for b in broken
+        ) == [
+            (capture_id, "database", "gone.png"),
+            (capture_id, "markdown", "gone.png"),
+        ]
+
+
+class TestVaultOverride:
+    def allow(self, tmp_path):
+        roots = tmp_path / "vaults"
+        (tmp_path / "config.yaml").write_text(f"vault:\n  allowed_roots: [{roots}]\n")
+        return roots / "work"
+
+    def post(self, client, vault):
+        return client.post(
+            "/api/capture",
+            data={"content": "routed"},
+            headers={"X-KMS-Vault": str(vault)},
+        )
+
+    def test_loopback_client_writes_to_an_allowed_vault(
+        self, server, client, tmp_path, monkeypatch
+    ):
+        monkeypatch.setattr(server, "LOOPBACK_HOSTS", {"testclient"})
+        work = self.allow(tmp_path)
+
+        response = self.post(client, work)
+
+        assert response.status_code == 200
+        assert Path(response.json()["saved_to"]).is_relative_to(work)
+
+    def test_non_loopback_client_is_forbidden(self, client, tmp_path):
+        work = self.allow(tmp_path)
+
+        response = self.post(client, work)
+
+        assert response.status_code == 403
+        assert "loopback" in response.json()["error"]
+        assert not work.exists()
+
+    def test_path_outside_allowed_roots_is_forbidden(
+        self, server, client, tmp_path, monkeypatch
+    ):
+        monkeypatch.setattr(server, "LOOPBACK_HOSTS", {"testclient"})
+        work = self.allow(tmp_path)
+
+        for vault in (tmp_path / "elsewhere", work / ".." / ".." / "elsewhere"):
+            response = self.post(client, vault)
+            assert response.status_code == 403
+            assert "allowed_roots" in response.json()["error"]
+        assert not (tmp_path / "elsewhere").exists()
+
+
+class TestPerVaultDatabases:
+    def test_vaults_with_their_own_database_are_isolated(
+        self, server, client, tmp_path, monkeypatch
+    ):
+        work, home = tmp_path / "vaults" / "work", tmp_path / "vaults" / "home"
+        (tmp_path / "config.yaml").write_text(
+            "vault:\n  allowed_roots:\n"
+            f"    - path: {work}\n      database: .kms/main.db\n"
+            f"    - path: {home}\n      database: {tmp_path / 'home.db'}\n"
+        )
+        monkeypatch.setattr(server, "LOOPBACK_HOSTS", {"testclient"})
+
+        def capture_in(vault, tag):
+            response = client.post(
+                "/api/capture",
+                data={"content": f"{tag} note", "tags": tag},
+                headers={"X-KMS-Vault": str(vault)},
+            )
+            assert response.status_code == 200, response.json()
+
+        def tags_in(vault=None):
+            headers = {"X-KMS-Vault": str(vault)} if vault else {}
+            response = client.get("/api/suggestions/tag", headers=headers)
+            return [s["value"] for s in response.json()["suggestions"]]
+
+        capture_in(work, "work-only")
+        capture_in(home, "home-only")
+        capture(client, tags="main-only")
+
+        assert (work / ".kms" / "main.db").exists()
+        assert (tmp_path / "home.db").exists()
+        assert tags_in(work) == ["work-only"]
+        assert tags_in(home) == ["home-only"]
+        assert tags_in() == ["main-only"]
+        listed = client.get(
+            "/api/captures",
+            params={"query": "note"},
+            headers={"X-KMS-Vault": str(home)},
+        ).json()
+        assert [c["tags"] for c in listed["captures"]] == [["home-only"]]
+
+    def test_captures_in_an_isolated_vault_can_be_edited_and_deleted(
+        self, server, client, tmp_path, monkeypatch
+    ):
+        work = tmp_path / "vaults" / "work"
+        (tmp_path / "config.yaml").write_text(
+            "vault:\n  allowed_roots:\n"
+            f"    - path: {work}\n      database: .kms/main.db\n"
+        )
+        monkeypatch.setattr(server, "LOOPBACK_HOSTS", {"testclient"})
+        headers = {"X-KMS-Vault": str(work)}
+        saved = client.post(
+            "/api/capture",
+            data={"content": "work note", "capture_id": "w"},
+            headers=headers,
+        )
+        idea_file = Path(saved.json()["saved_to"])
+
+        locked = client.post("/api/captures/w/lock", headers=headers)
+        edited = client.put(
+            "/api/captures/w",
+            params={"force": "true"},
+            json={"tags": ["edited"]},
+            headers=headers,
+        )
+        assert (locked.status_code, edited.status_code) == (200, 200)
+        assert "- edited\n" in idea_file.read_text()
+        listed = client.get("/api/captures", headers=headers).json()["captures"]
+        assert [c["tags"] for c in listed] == [["edited"]]
+        assert client.put("/api/captures/w", json={"tags": ["x"]}).status_code == 404
+
+        deleted = client.delete(
+            "/api/captures/w", params={"force": "true"}, headers=headers
+        )
+        assert deleted.status_code == 200
+        assert not idea_file.exists()
+        assert client.get("/api/captures", headers=headers).json()["captures"] == []
+
+
+class TestRecovery:
+    def test_failed_capture_keeps_clipboard_for_resubmit(
+        self, client, tmp_path, monkeypatch
+    ):
+        (tmp_path / "gone").symlink_to(tmp_path / "missing")
+        monkeypatch.setenv("KMS_VAULT_PATH", str(tmp_path / "gone"))
+        failed = client.post(
+            "/api/capture", data={"content": "note", "clipboard": "copied text"}
+        )
+
+        (draft,) = client.get("/api/recovery").json()["recovery"]
+        assert draft["id"] == failed.json()["recovery_id"]
+        assert draft["clipboard"] == "copied text"
+
+        monkeypatch.setenv("KMS_VAULT_PATH", str(tmp_path / "vault"))
+        saved = client.post(f"/api/recovery/{draft['id']}/resubmit").json()
+        assert "copied text" in Path(saved["saved_to"]).read_text()
+        assert client.get("/api/recovery").json()["recovery"] == []
+
+    def test_recovery_can_be_turned_off(self, client, tmp_path, monkeypatch):
+        (tmp_path / "config.yaml").write_text("capture:\n  recovery_enabled: false\n")
+        (tmp_path / "gone").symlink_to(tmp_path / "missing")
+        monkeypatch.setenv("KMS_VAULT_PATH", str(tmp_path / "gone"))
+
+        failed = client.post("/api/capture", data={"content": "note"})
+
+        assert failed.status_code == 400 and "recovery_id" not in failed.json()
+        assert client.get("/api/recovery").json()["recovery"] == []
+
+
+class TestDeadLetter:
+    def test_failed_capture_is_queued_and_retried(self, client, tmp_path, monkeypatch):
+        (tmp_path / "gone").symlink_to(tmp_path / "missing")
+        monkeypatch.setenv("KMS_VAULT_PATH", str(tmp_path / "gone"))
+
+        failed = client.post("/api/capture", data={"content": "don't lose me"})
+
+        recovery_id = failed.json()["recovery_id"]
+        queued = client.get("/api/deadletter").json()["recovery"]
+        assert [(d["id"], d["content"], d["attempts"]) for d in queued] == [
+            (recovery_id, "don't lose me", 1)
+        ]
+
+        retry = client.post(f"/api/deadletter/{recovery_id}/retry")
+        assert retry.status_code == 400
+        queued = client.get("/api/deadletter").json()["recovery"]
+        assert queued[0]["attempts"] == 2
+        assert "broken symlink" in queued[0]["error"]
+
+        monkeypatch.setenv("KMS_VAULT_PATH", str(tmp_path / "vault"))
+        saved = client.post(f"/api/deadletter/{recovery_id}/retry").json()
+        assert "don't lose me" in Path(saved["saved_to"]).read_text()
+        assert client.get("/api/deadletter").json()["recovery"] == []
+        missing = client.post(f"/api/deadletter/{recovery_id}/retry")
+        assert missing.status_code == 404
+
+
+class TestVaultPathChecks:
+    def test_paths_report_the_canonical_vault(self, client, tmp_path, monkeypatch):
+        (tmp_path / "real").mkdir()
+        (tmp_path / "link").symlink_to(tmp_path / "real")
+        monkeypatch.setenv("KMS_VAULT_PATH", str(tmp_path / "link"))
+
+        paths = client.get("/api/config/paths").json()
+
+        assert paths["vault"] == str(tmp_path / "link")
+        assert paths["vault_resolved"] == str(tmp_path / "real")
+        assert paths["vault_error"] is None
+        assert paths["database"] == str(tmp_path / "main.db")
+        assert capture(client).parent == tmp_path / "link" / "capture" / "raw_capture"
+
+    def test_broken_symlinks_and_loops_are_rejected(
+        self, client, tmp_path, monkeypatch
+    ):
+        (tmp_path / "broken").symlink_to(tmp_path / "missing")
+        (tmp_path / "loop").symlink_to(tmp_path / "loop")
+
+        for name, problem in [("broken", "broken symlink"), ("loop", "symlink loop")]:
+            monkeypatch.setenv("KMS_VAULT_PATH", str(tmp_path / name / "notes"))
+            response = client.post("/api/capture", data={"content": "note"})
+            assert response.status_code == 400
+            assert problem in response.json()["error"]
+            paths = client.get("/api/config/paths").json()
+            assert paths["vault_resolved"] is None
+            assert problem in paths["vault_error"]
+        assert not (tmp_path / "missing").exists()
+
+    def test_vault_can_be_confined_to_allowed_roots(self, client, tmp_path):
+        config_file = tmp_path / "config.yaml"
+        config_file.write_text(
+            "vault:\n  restrict_to_allowed_roots: true\n"
+            f"  allowed_roots: [{tmp_path / 'vaults'}]\n"
+        )
+
+        response = client.post("/api/capture", data={"content": "note"})
+
+        assert response.status_code == 403
+        assert "not in vault.allowed_roots" in response.json()["error"]
+        config_file.write_text(config_file.read_text() + "  on_invalid_path: warn\n")
+        assert capture(client).exists()
+
+    def test_warn_mode_reports_each_problem_once(
+        self, client, server, tmp_path, monkeypatch, capsys
+    ):
+        monkeypatch.setattr(server, "_warned_vault_problems", set())
+        (tmp_path / "config.yaml").write_text(
+            "vault:\n  restrict_to_allowed_roots: true\n"
+            f"  allowed_roots: [{tmp_path / 'vaults'}]\n  on_invalid_path: warn\n"
+        )
+
+        capture(client, content="one")
+        capture(client, content="two")
+
+        assert capsys.readouterr().out.count("not in vault.allowed_roots") == 1
+
+
+class TestVaultInit:
+    def test_creates_missing_structure_once(self, client, tmp_path, vault):
+        first = client.post("/api/vault/init").json()
+
+        config_file = tmp_path / "config.yaml"
+        expected = [vault, vault / "capture" / "raw_capture"]
+        assert [str(p) for p in expected] == first["created"][:2]
+        assert str(config_file) in first["created"] and config_file.exists()
+        assert (vault / "capture" / "raw_capture" / "media").is_dir()
+        second = client.post("/api/vault/init").json()
+        assert second["created"] == []
+        assert sorted(second["existing"]) == sorted(first["created"])
+
+
+class TestPostSaveHook:
+    def wait_for(self, path):
+        deadline = time.monotonic() + 5
+        while not path.exists() and time.monotonic() < deadline:
+            time.sleep(0.05)
+        return path.exists()
+
+    def test_hook_gets_capture_path_and_id(self, client, tmp_path):
+        out = tmp_path / "hook.txt"
+        (tmp_path / "config.yaml").write_text(
+            "capture:\n  post_save_hook: "
+            f"'echo $KMS_CAPTURE_ID $KMS_CAPTURE_PATH > {out}'\n"
+        )
+
+        path = capture(client, capture_id="hooked")
+
+        assert self.wait_for(out)
+        assert out.read_text() == f"hooked {path}\n"
+
+    def test_failures_and_skip_hook_never_fail_the_capture(self, client, tmp_path):
+        out = tmp_path / "hook.txt"
+        (tmp_path / "config.yaml").write_text(
+            f"capture:\n  post_save_hook: 'touch {out}; exit 3'\n"
+        )
+
+        capture(client, skip_hook="true")
+        time.sleep(0.2)
+        assert not out.exists()
+        capture(client, content="two")
+        assert self.wait_for(out)
+
+
+class TestArchive:
+    def test_moves_old_captures_and_keeps_media_links(self, client, vault):
+        response = client.post(
+            "/api/capture",
+            data={"content": "old", "timestamp": "2020-01-01T00:00:00Z"},
+            files={"media": ("pic.png", b"png", "image/png")},
+        )
+        old_file = Path(response.json()["saved_to"])
+        recent = capture(client, content="recent")
+
+        moved = client.post("/api/archive", params={"before": "2024-01-01"}).json()
+
+        assert moved["capture_ids"] == [old_file.stem]
+        assert not old_file.exists() and recent.exists()
+        archived = vault / "capture" / "raw_capture" / "archive" / old_file.name
+        text = archived.read_text()
+        assert "processing_status: archived" in text
+        assert "](../media/pic.png)" in text
+        listed = client.get("/api/captures", params={"status": "archived"}).json()
+        assert listed["captures"][0]["capture_id"] == old_file.stem
+        assert client.get("/api/captures/drift").json()["count"] == 0
+
+    def test_invalid_date_is_rejected(self, client):
+        assert client.post("/api/archive", params={"before": "soon"}).status_code == 400
+
+    def test_reindex_skips_the_archive(self, client, server):
+        old = capture(client, content="old", timestamp="2020-01-01T00:00:00Z")
+        capture(client, content="recent")
+        client.post("/api/archive", params={"before": "2024-01-01"})
+        server.get_main_db().delete_capture(old.stem)
+
+        result = client.post("/api/reindex").json()
+
+        assert (result["indexed"], result["skipped_excluded"]) == (1, 1)
+        listed = client.get("/api/captures", params={"status": "archived"}).json()
+        assert listed["count"] == 0
+        listed = client.get("/api/captures").json()["captures"]
+        assert [c["content"] for c in listed] == ["recent"]
+
+
+class TestExpiry:
+    def test_sweeper_only_removes_expired_unlocked_captures(
+        self, server, client, vault
+    ):
+        expired = capture(client, content="stash", expires_at="2025-01-01T00:00:00Z")
+        locked = capture(client, content="keep me", expires_at="2025-01-02")
+        client.post(f"/api/captures/{locked.stem}/lock")
+        later = capture(client, content="later", expires_at="2030-01-01T00:00:00Z")
+        forever = capture(client, content="forever")
+
+        assert "expires_at: '2025-01-01T00:00:00+00:00'" in expired.read_text()
+        preview = client.get("/api/captures/expiring", params={"before": "2026-01-01"})
+        assert [c["capture_id"] for c in preview.json()["captures"]] == [
+            expired.stem,
+            locked.stem,
+        ]
+        assert client.get("/api/captures/expiring").json()["count"] == 3
+
+        removed = server._sweep_expired()
+
+        assert removed == [expired.stem]
+        assert not expired.exists()
+        assert (vault / "capture" / "trash" / expired.name).exists()
+        assert locked.exists() and later.exists() and forever.exists()
+        listed = client.get("/api/captures").json()["captures"]
+        remaining = [c["capture_id"] for c in listed]
+        assert expired.stem not in remaining and forever.stem in remaining
+
+    def test_expiry_can_be_edited_and_cleared(self, client):
+        path = capture(client)
+        update = {"expires_at": "2031-05-01"}
+        assert client.put(f"/api/captures/{path.stem}", json=update).status_code == 200
+        assert client.get("/api/captures/expiring").json()["count"] == 1
+
+        client.put(f"/api/captures/{path.stem}", json={"expires_at": None})
+
+        assert client.get("/api/captures/expiring").json()["count"] == 0
+        assert "expires_at" not in path.read_text()
+
+    def test_invalid_expiry_is_rejected(self, client):
+        response = client.post(
+            "/api/capture", data={"content": "x", "expires_at": "tomorrow"}
+        )
+        assert response.status_code == 400
+
+
+class TestObsidianAttachments:
+    def test_media_follows_obsidian_attachment_folder(self, client, vault, tmp_path):
+        (vault / ".obsidian").mkdir(parents=True)
+        (vault / ".obsidian" / "app.json").write_text(
+            json.dumps({"attachmentFolderPath": "attachments"})
+        )
+        (tmp_path / "config.yaml").write_text(
+            "vault:\n  use_obsidian_attachment_folder: true\n"
+        )
+
+        response = client.post(
+            "/api/capture",
+            data={"content": "with file"},
+            files={"media": ("pic.png", b"png", "image/png")},
+        )
+
+        assert (vault / "attachments" / "pic.png").read_bytes() == b"png"
+        text = Path(response.json()["saved_to"]).read_text()
+        assert "](../../attachments/pic.png)" in text
+
+    def test_relative_folders_resolve_against_capture_dir(self, server, vault):
+        (vault / ".obsidian").mkdir(parents=True)
+        app_json = vault / ".obsidian" / "app.json"
+        cases = {"./": "capture/raw_capture", "./files": "capture/raw_capture/files"}
+        for folder, expected in {**cases, "/": "."}.items():
+            app_json.write_text(json.dumps({"attachmentFolderPath": folder}))
+            assert (
+                server._obsidian_attachment_dir(str(vault), "capture/raw_capture")
+                == expected
+            )
+
+    def test_ignored_without_opt_in(self, server, vault):
+        (vault / ".obsidian").mkdir(parents=True)
+        (vault / ".obsidian" / "app.json").write_text(
+            json.dumps({"attachmentFolderPath": "attachments"})
+        )
+        cfg = server.normalize_config({"vault": {"path": str(vault)}})
+        assert cfg["vault"]["media_dir"] == "capture/raw_capture/media"
+
+
+class TestExport:
+    def test_txt_strips_markdown(self, client):
+        path = capture(client, content="**Bold** and [a link](https://x.y)")
+
+        response = client.get(
+            f"/api/captures/{path.stem}/export", params={"format": "txt"}
+        )
+
+        assert response.text == "Content\nBold and a link\n"
+
+    def test_html_renders_markdown(self, client):
+        pytest.importorskip("markdown")
+        path = capture(client, content="**Bold**")
+
+        response = client.get(f"/api/captures/{path.stem}/export")
+
+        assert "<strong>Bold</strong>" in response.text
+
+    def test_unsupported_format_and_missing_converter_are_501(
+        self, server, client, monkeypatch
+    ):
+        path = capture(client)
+        monkeypatch.setattr(server.shutil, "which", lambda name: None)
+
+        for export_format in ("docx", "pdf"):
+            response = client.get(
+                f"/api/captures/{path.stem}/export", params={"format": export_format}
+            )
+            assert response.status_code == 501
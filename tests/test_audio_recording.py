@@ -279,6 +279,8 @@ class TestAudioRecordingManager:
         assert status['is_recording'] is False
         assert status['sample_rate'] == 44100
         assert status['channels'] == 1
+        assert status['duration_seconds'] == 0
+        assert status['samples_collected'] == 0
         
     def test_get_recording_status_nonexistent(self):
         manager = AudioRecordingManager()
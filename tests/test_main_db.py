@@ -0,0 +1,629 @@
+import itertools
+import os
+import sqlite3
+import sys
+from concurrent.futures import ThreadPoolExecutor
+from datetime import datetime, timedelta, timezone
+from pathlib import Path
+
+import pytest
+
+sys.path.insert(0, str(Path(__file__).parent.parent))
+
+from kms_errors import DatabaseError, StorageError
+from markdown_writer import SafeMarkdownWriter
+from server.main_db import (
+    MIGRATIONS,
+    MainDatabase,
+    _trigrams,
+    migrate,
+    value_color,
+)
+
+
+def make_db(tmp_path):
+    return MainDatabase(str(tmp_path / "main.db"))
+
+
+def store(db, capture_id, **fields):
+    capture = {
+        "capture_id": capture_id,
+        "timestamp": datetime(2025, 1, 1, tzinfo=timezone.utc),
+        "content": fields.pop("content", capture_id),
+        "modalities": ["text"],
+    }
+    capture.update(fields)
+    db.store_capture_data(capture)
+    return capture
+
+
+def suggestion_values(db, field_type, query):
+    return [s.value for s in db.get_suggestions(field_type, query, 10)]
+
+
+class TestUnicodeSuggestions:
+    def test_emoji_prefix_matches_context(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a", context="writing notes")
+        store(db, "b", context="☕ coffee chat")
+
+        assert suggestion_values(db, "context", "☕")[0] == "☕ coffee chat"
+
+    def test_prefix_does_not_split_zwj_sequence(self, tmp_path):
+        db = make_db(tmp_path)
+        for i in range(3):
+            store(db, f"work-{i}", context="\U0001f469\u200d\U0001f4bb work")
+        store(db, "mom", context="\U0001f469 mom")
+
+        # The woman emoji is only the first codepoint of the technologist
+        # sequence, so it must not count as a prefix match for it.
+        assert suggestion_values(db, "context", "\U0001f469")[0] == "\U0001f469 mom"
+
+    def test_decomposed_query_matches_precomposed_value_exactly(self, tmp_path):
+        db = make_db(tmp_path)
+        for i in range(5):
+            store(db, f"plural-{i}", tags=["cafe\u0301s"])
+        store(db, "single", tags=["caf\u00e9"])
+
+        assert suggestion_values(db, "tag", "cafe\u0301")[0] == "caf\u00e9"
+
+    def test_prefix_does_not_stop_before_combining_mark(self, tmp_path):
+        db = make_db(tmp_path)
+        for i in range(5):
+            store(db, f"accent-{i}", tags=["cafe\u0301"])
+        store(db, "plain", tags=["cafeteria"])
+
+        # In the decomposed value "cafe" + U+0301 the query "cafe" is a
+        # codepoint prefix, but only "cafeteria" is a grapheme prefix.
+        assert suggestion_values(db, "tag", "cafe")[0] == "cafeteria"
+
+
+class TestCaptureIdCollisions:
+    def test_same_second_captures_keep_distinct_files_and_rows(self, tmp_path):
+        db = make_db(tmp_path)
+        writer = SafeMarkdownWriter(str(tmp_path / "vault"))
+        ts = datetime(2025, 1, 1, 12, 0, 0, tzinfo=timezone.utc)
+
+        paths = []
+        for content in ("first", "second"):
+            capture = {"timestamp": ts, "content": content, "modalities": ["text"]}
+            capture["capture_id"] = writer.generate_capture_id(ts)
+            paths.append(writer.write_capture(capture))
+            capture["file_path"] = str(paths[-1])
+            db.store_capture_data(capture)
+
+        assert paths[0] != paths[1]
+        assert "second" in paths[1].read_text()
+        assert "first" in paths[0].read_text()
+        assert db.get_capture_file_path(paths[0].stem) == str(paths[0])
+        assert db.get_capture_file_path(paths[1].stem) == str(paths[1])
+        assert f"id: {paths[1].stem}" in paths[1].read_text()
+
+
+class TestLockedCaptures:
+    def test_lock_flag_round_trips(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a", locked=True)
+        store(db, "b")
+
+        assert db.is_locked("a")
+        assert not db.is_locked("b")
+
+        db.set_locked("a", False)
+        assert not db.is_locked("a")
+
+    def test_locked_column_added_to_existing_database(self, tmp_path):
+        conn = sqlite3.connect(tmp_path / "main.db")
+        conn.execute(
+            "CREATE TABLE captures (id INTEGER PRIMARY KEY AUTOINCREMENT, "
+            "capture_id TEXT UNIQUE NOT NULL, timestamp TEXT NOT NULL, content TEXT, "
+            "context TEXT, modalities TEXT, location TEXT, metadata TEXT, "
+            "created_date TEXT, last_edited_date TEXT, file_path TEXT)"
+        )
+        conn.commit()
+        conn.close()
+
+        db = make_db(tmp_path)
+        store(db, "a", locked=True)
+
+        assert db.is_locked("a")
+
+    def test_restoring_capture_replaces_its_tags(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a", tags=["old"])
+        store(db, "a", tags=["new"])
+
+        assert suggestion_values(db, "tag", "") == ["new"]
+
+
+class TestMigrations:
+    def test_migrate_upgrades_an_old_database(self, tmp_path):
+        conn = sqlite3.connect(tmp_path / "main.db")
+        conn.execute(
+            "CREATE TABLE captures (id INTEGER PRIMARY KEY AUTOINCREMENT, "
+            "capture_id TEXT UNIQUE NOT NULL, timestamp TEXT NOT NULL, content TEXT, "
+            "context TEXT, modalities TEXT, location TEXT, metadata TEXT, "
+            "created_date TEXT, last_edited_date TEXT, file_path TEXT)"
+        )
+        conn.execute(
+            "CREATE TABLE media_files (id INTEGER PRIMARY KEY AUTOINCREMENT, "
+            "capture_id TEXT NOT NULL, file_path TEXT NOT NULL, file_type TEXT, "
+            "file_name TEXT, timestamp TEXT NOT NULL)"
+        )
+        conn.execute(
+            "INSERT INTO captures (capture_id, timestamp, content) "
+            "VALUES ('old', '2024-01-01T00:00:00+00:00', 'kept')"
+        )
+
+        assert migrate(conn) == len(MIGRATIONS)
+        assert migrate(conn) == len(MIGRATIONS)
+        columns = [row[1] for row in conn.execute("PRAGMA table_info(captures)")]
+        assert {"importance", "locked", "parent", "file_sha256"} <= set(columns)
+        assert conn.execute(
+            "SELECT capture_id, content, importance FROM captures"
+        ).fetchall() == [("old", "kept", None)]
+        conn.commit()
+        conn.close()
+
+        db = make_db(tmp_path)
+        store(db, "new", importance=0.25)
+        importance = {c["capture_id"]: c["importance"] for c in db.list_captures({})}
+        assert importance == {"old": None, "new": 0.25}
+
+    def test_existing_columns_are_skipped(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a", locked=True)
+        with sqlite3.connect(db.db_path) as conn:
+            conn.execute("PRAGMA user_version = 0")
+
+        db = make_db(tmp_path)
+
+        assert db.is_locked("a")
+        with sqlite3.connect(db.db_path) as conn:
+            version = conn.execute("PRAGMA user_version").fetchone()[0]
+        assert version == len(MIGRATIONS)
+
+
+class TestParentChain:
+    def test_chain_is_nearest_first_and_stops_at_a_repeat(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a", parent="c")
+        store(db, "b", parent="a")
+        store(db, "c", parent="b")
+        store(db, "d", parent="c")
+
+        assert db.parent_chain("d") == ["c", "b", "a"]
+        assert db.parent_chain("missing") == []
+
+
+class TestRevisions:
+    def test_revision_records_given_text_or_content(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a", content="stored", revision_text="## Content\n\nstored")
+        store(db, "a", content="stored", revision_text="## Content\n\nstored")
+        store(db, "b", content="plain")
+
+        assert [r["revision"] for r in db.list_revisions("a")] == [1]
+        assert db.get_revision("a", 1) == "## Content\n\nstored"
+        assert db.get_revision("b", 1) == "plain"
+
+
+class TestExportImport:
+    def test_rows_move_to_another_database_under_a_new_id(self, tmp_path):
+        source = make_db(tmp_path)
+        store(source, "a", tags=["work"], parent="p")
+        (tmp_path / "other").mkdir()
+        target = MainDatabase(str(tmp_path / "other" / "main.db"))
+
+        target.import_capture(source.export_capture("a"), "b", "/vault/b.md")
+
+        (row,) = target.list_captures({})
+        assert (row["capture_id"], row["file_path"]) == ("b", "/vault/b.md")
+        assert row["tags"] == ["work"]
+        assert target.parent_chain("b") == ["p"]
+
+
+class TestSuggestionColors:
+    def colors(self, db, **options):
+        return {s.value: s.color for s in db.get_suggestions("tag", **options)}
+
+    def test_same_value_gets_the_same_color(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a", tags=["work", "home"])
+
+        colors = self.colors(db)
+
+        assert colors == {"work": value_color("work"), "home": value_color("home")}
+        assert colors["work"] != colors["home"]
+        assert self.colors(make_db(tmp_path)) == colors
+        assert value_color("work") == value_color("work")
+
+    def test_override_wins_over_palette(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a", tags=["work", "home"])
+
+        colors = self.colors(db, colors={"work": "#123456"}, palette=["#abcdef"])
+
+        assert colors == {"work": "#123456", "home": "#abcdef"}
+
+
+class TestSuggestionDecay:
+    def store_usage(self, db):
+        old = datetime.now(timezone.utc) - timedelta(days=60)
+        for i in range(10):
+            store(db, f"old-{i}", timestamp=old, tags=["project-old"])
+        store(db, "new", timestamp=datetime.now(timezone.utc), tags=["project-new"])
+
+    def test_count_mode_prefers_frequent_tag(self, tmp_path):
+        db = make_db(tmp_path)
+        self.store_usage(db)
+
+        assert suggestion_values(db, "tag", "project")[0] == "project-old"
+
+    def test_decay_mode_prefers_recent_tag(self, tmp_path):
+        db = make_db(tmp_path)
+        self.store_usage(db)
+
+        values = [
+            s.value
+            for s in db.get_suggestions(
+                "tag", "project", 10, scoring="decay", half_life_days=7
+            )
+        ]
+        assert values[0] == "project-new"
+
+
+class TestTagGraph:
+    def test_counts_nodes_and_co_occurrence_edges(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a", tags=["python", "sqlite"])
+        store(db, "b", tags=["python", "sqlite", "web"])
+        store(db, "c", tags=["python"])
+
+        graph = db.get_tag_graph()
+
+        assert {"id": "python", "count": 3} in graph["nodes"]
+        strongest = {"source": "python", "target": "sqlite", "weight": 2}
+        assert graph["edges"][0] == strongest
+        assert len(graph["edges"]) == 3
+
+    def test_min_weight_prunes_weak_edges(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a", tags=["python", "sqlite"])
+        store(db, "b", tags=["python", "sqlite", "web"])
+
+        edges = db.get_tag_graph(min_weight=2)["edges"]
+
+        assert edges == [{"source": "python", "target": "sqlite", "weight": 2}]
+
+
+class TestCaptureGraph:
+    def test_wikilinks_become_edges_between_captures(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a", content="see [[b]] and [[c|the c note]], ![[image.png]]")
+        store(db, "b", content="back to [[a#intro]]", tags=["python"])
+        store(db, "c", content="links to [[missing]]", file_path="/v/c-title.md")
+        store(db, "d", content="refers to [[c-title]]")
+
+        graph = db.get_capture_graph(include_tags=True)
+
+        edges = graph["edges"]
+        links = {(e["source"], e["target"]) for e in edges if e["type"] == "link"}
+        assert links == {("a", "b"), ("a", "c"), ("b", "a"), ("d", "c")}
+        assert {"id": "tag:python", "type": "tag", "label": "python"} in graph["nodes"]
+        assert {"source": "b", "target": "tag:python", "type": "tag"} in edges
+        assert not graph["truncated"]
+
+    def test_labels_come_from_the_given_function(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a", content="# Title\nbody")
+        store(db, "b", content="")
+
+        graph = db.get_capture_graph(label=lambda content: content[:7] or None)
+
+        labels = {n["id"]: n["label"] for n in graph["nodes"]}
+        assert labels == {"a": "# Title", "b": "b"}
+
+    def test_local_graph_follows_depth_and_limit(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a", content="[[b]]")
+        store(db, "b", content="[[c]]")
+        store(db, "c", content="[[d]]")
+        store(db, "d", content="end")
+
+        def ids(**kwargs):
+            return [n["id"] for n in db.get_capture_graph("b", **kwargs)["nodes"]]
+
+        assert ids(depth=1) == ["b", "a", "c"]
+        assert ids(depth=2) == ["b", "a", "c", "d"]
+        capped = db.get_capture_graph("b", depth=2, limit=2)
+        assert [n["id"] for n in capped["nodes"]] == ["b", "a"]
+        assert capped["truncated"]
+        assert capped["edges"] == [{"source": "a", "target": "b", "type": "link"}]
+
+
+class TestUiState:
+    def test_survives_reopening_the_database(self, tmp_path):
+        make_db(tmp_path).update_ui_state({"last_vault": "home", "zoom": 1.25})
+        db = make_db(tmp_path)
+
+        assert db.get_ui_state() == {"last_vault": "home", "zoom": 1.25}
+        assert db.update_ui_state({"zoom": None}) == {"last_vault": "home"}
+
+
+class TestSearchCaptures:
+    @pytest.fixture
+    def db(self, tmp_path):
+        db = make_db(tmp_path)
+        for day, capture_id, tags in (
+            (1, "jan-1", ["work"]),
+            (5, "jan-5", ["home"]),
+            (9, "jan-9", ["work"]),
+        ):
+            timestamp = datetime(2025, 1, day, 12, tzinfo=timezone.utc)
+            store(db, capture_id, timestamp=timestamp, tags=tags)
+        return db
+
+    def ids(self, results):
+        return [r["capture_id"] for r in results]
+
+    def test_empty_query_returns_most_recent(self, db):
+        assert self.ids(db.search_captures("")) == ["jan-9", "jan-5", "jan-1"]
+        assert self.ids(db.search_captures("", limit=1, offset=1)) == ["jan-5"]
+
+    def test_filters_by_tag(self, db):
+        assert self.ids(db.search_captures("", {"tag": "work"})) == ["jan-9", "jan-1"]
+
+    def test_filters_by_date_range(self, db):
+        capture_filter = {"from": "2025-01-02", "before": "2025-01-09"}
+        assert self.ids(db.search_captures("", capture_filter)) == ["jan-5"]
+
+    def test_matches_context_and_snips_content(self, db):
+        store(db, "long", content="x " * 200 + "needle", context="reading")
+
+        assert self.ids(db.search_captures("reading")) == ["long"]
+        snippet = db.search_captures("needle")[0]["snippet"]
+        assert snippet.startswith("…") and snippet.endswith("needle")
+
+
+class TestModalities:
+    def test_list_captures_filters_by_modality(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a", modalities=["text", "audio"])
+        store(db, "b", modalities=["text"])
+
+        ids = [c["capture_id"] for c in db.list_captures({"modality": "audio"})]
+
+        assert ids == ["a"]
+        assert len(db.list_captures({})) == 2
+
+    def test_existing_json_modalities_are_backfilled(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a", modalities=["screenshot"])
+        with sqlite3.connect(db.db_path) as conn:
+            conn.execute("DELETE FROM modalities")
+
+        db = make_db(tmp_path)
+
+        assert db.find_capture_ids({"modality": "screenshot"}) == ["a"]
+
+    def test_modalities_are_suggested(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a", modalities=["audio"])
+
+        assert suggestion_values(db, "modality", "aud") == ["audio"]
+
+
+class TestTransactions:
+    def test_sqlite_errors_become_database_errors(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a", tags=["keep"])
+        with sqlite3.connect(db.db_path) as conn:
+            conn.execute("DROP TABLE sources")
+
+        with pytest.raises(DatabaseError):
+            db.delete_capture("a")
+        assert db.capture_exists("a")
+
+    def test_before_commit_failure_rolls_back(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a")
+
+        def fail():
+            raise StorageError("disk full")
+
+        with pytest.raises(StorageError):
+            db.set_locked("a", True, before_commit=fail)
+        assert not db.is_locked("a")
+
+
+class TestBrowseTags:
+    def test_counts_and_most_recent_captures_per_tag(self, tmp_path):
+        db = make_db(tmp_path)
+        for day in range(1, 5):
+            ts = datetime(2025, 1, day, tzinfo=timezone.utc)
+            store(db, f"day-{day}", timestamp=ts, tags=["daily"])
+        store(db, "other", tags=["misc"])
+
+        tags = db.browse_tags(recent=2)
+
+        assert tags[0] == {
+            "tag": "daily",
+            "count": 4,
+            "recent_capture_ids": ["day-4", "day-3"],
+        }
+        assert tags[1] == {"tag": "misc", "count": 1, "recent_capture_ids": ["other"]}
+
+
+class TestCapturesNear:
+    def test_returns_captures_within_radius_sorted_by_distance(self, tmp_path):
+        db = make_db(tmp_path)
+        # Around Champaign, IL: ~1.1 km and ~5.5 km away, then Chicago
+        store(db, "near", location={"latitude": 40.12, "longitude": -88.24})
+        store(db, "town", location={"latitude": 40.16, "longitude": -88.24})
+        store(db, "far", location={"latitude": 41.88, "longitude": -87.63})
+        store(db, "nowhere", location=None)
+
+        captures = db.captures_near(40.11, -88.24, radius_km=10)
+
+        assert [c["capture_id"] for c in captures] == ["near", "town"]
+        assert captures[0]["distance_km"] < captures[1]["distance_km"] < 10
+
+
+class TestFindDuplicates:
+    def test_pairs_near_identical_content_only(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a", content="Remember to renew the passport before June")
+        store(db, "b", content="remember to renew the  passport before June!")
+        store(db, "c", content="Buy oat milk and coffee beans")
+
+        pairs = db.find_duplicates(0.8)
+
+        assert [sorted(p["capture_ids"]) for p in pairs] == [["a", "b"]]
+        assert 0.8 <= pairs[0]["score"] < 1
+
+    def test_matches_comparing_every_pair(self, tmp_path):
+        db = make_db(tmp_path)
+        words = "alpha beta gamma delta epsilon zeta eta theta iota kappa".split()
+        contents = {}
+        for n in range(40):
+            text = " ".join(words[(n * k) % len(words)] for k in range(1, 4 + n % 5))
+            contents[f"c{n}"] = text
+            store(db, f"c{n}", content=text)
+
+        for threshold in (0.3, 0.6, 0.9, 1.0):
+            expected = set()
+            for a, b in itertools.combinations(contents, 2):
+                set_a, set_b = _trigrams(contents[a]), _trigrams(contents[b])
+                if len(set_a & set_b) / len(set_a | set_b) >= threshold:
+                    expected.add(frozenset((a, b)))
+
+            pairs = db.find_duplicates(threshold)
+
+            assert {frozenset(p["capture_ids"]) for p in pairs} == expected
+
+
+class TestFullTextIndex:
+    def fts_ids(self, db, query):
+        with sqlite3.connect(db.db_path) as conn:
+            return [
+                row[0]
+                for row in conn.execute(
+                    "SELECT c.capture_id FROM captures_fts f "
+                    "JOIN captures c ON c.id = f.rowid WHERE captures_fts MATCH ?",
+                    (query,),
+                )
+            ]
+
+    def test_index_follows_restores_and_deletes(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a", content="first draft")
+        store(db, "a", content="second version")
+        store(db, "b", content="second opinion")
+        db.delete_capture("b")
+
+        assert self.fts_ids(db, "first") == []
+        assert self.fts_ids(db, "second") == ["a"]
+
+    def test_rebuild_recovers_from_drift(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a", content="orchid")
+        with sqlite3.connect(db.db_path) as conn:
+            conn.execute("INSERT INTO captures_fts(captures_fts) VALUES ('delete-all')")
+
+        assert self.fts_ids(db, "orchid") == []
+        assert db.rebuild_fts() == 1
+        assert self.fts_ids(db, "orchid") == ["a"]
+
+
+class TestDrift:
+    def test_reports_modified_and_missing_files_only(self, tmp_path):
+        db = make_db(tmp_path)
+        files = {}
+        for capture_id in ("same", "touched", "edited", "gone"):
+            files[capture_id] = tmp_path / f"{capture_id}.md"
+            files[capture_id].write_text(capture_id)
+            store(db, capture_id, file_path=str(files[capture_id]))
+
+        os.utime(files["touched"], ns=(0, 0))
+        files["edited"].write_text("changed in another editor")
+        os.utime(files["edited"], ns=(0, 0))
+        files["gone"].unlink()
+
+        drift = {d["capture_id"]: d["status"] for d in db.find_drift()}
+        assert drift == {"edited": "modified", "gone": "missing"}
+
+
+class TestMediaPaths:
+    def test_paths_under_the_vault_are_stored_relative(self, tmp_path):
+        vault = tmp_path / "vault"
+        db = MainDatabase(str(tmp_path / "main.db"), str(vault))
+        inside = str(vault / "capture" / "raw_capture" / "media" / "a.png")
+        store(db, "a", media_files=[{"path": inside}, {"path": "/elsewhere/b.png"}])
+
+        with sqlite3.connect(db.db_path) as conn:
+            stored = [r[0] for r in conn.execute("SELECT file_path FROM media_files")]
+        assert stored == ["capture/raw_capture/media/a.png", "/elsewhere/b.png"]
+        assert db.get_media_paths("a") == [inside, "/elsewhere/b.png"]
+
+    def test_existing_absolute_rows_are_migrated(self, tmp_path):
+        vault = tmp_path / "vault"
+        store(make_db(tmp_path), "a", media_files=[{"path": str(vault / "m.png")}])
+
+        db = MainDatabase(str(tmp_path / "main.db"), str(vault))
+
+        with sqlite3.connect(db.db_path) as conn:
+            (stored,) = conn.execute("SELECT file_path FROM media_files").fetchone()
+        assert stored == "m.png"
+
+
+class TestConcurrentInit:
+    def test_concurrent_opens_migrate_one_at_a_time(self, tmp_path, monkeypatch):
+        running, overlaps = [], []
+        init_database = MainDatabase.init_database
+
+        def tracking_init(self):
+            running.append(self)
+            overlaps.append(len(running))
+            try:
+                init_database(self)
+            finally:
+                running.remove(self)
+
+        monkeypatch.setattr(MainDatabase, "init_database", tracking_init)
+        path = str(tmp_path / "main.db")
+
+        with ThreadPoolExecutor(max_workers=16) as pool:
+            dbs = list(pool.map(lambda _: MainDatabase(path), range(32)))
+
+        assert len(overlaps) == 32 and max(overlaps) == 1
+        for n, db in enumerate(dbs[:4]):
+            store(db, f"c{n}")
+        assert dbs[-1].capture_exists("c3")
+
+
+class TestCaptureFields:
+    def test_merge_keeps_the_kept_captures_fields(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a", fields={"author": "kept"})
+        store(db, "b", fields={"author": "merged", "page": 3})
+
+        db.merge_captures("a", ["b"])
+
+        listed = db.list_captures({"capture_ids": ["a"]})
+        assert listed[0]["fields"] == {"author": "kept", "page": 3}
+        assert db.find_capture_ids({"fields": [("page", "3")]}) == ["a"]
+
+    def test_listing_groups_fields_by_capture(self, tmp_path):
+        db = make_db(tmp_path)
+        store(db, "a", fields={"author": "ann", "page": 1})
+        store(db, "b")
+        store(db, "c", fields={"done": True})
+
+        listed = {c["capture_id"]: c["fields"] for c in db.list_captures({})}
+
+        assert listed == {
+            "a": {"author": "ann", "page": 1},
+            "b": {},
+            "c": {"done": True},
+        }
@@ -0,0 +1,484 @@
+import os
+import sys
+from datetime import datetime, timezone
+from pathlib import Path
+
+import pytest
+
+sys.path.insert(0, str(Path(__file__).parent.parent))
+
+from kms_errors import StorageError, ValidationError
+from markdown_writer import SafeMarkdownWriter, content_sha256, media_links
+
+
+def make_writer(tmp_path, **vault_options):
+    return SafeMarkdownWriter(str(tmp_path), {"vault": vault_options})
+
+
+def base_capture(**overrides):
+    capture = {
+        "timestamp": datetime(2025, 8, 16, 6, 58, 42, tzinfo=timezone.utc),
+        "capture_id": "2025-08-16T06:58:42+00:00",
+        "modalities": ["text"],
+    }
+    capture.update(overrides)
+    return capture
+
+
+class TestTrailingNewline:
+    def test_frontmatter_only_ends_with_single_newline(self, tmp_path):
+        output = make_writer(tmp_path).format_capture(base_capture())
+        assert output.endswith("---\n")
+        assert not output.endswith("\n\n")
+
+    def test_content_with_trailing_newlines_is_collapsed(self, tmp_path):
+        output = make_writer(tmp_path).format_capture(
+            base_capture(content="some text\n\n\n")
+        )
+        assert output.endswith("some text\n")
+        assert not output.endswith("\n\n")
+
+    def test_policy_can_be_disabled(self, tmp_path):
+        output = make_writer(tmp_path, trailing_newline=False).format_capture(
+            base_capture(content="some text\n\n")
+        )
+        assert output.endswith("some text\n\n\n")
+
+
+class TestMediaLinkStyle:
+    def media_capture(self, tmp_path):
+        media_dir = tmp_path / "capture" / "raw_capture" / "media"
+        return base_capture(
+            media_files=[
+                {"path": str(media_dir / "screen.png"), "type": "screenshot"},
+                {"path": str(media_dir / "shot.png"), "type": "image"},
+                {"path": str(media_dir / "memo.wav"), "type": "audio"},
+                {"path": str(media_dir / "doc.pdf"), "type": "file"},
+            ]
+        )
+
+    def test_markdown_style_is_default(self, tmp_path):
+        output = make_writer(tmp_path).format_capture(self.media_capture(tmp_path))
+        assert "## Screenshot\n![Screenshot](media/screen.png)\n" in output
+        assert "## Image\n![Image](media/shot.png)\n" in output
+        assert "## Audio\n[Audio Recording](media/memo.wav)\n" in output
+        assert "## File\n[Attachment](media/doc.pdf)\n" in output
+
+    def test_wikilink_style(self, tmp_path):
+        writer = make_writer(tmp_path, media_link_style="wikilink")
+        output = writer.format_capture(self.media_capture(tmp_path))
+        assert "## Screenshot\n![[media/screen.png]]\n" in output
+        assert "## Image\n![[media/shot.png]]\n" in output
+        assert "## Audio\n[[media/memo.wav]]\n" in output
+        assert "## File\n[[media/doc.pdf]]\n" in output
+
+    def test_media_sections_follow_configured_order(self, tmp_path):
+        media_dir = tmp_path / "capture" / "raw_capture" / "media"
+        capture = base_capture(
+            media_files=[
+                {"path": str(media_dir / "doc.pdf"), "type": "file"},
+                {"path": str(media_dir / "a.png"), "type": "image"},
+                {"path": str(media_dir / "memo.wav"), "type": "audio"},
+                {"path": str(media_dir / "b.png"), "type": "image"},
+            ]
+        )
+        writer = make_writer(tmp_path, media_section_order=["image", "audio"])
+
+        output = writer.format_capture(capture)
+
+        positions = [
+            output.index(name) for name in ("a.png", "b.png", "memo.wav", "doc.pdf")
+        ]
+        assert positions == sorted(positions)
+
+    def test_media_sections_can_be_disabled(self, tmp_path):
+        writer = make_writer(tmp_path, embed_media=False)
+        output = writer.format_capture(self.media_capture(tmp_path))
+        assert "## Screenshot" not in output
+        assert "## Image" not in output
+        assert "## Audio" not in output
+        assert "media/" not in output
+
+
+class TestMediaLinks:
+    def test_only_media_sections_are_scanned(self):
+        body = (
+            "## Content\n[notes](other.md) ![[diagram.png]]\n"
+            "## Image\n![Image](media/shot.png)\n"
+            "## File\n[[media/doc.pdf|the doc]]\n"
+            "## Audio\n[Audio Recording](media/memo.wav)\n"
+        )
+        assert media_links(body) == [
+            ("media/shot.png", "markdown"),
+            ("media/doc.pdf", "wikilink"),
+            ("media/memo.wav", "markdown"),
+        ]
+
+
+class TestReadCapture:
+    def test_round_trip_is_stable(self, tmp_path):
+        writer = make_writer(tmp_path)
+        audio = writer.media_dir / "note.wav"
+        path = writer.write_capture(
+            base_capture(
+                content="hello\n\nsecond paragraph",
+                blocks=[{"heading": "Follow-up", "body": "call Sam"}],
+                clipboard="let x = 1;",
+                context="reading",
+                tags=["a", "b"],
+                sources=["book"],
+                modalities=["text", "clipboard", "audio"],
+                media_files=[{"path": str(audio), "type": "audio"}],
+            )
+        )
+        original = path.read_text()
+
+        capture = writer.read_capture(path)
+        writer.write_capture(capture, path)
+
+        assert path.read_text() == original
+        assert capture["content"] == "hello\n\nsecond paragraph"
+        assert capture["clipboard"] == "```\nlet x = 1;\n```"
+        assert capture["blocks"] == [{"heading": "Follow-up", "body": "call Sam"}]
+        assert capture["tags"] == ["a", "b"] and capture["sources"] == ["book"]
+        assert capture["modalities"] == ["text", "clipboard", "audio"]
+        assert capture["media_files"] == [
+            {"path": str(audio), "name": "note.wav", "type": "audio"}
+        ]
+
+    def test_malformed_frontmatter_is_treated_as_missing(self, tmp_path):
+        writer = make_writer(tmp_path)
+        path = writer.capture_dir / "broken.md"
+        path.write_text("---\ntags: [unclosed\n---\n## Content\nstill here\n")
+
+        capture = writer.read_capture(path)
+
+        assert capture["capture_id"] == "broken"
+        assert capture["content"] == "still here"
+        assert capture["tags"] == [] and capture["modalities"] == ["text"]
+        assert "created_date" not in capture
+
+    def test_hand_edited_values_are_tolerated(self, tmp_path):
+        writer = make_writer(tmp_path)
+        path = writer.capture_dir / "edited.md"
+        path.write_text("---\ntimestamp: last tuesday\ntags: a, b\n---\nplain text\n")
+
+        capture = writer.read_capture(path)
+
+        assert capture["tags"] == ["a", "b"]
+        assert capture["content"] == "plain text"
+        assert isinstance(capture["timestamp"], datetime)
+
+    def test_round_trips_written_capture(self, tmp_path):
+        writer = make_writer(tmp_path)
+        path = writer.write_capture(
+            base_capture(
+                content="hello",
+                context="reading",
+                tags=["a", "b"],
+                sources=["book"],
+            )
+        )
+
+        capture = writer.read_capture(path)
+
+        assert capture["capture_id"] == "2025-08-16T06:58:42+00:00"
+        assert capture["timestamp"] == base_capture()["timestamp"]
+        assert capture["content"] == "hello"
+        assert capture["context"] == "reading"
+        assert capture["tags"] == ["a", "b"]
+        assert capture["sources"] == ["book"]
+        assert capture["file_path"] == str(path)
+
+    def test_custom_fields_round_trip_with_prefix(self, tmp_path):
+        writer = make_writer(tmp_path)
+        path = writer.write_capture(base_capture(fields={"tags": "x", "page": 4}))
+
+        frontmatter, _ = writer.split_frontmatter(path.read_text())
+        assert frontmatter["field_tags"] == "x" and frontmatter["tags"] == []
+        assert writer.read_capture(path)["fields"] == {"tags": "x", "page": 4}
+
+    def test_file_without_frontmatter_uses_file_name(self, tmp_path):
+        writer = make_writer(tmp_path)
+        path = writer.capture_dir / "loose-note.md"
+        path.write_text("just text\n")
+
+        capture = writer.read_capture(path)
+
+        assert capture["capture_id"] == "loose-note"
+        assert capture["content"] == "just text"
+
+
+class TestLockedFrontmatter:
+    def test_locked_only_written_when_set(self, tmp_path):
+        writer = make_writer(tmp_path)
+        assert "locked" not in writer.format_capture(base_capture())
+        assert "locked: true" in writer.format_capture(base_capture(locked=True))
+
+    def test_rewrite_frontmatter_removes_none_values(self, tmp_path):
+        writer = make_writer(tmp_path)
+        path = writer.write_capture(base_capture(locked=True))
+
+        writer.rewrite_frontmatter(path, {"locked": None})
+
+        assert "locked" not in path.read_text()
+        assert not writer.read_capture(path)["locked"]
+
+
+class TestStorageErrors:
+    def test_failed_write_raises_storage_error(self, tmp_path):
+        writer = make_writer(tmp_path)
+        target = tmp_path / "missing-dir" / "note.md"
+
+        with pytest.raises(StorageError):
+            writer.atomic_write(target, "text")
+        assert not target.with_suffix(".tmp").exists()
+
+
+class TestDateFormat:
+    def test_default_is_iso_date(self, tmp_path):
+        output = make_writer(tmp_path).format_capture(base_capture())
+        assert "created_date: '2025-08-16'" in output
+
+    def test_custom_format_round_trips(self, tmp_path):
+        writer = make_writer(tmp_path, date_format="%d/%m/%Y %H:%M")
+        path = writer.write_capture(base_capture())
+
+        assert "created_date: 16/08/2025 06:58" in path.read_text()
+        capture = writer.read_capture(path)
+        assert capture["created_date"] == "16/08/2025 06:58"
+        assert writer.parse_date(capture["created_date"]) == datetime(
+            2025, 8, 16, 6, 58
+        )
+
+    def test_iso_dates_still_parse_with_custom_format(self, tmp_path):
+        writer = make_writer(tmp_path, date_format="%d.%m.%Y")
+        assert writer.parse_date("2025-08-16") == datetime(2025, 8, 16)
+
+
+class TestClipboardLanguage:
+    def clipboard_section(self, tmp_path, **fields):
+        output = make_writer(tmp_path).format_capture(base_capture(**fields))
+        return output.split("## Clipboard\n", 1)[1]
+
+    def test_fenced_clipboard_is_untouched(self, tmp_path):
+        clip = "```js\nconsole.log(1)\n```"
+        assert self.clipboard_section(tmp_path, clipboard=clip) == clip + "\n"
+
+    def test_fenced_clipboard_ignores_language_hint(self, tmp_path):
+        clip = "```\nplain\n```"
+        section = self.clipboard_section(
+            tmp_path, clipboard=clip, clipboard_lang="rust"
+        )
+        assert section == clip + "\n"
+
+    def test_client_language_hint(self, tmp_path):
+        section = self.clipboard_section(
+            tmp_path, clipboard="fn main() {}", clipboard_lang="rust"
+        )
+        assert section == "```rust\nfn main() {}\n```\n"
+
+    def test_shebang_detection(self, tmp_path):
+        clip = "#!/usr/bin/env python3\nprint('hi')"
+        section = self.clipboard_section(tmp_path, clipboard=clip)
+        assert section == f"```python\n{clip}\n```\n"
+
+    def test_file_name_comment_detection(self, tmp_path):
+        clip = "// src/main.rs\nfn main() {}"
+        assert self.clipboard_section(tmp_path, clipboard=clip).startswith("```rust\n")
+
+    def test_plain_text_gets_no_hint(self, tmp_path):
+        section = self.clipboard_section(tmp_path, clipboard="just a sentence")
+        assert section == "```\njust a sentence\n```\n"
+
+
+class TestClipboardFence:
+    PROSE = "Met with the team today.\nWe agreed to ship on Friday."
+    CODE = "for x in items:\n    total += x\nprint(total)"
+
+    def clipboard_section(self, tmp_path, clip, **capture_options):
+        writer = SafeMarkdownWriter(str(tmp_path), {"capture": capture_options})
+        output = writer.format_capture(base_capture(clipboard=clip))
+        return output.split("## Clipboard\n", 1)[1]
+
+    def is_fenced(self, tmp_path, clip, **capture_options):
+        return self.clipboard_section(tmp_path, clip, **capture_options).startswith(
+            "```"
+        )
+
+    def test_auto_fences_one_liners_only(self, tmp_path):
+        assert self.is_fenced(tmp_path, "a short note")
+        assert not self.is_fenced(tmp_path, self.PROSE)
+        assert not self.is_fenced(tmp_path, self.CODE)
+
+    def test_always_fences_prose_and_code(self, tmp_path):
+        assert self.is_fenced(tmp_path, self.PROSE, clipboard_fence="always")
+        assert self.is_fenced(tmp_path, self.CODE, clipboard_fence="always")
+
+    def test_code_fences_only_code(self, tmp_path):
+        assert not self.is_fenced(tmp_path, "a short note", clipboard_fence="code")
+        assert not self.is_fenced(tmp_path, self.PROSE, clipboard_fence="code")
+        assert self.is_fenced(tmp_path, self.CODE, clipboard_fence="code")
+        assert self.is_fenced(tmp_path, "let x = 1;", clipboard_fence="code")
+
+    def test_never_leaves_code_unfenced(self, tmp_path):
+        section = self.clipboard_section(tmp_path, self.CODE, clipboard_fence="never")
+        assert section == self.CODE + "\n"
+
+    def test_min_lines_skips_short_clips(self, tmp_path):
+        options = {"clipboard_fence": "always", "clipboard_fence_min_lines": 3}
+        assert not self.is_fenced(tmp_path, self.PROSE, **options)
+        assert self.is_fenced(tmp_path, self.CODE, **options)
+        assert not self.is_fenced(
+            tmp_path, "a short note", clipboard_fence_min_lines=2
+        )
+
+    def test_detected_language_keeps_its_hint(self, tmp_path):
+        clip = "#!/usr/bin/env bash\necho hi"
+        section = self.clipboard_section(tmp_path, clip, clipboard_fence="code")
+        assert section == f"```bash\n{clip}\n```\n"
+
+    def test_unknown_mode_is_rejected(self, tmp_path):
+        with pytest.raises(ValidationError):
+            self.clipboard_section(tmp_path, "x", clipboard_fence="sometimes")
+
+
+class TestMirrorPaths:
+    def test_copies_capture_and_media_to_each_mirror(self, tmp_path):
+        mirror = tmp_path / "mirror"
+        broken = tmp_path / "not-a-dir"
+        broken.write_text("")
+        writer = make_writer(
+            tmp_path / "vault", mirror_paths=[str(mirror), str(broken)]
+        )
+        idea_file = writer.write_capture(base_capture(content="hi"))
+        image = writer.media_dir / "photo.png"
+        image.write_bytes(b"png")
+
+        mirrored = writer.mirror_files([idea_file, image])
+
+        assert mirrored == [str(mirror)]
+        rel_path = idea_file.relative_to(tmp_path / "vault")
+        assert (mirror / rel_path).read_text() == idea_file.read_text()
+        assert (mirror / "capture" / "raw_capture" / "media" / "photo.png").exists()
+
+
+class TestTemplateFile:
+    def make_templated_writer(self, tmp_path):
+        return SafeMarkdownWriter(
+            str(tmp_path), {"capture": {"template_file": "templates/default.md"}}
+        )
+
+    def test_missing_template_uses_built_in_layout(self, tmp_path):
+        output = self.make_templated_writer(tmp_path).format_capture(
+            base_capture(content="hi")
+        )
+        assert "## Content\nhi\n" in output
+
+    def test_placeholders_are_rendered_and_changes_reloaded(self, tmp_path):
+        template = tmp_path / "templates" / "default.md"
+        template.parent.mkdir()
+        template.write_text(
+            "# {{ capture_id }}\n## Content\n{{content}}\nTags: {{tags}}{{nope}}\n"
+        )
+        writer = self.make_templated_writer(tmp_path)
+
+        output = writer.format_capture(base_capture(content="hi", tags=["a", "b"]))
+        assert output.endswith(
+            "---\n# 2025-08-16T06:58:42+00:00\n## Content\nhi\nTags: a, b\n"
+        )
+
+        template.write_text("{{sections}}")
+        os.utime(template, ns=(0, 0))
+        output = writer.format_capture(base_capture(content="hi"))
+        assert output.endswith("---\n## Content\nhi\n")
+
+    def test_templates_can_be_chosen_by_modality(self, tmp_path):
+        (tmp_path / "audio.md").write_text("Audio\n## Content\n{{content}}")
+        (tmp_path / "default.md").write_text("Note\n## Content\n{{content}}")
+        templates = {"audio": "audio.md", "default": "default.md"}
+        writer = SafeMarkdownWriter(
+            str(tmp_path), {"capture": {"template_file": templates}}
+        )
+
+        audio = base_capture(content="hi", modalities=["text", "audio"])
+        assert writer.format_capture(audio).endswith("---\nAudio\n## Content\nhi\n")
+        assert writer.format_capture(base_capture(content="hi")).endswith(
+            "---\nNote\n## Content\nhi\n"
+        )
+
+    def test_template_without_content_section_uses_built_in_layout(self, tmp_path):
+        template = tmp_path / "templates" / "default.md"
+        template.parent.mkdir()
+        template.write_text("Note: {{content}}\n")
+        writer = self.make_templated_writer(tmp_path)
+
+        output = writer.format_capture(base_capture(content="hi"))
+        assert output.endswith("---\n## Content\nhi\n")
+
+
+class TestContentHash:
+    def test_hash_covers_body_and_follows_rewrites(self, tmp_path):
+        writer = SafeMarkdownWriter(str(tmp_path), {"capture": {"content_hash": True}})
+        idea_file = writer.write_capture(base_capture(content="hi"))
+        frontmatter, body = writer.split_frontmatter(idea_file.read_text())
+        assert frontmatter["content_sha256"] == content_sha256(body)
+
+        rendered = writer.render_file(frontmatter, "## Content\nedited\n")
+        frontmatter, body = writer.split_frontmatter(rendered)
+        assert frontmatter["content_sha256"] == content_sha256(body)
+
+    def test_unsigned_by_default(self, tmp_path):
+        output = make_writer(tmp_path).format_capture(base_capture(content="hi"))
+        assert "content_sha256" not in output
+
+
+class TestAliases:
+    def aliases(self, tmp_path, mode=None, **fields):
+        writer = SafeMarkdownWriter(str(tmp_path), {"capture": {"aliases": mode}})
+        frontmatter, _ = writer.split_frontmatter(
+            writer.format_capture(base_capture(**fields))
+        )
+        return frontmatter["aliases"]
+
+    def test_capture_id_by_default(self, tmp_path):
+        assert self.aliases(tmp_path) == ["2025-08-16T06:58:42+00:00"]
+
+    def test_none_keeps_only_explicit_aliases(self, tmp_path):
+        assert self.aliases(tmp_path, "none") == []
+        assert self.aliases(tmp_path, "none", aliases=["Standup"]) == ["Standup"]
+
+    def test_title_uses_first_content_line(self, tmp_path):
+        content = "\n# Weekly review\n\nbody"
+        assert self.aliases(tmp_path, "title", content=content) == ["Weekly review"]
+        assert self.aliases(tmp_path, "title", content="") == []
+
+    def test_unknown_mode_is_rejected(self, tmp_path):
+        with pytest.raises(ValidationError):
+            self.aliases(tmp_path, "slug")
+
+
+class TestFilenamePattern:
+    def test_files_are_named_by_capture_id_by_default(self, tmp_path):
+        path = make_writer(tmp_path).write_capture(base_capture(content="Hi"))
+        assert path.name == "2025-08-16T06:58:42+00:00.md"
+
+    def test_pattern_names_file_but_keeps_id(self, tmp_path):
+        writer = make_writer(tmp_path, filename_pattern="{slug}-{date}")
+        first = base_capture(content="# Weekly Review: Q3!\nbody")
+        second = base_capture(content="Weekly review, Q3", capture_id="other-id")
+
+        paths = [writer.write_capture(first), writer.write_capture(second)]
+
+        assert [p.name for p in paths] == [
+            "weekly-review-q3-2025-08-16.md",
+            "weekly-review-q3-2025-08-16_1.md",
+        ]
+        assert second["capture_id"] == "other-id"
+        assert writer.read_capture(paths[1])["capture_id"] == "other-id"
+        assert writer.find_capture_file("other-id") == paths[1]
+
+    def test_unknown_placeholder_is_rejected(self, tmp_path):
+        writer = make_writer(tmp_path, filename_pattern="{title}")
+        with pytest.raises(ValidationError, match="filename_pattern"):
+            writer.write_capture(base_capture())